@@ -0,0 +1,88 @@
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use log::info;
+use sha2::Sha256;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{AesMode, CompressionMethod, ZipWriter};
+
+use crate::report_files::{persist, temp_path};
+
+/// Password characters; look-alikes (0/O, 1/l/I) are left out so that it can be dictated
+const PASSWORD_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz23456789";
+
+const PASSWORD_LENGTH: usize = 16;
+
+/// Pack report files into a ZIP archive, encrypted with AES-256 when a password is given,
+/// and return its path
+pub fn write_zip(files: &[&str], output_path: &str, password: Option<&str>) -> Result<String> {
+    info!(
+        "Packing {} report files into {} archive: {}",
+        files.len(),
+        if password.is_some() { "encrypted" } else { "plain" },
+        output_path
+    );
+    let mut zip = ZipWriter::new(File::create(temp_path(output_path))?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let options = match password {
+        Some(password) => options.with_aes_encryption(AesMode::Aes256, password),
+        None => options,
+    };
+
+    for path in files {
+        let name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.to_string());
+        zip.start_file(name, options)?;
+        zip.write_all(&std::fs::read(path)?)?;
+    }
+
+    zip.finish()?;
+    persist(output_path)
+}
+
+/// Archive password of a chat: HMAC-SHA256 of the chat id keyed by the secret, so the same
+/// chat always gets the same password and it never has to be stored
+pub fn derive_password(secret: &[u8], chat_id: i64) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("archive:{}", chat_id).as_bytes());
+    mac.finalize().into_bytes()[..PASSWORD_LENGTH]
+        .iter()
+        .map(|b| PASSWORD_ALPHABET[*b as usize % PASSWORD_ALPHABET.len()] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_encrypted_zip_round_trip() {
+        let password = derive_password(b"secret", 42);
+        assert_eq!(password.len(), PASSWORD_LENGTH);
+        assert_eq!(password, derive_password(b"secret", 42));
+        assert_ne!(password, derive_password(b"secret", 43));
+
+        let input_path = "test_archive_input.csv";
+        let output_path = "test_archive.zip";
+        std::fs::write(input_path, "Телефон;Имя\n+79991234567;Анна\n").unwrap();
+        write_zip(&[input_path], output_path, Some(&password)).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(output_path).unwrap()).unwrap();
+        assert!(archive.by_name_decrypt(input_path, b"wrong").is_err());
+        let mut content = String::new();
+        archive
+            .by_name_decrypt(input_path, password.as_bytes())
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+
+        std::fs::remove_file(input_path).unwrap();
+        std::fs::remove_file(output_path).unwrap();
+        assert_eq!(content, "Телефон;Имя\n+79991234567;Анна\n");
+    }
+}
@@ -0,0 +1,272 @@
+use chrono::NaiveDate;
+use serde::Serialize;
+
+/// Default bucket set: low below 60%, normal 60-80%, high above 80%
+pub const DEFAULT_AURA_BUCKETS: &str =
+    "<60|Низкая аура|🔴|#EF5350;<=80|Нормальная аура|🟡|#FFA726;*|Высокая аура|🟢|#26A69A";
+
+/// Upper boundary of an aura bucket
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BucketBound {
+    /// Percentages strictly below the value
+    Below(f64),
+    /// Percentages up to and including the value
+    UpTo(f64),
+    /// Everything above the previous bucket; only for the last bucket
+    Rest,
+}
+
+/// A range of aura percentages with its presentation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuraBucket {
+    pub bound: BucketBound,
+    pub label: String,
+    pub emoji: String,
+    /// Chart color as RGB
+    pub color: [u8; 3],
+}
+
+/// Ordered aura buckets covering all percentages
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct AuraBuckets {
+    buckets: Vec<AuraBucket>,
+}
+
+impl Default for AuraBuckets {
+    fn default() -> Self {
+        Self::parse(DEFAULT_AURA_BUCKETS).expect("default aura buckets are valid")
+    }
+}
+
+impl AuraBuckets {
+    /// Parse a bucket set: `;`-separated buckets of `bound|label|emoji|#RRGGBB`, where the
+    /// bound is `<N` or `<=N` and the last bucket's bound is `*`. Boundaries must increase.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut buckets = Vec::new();
+        for entry in spec.split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let parts: Vec<&str> = entry.split('|').map(str::trim).collect();
+            let [bound, label, emoji, color] = parts.as_slice() else {
+                return Err(format!("expected bound|label|emoji|#RRGGBB, got \"{}\"", entry));
+            };
+            let bound = if *bound == "*" {
+                BucketBound::Rest
+            } else if let Some(value) = bound.strip_prefix("<=") {
+                BucketBound::UpTo(parse_percent(value)?)
+            } else if let Some(value) = bound.strip_prefix('<') {
+                BucketBound::Below(parse_percent(value)?)
+            } else {
+                return Err(format!("bound must be <N, <=N or *, got \"{}\"", bound));
+            };
+            if label.is_empty() {
+                return Err(format!("empty label in \"{}\"", entry));
+            }
+            buckets.push(AuraBucket {
+                bound,
+                label: label.to_string(),
+                emoji: emoji.to_string(),
+                color: parse_color(color)?,
+            });
+        }
+
+        let Some((last, rest)) = buckets.split_last() else {
+            return Err("no buckets defined".to_string());
+        };
+        if last.bound != BucketBound::Rest || rest.iter().any(|b| b.bound == BucketBound::Rest) {
+            return Err("only the last bucket must have bound *".to_string());
+        }
+        let limits: Vec<f64> = rest.iter().map(|b| b.limit()).collect();
+        if limits.windows(2).any(|pair| pair[1] <= pair[0]) {
+            return Err("bucket boundaries must increase".to_string());
+        }
+
+        Ok(Self { buckets })
+    }
+
+    /// Index of the bucket containing the percentage
+    pub fn index_of(&self, percent: f64) -> usize {
+        self.buckets
+            .iter()
+            .position(|bucket| match bucket.bound {
+                BucketBound::Below(limit) => percent < limit,
+                BucketBound::UpTo(limit) => percent <= limit,
+                BucketBound::Rest => true,
+            })
+            .unwrap_or(self.buckets.len() - 1)
+    }
+
+    /// Bucket containing the percentage
+    pub fn bucket_of(&self, percent: f64) -> &AuraBucket {
+        &self.buckets[self.index_of(percent)]
+    }
+
+    pub fn count(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AuraBucket> {
+        self.buckets.iter()
+    }
+
+    /// Human-readable range of the bucket at `index`, e.g. "<60%", "60-80%" or ">80%"
+    pub fn range_label(&self, index: usize) -> String {
+        let bucket = &self.buckets[index];
+        let previous = index.checked_sub(1).map(|i| &self.buckets[i]);
+        match (previous, bucket.bound) {
+            (None, BucketBound::Below(limit)) => format!("<{}%", format_percent(limit)),
+            (None, BucketBound::UpTo(limit)) => format!("≤{}%", format_percent(limit)),
+            (None, BucketBound::Rest) => "0-100%".to_string(),
+            (Some(previous), BucketBound::Rest) => match previous.bound {
+                BucketBound::Below(limit) => format!("≥{}%", format_percent(limit)),
+                _ => format!(">{}%", format_percent(previous.limit())),
+            },
+            (Some(previous), _) => {
+                format!("{}-{}%", format_percent(previous.limit()), format_percent(bucket.limit()))
+            }
+        }
+    }
+}
+
+impl AuraBucket {
+    fn limit(&self) -> f64 {
+        match self.bound {
+            BucketBound::Below(limit) | BucketBound::UpTo(limit) => limit,
+            BucketBound::Rest => f64::INFINITY,
+        }
+    }
+}
+
+fn parse_percent(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|_| format!("invalid boundary \"{}\"", value))
+}
+
+/// Parse "#RRGGBB"
+fn parse_color(value: &str) -> Result<[u8; 3], String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(format!("invalid color \"{}\", expected #RRGGBB", value)),
+    }
+}
+
+/// Boundary without a trailing ".0"
+fn format_percent(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Mean, median and spread of aura percentages
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuraSummary {
+    /// Records with a parsable aura percentage
+    pub count: usize,
+    pub mean: f64,
+    pub median: f64,
+    /// Population standard deviation
+    pub std_dev: f64,
+}
+
+/// Aura summary of a single day (Moscow time)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DailyAura {
+    pub date: NaiveDate,
+    pub summary: AuraSummary,
+}
+
+/// Collects aura percentages for a summary
+#[derive(Debug, Clone, Default)]
+pub struct AuraCollector {
+    samples: Vec<f64>,
+}
+
+impl AuraCollector {
+    pub fn add(&mut self, percent: f64) {
+        self.samples.push(percent);
+    }
+
+    /// Compute the summary; `None` when no percentages were collected
+    pub fn finish(mut self) -> Option<AuraSummary> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        self.samples.sort_by(|a, b| a.total_cmp(b));
+        let samples = &self.samples;
+        let count = samples.len();
+
+        let mean = samples.iter().sum::<f64>() / count as f64;
+        let median = median(samples);
+        let variance = samples.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count as f64;
+
+        Some(AuraSummary {
+            count,
+            mean,
+            median,
+            std_dev: variance.sqrt(),
+        })
+    }
+}
+
+/// Median of sorted, non-empty values; the mean of the two middle values for an even count
+pub fn median(sorted: &[f64]) -> f64 {
+    let count = sorted.len();
+    if count % 2 == 0 {
+        (sorted[count / 2 - 1] + sorted[count / 2]) / 2.0
+    } else {
+        sorted[count / 2]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut collector = AuraCollector::default();
+        for percent in [70.0, 90.0, 50.0, 70.0] {
+            collector.add(percent);
+        }
+
+        let summary = collector.finish().unwrap();
+        assert_eq!(summary.count, 4);
+        assert_eq!(summary.mean, 70.0);
+        assert_eq!(summary.median, 70.0);
+        assert!((summary.std_dev - 200.0_f64.sqrt()).abs() < 1e-9);
+
+        assert!(AuraCollector::default().finish().is_none());
+    }
+
+    #[test]
+    fn test_default_buckets_match_fixed_thresholds() {
+        let buckets = AuraBuckets::default();
+        let indexes: Vec<usize> = [0.0, 59.9, 60.0, 80.0, 80.1, 100.0]
+            .iter()
+            .map(|percent| buckets.index_of(*percent))
+            .collect();
+        assert_eq!(indexes, vec![0, 0, 1, 1, 2, 2]);
+        assert_eq!(buckets.bucket_of(90.0).color, [0x26, 0xA6, 0x9A]);
+        let ranges: Vec<String> = (0..buckets.count()).map(|i| buckets.range_label(i)).collect();
+        assert_eq!(ranges, vec!["<60%", "60-80%", ">80%"]);
+    }
+
+    #[test]
+    fn test_parse_custom_buckets() {
+        let buckets = AuraBuckets::parse("<40|A|🔵|#0000FF; <70|B|🟣|#800080; <90|C||#FFFFFF; *|D|⭐|#FFD700").unwrap();
+        assert_eq!(buckets.count(), 4);
+        assert_eq!(buckets.index_of(70.0), 2);
+        assert_eq!(buckets.range_label(3), "≥90%");
+
+        assert!(AuraBuckets::parse("<60|A|x|#000000").is_err());
+        assert!(AuraBuckets::parse("<80|A|x|#000000;<60|B|x|#000000;*|C|x|#000000").is_err());
+        assert!(AuraBuckets::parse("<60|A|x|red;*|B|x|#000000").is_err());
+    }
+}
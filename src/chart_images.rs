@@ -0,0 +1,286 @@
+use anyhow::{anyhow, Result};
+use chrono::Duration;
+use log::info;
+use plotters::coord::ranged1d::SegmentValue;
+use plotters::prelude::*;
+use teloxide::prelude::*;
+use teloxide::types::{InputMedia, InputMediaPhoto};
+
+use crate::pdf_generator::ChartData;
+use crate::report_files::{input_file, persist, temp_path};
+use crate::stats::ReportStats;
+
+/// Font with Cyrillic glyphs, resolved through fontconfig (`fonts-dejavu-core` on Debian)
+const FONT: &str = "DejaVu Sans";
+
+/// Image size in pixels; 16:9 fits a phone screen in landscape and a chat bubble in portrait
+const WIDTH: u32 = 1280;
+const HEIGHT: u32 = 720;
+
+/// Clubs shown on the ranking chart
+const CLUB_CHART_LIMIT: usize = 15;
+
+/// Bar color, same teal as in the PDF
+const ACCENT: RGBColor = RGBColor(0x26, 0xA6, 0x9A);
+
+/// A rendered chart ready to be sent as a photo
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChartImage {
+    pub path: String,
+    pub caption: String,
+}
+
+/// Render the key charts of a report to PNG files named `{path_prefix}_{chart}.png`.
+/// Charts without data (e.g. the daily trend of a one-day report or the ranking of a single
+/// club) are skipped.
+pub fn render_chart_images(chart_data: &ChartData, stats: &ReportStats, path_prefix: &str) -> Result<Vec<ChartImage>> {
+    let mut images = Vec::new();
+    if stats.total_records == 0 {
+        return Ok(images);
+    }
+
+    let path = format!("{}_hourly.png", path_prefix);
+    hourly_chart(&temp_path(&path), &chart_data.hourly_totals())?;
+    images.push(ChartImage { path: persist(&path)?, caption: "Генерации по часам".to_string() });
+
+    if stats.daily_generations.len() > 1 {
+        let path = format!("{}_daily.png", path_prefix);
+        daily_chart(&temp_path(&path), stats)?;
+        images.push(ChartImage { path: persist(&path)?, caption: "Генерации по дням".to_string() });
+    }
+
+    if stats.aura_levels.iter().any(|count| *count > 0) {
+        let path = format!("{}_aura.png", path_prefix);
+        aura_chart(&temp_path(&path), stats)?;
+        images.push(ChartImage { path: persist(&path)?, caption: "Уровни ауры".to_string() });
+    }
+
+    if stats.club_stats.len() > 1 {
+        let path = format!("{}_clubs.png", path_prefix);
+        clubs_chart(&temp_path(&path), stats)?;
+        images.push(ChartImage { path: persist(&path)?, caption: "Рейтинг комплексов".to_string() });
+    }
+
+    info!("Rendered {} chart images", images.len());
+    Ok(images)
+}
+
+/// Send rendered charts to a chat: one media group, or a single photo when there is only one
+/// chart (a media group needs at least two items)
+pub async fn send_chart_images(bot: &Bot, chat_id: ChatId, images: &[ChartImage]) -> ResponseResult<()> {
+    match images {
+        [] => {}
+        [image] => {
+            bot.send_photo(chat_id, input_file(&image.path)?)
+                .caption(image.caption.clone())
+                .await?;
+        }
+        images => {
+            let mut media = Vec::with_capacity(images.len());
+            for image in images {
+                media.push(InputMedia::Photo(InputMediaPhoto::new(input_file(&image.path)?).caption(image.caption.clone())));
+            }
+            bot.send_media_group(chat_id, media).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Upper bound of a count axis with some headroom above the tallest bar
+fn axis_max(max: u32) -> u32 {
+    max + max / 10 + 1
+}
+
+fn hourly_chart(path: &str, totals: &[u32; 24]) -> Result<()> {
+    let root = BitMapBackend::new(path, (WIDTH, HEIGHT)).into_drawing_area();
+    root.fill(&WHITE).map_err(|e| anyhow!("{}", e))?;
+    let max = totals.iter().copied().max().unwrap_or(0);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Генерации по часам", (FONT, 36))
+        .margin(20)
+        .x_label_area_size(50)
+        .y_label_area_size(70)
+        .build_cartesian_2d((0u32..23u32).into_segmented(), 0u32..axis_max(max))
+        .map_err(|e| anyhow!("{}", e))?;
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(24)
+        .x_desc("Час")
+        .y_desc("Генераций")
+        .label_style((FONT, 18))
+        .axis_desc_style((FONT, 20))
+        .draw()
+        .map_err(|e| anyhow!("{}", e))?;
+    chart
+        .draw_series(
+            Histogram::vertical(&chart)
+                .style(ACCENT.filled())
+                .margin(4)
+                .data(totals.iter().enumerate().map(|(hour, count)| (hour as u32, *count))),
+        )
+        .map_err(|e| anyhow!("{}", e))?;
+    root.present().map_err(|e| anyhow!("{}", e))?;
+    Ok(())
+}
+
+fn daily_chart(path: &str, stats: &ReportStats) -> Result<()> {
+    let root = BitMapBackend::new(path, (WIDTH, HEIGHT)).into_drawing_area();
+    root.fill(&WHITE).map_err(|e| anyhow!("{}", e))?;
+    let days = &stats.daily_generations;
+    let (first, last) = (days[0].0, days[days.len() - 1].0);
+    let max = days.iter().map(|(_, count)| *count as u32).max().unwrap_or(0);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Генерации по дням", (FONT, 36))
+        .margin(20)
+        .x_label_area_size(50)
+        .y_label_area_size(70)
+        .build_cartesian_2d(first..last + Duration::days(1), 0u32..axis_max(max))
+        .map_err(|e| anyhow!("{}", e))?;
+    chart
+        .configure_mesh()
+        .x_labels(10)
+        .x_label_formatter(&|date| date.format("%d.%m").to_string())
+        .y_desc("Генераций")
+        .label_style((FONT, 18))
+        .axis_desc_style((FONT, 20))
+        .draw()
+        .map_err(|e| anyhow!("{}", e))?;
+    let points = days.iter().map(|(date, count)| (*date, *count as u32));
+    chart
+        .draw_series(AreaSeries::new(points.clone(), 0, ACCENT.mix(0.2)).border_style(ACCENT.stroke_width(3)))
+        .map_err(|e| anyhow!("{}", e))?;
+    chart
+        .draw_series(points.map(|point| Circle::new(point, 4, ACCENT.filled())))
+        .map_err(|e| anyhow!("{}", e))?;
+    root.present().map_err(|e| anyhow!("{}", e))?;
+    Ok(())
+}
+
+fn aura_chart(path: &str, stats: &ReportStats) -> Result<()> {
+    let root = BitMapBackend::new(path, (WIDTH, HEIGHT)).into_drawing_area();
+    root.fill(&WHITE).map_err(|e| anyhow!("{}", e))?;
+    let buckets: Vec<_> = stats.aura_buckets.iter().collect();
+    let total: usize = stats.aura_levels.iter().sum();
+    let max = stats.aura_levels.iter().copied().max().unwrap_or(0) as u32;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Уровни ауры", (FONT, 36))
+        .margin(20)
+        .x_label_area_size(50)
+        .y_label_area_size(70)
+        .build_cartesian_2d((0..buckets.len() - 1).into_segmented(), 0u32..axis_max(max))
+        .map_err(|e| anyhow!("{}", e))?;
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(buckets.len())
+        .x_label_formatter(&|value| match value {
+            SegmentValue::CenterOf(index) | SegmentValue::Exact(index) => buckets
+                .get(*index)
+                .map(|bucket| {
+                    let count = stats.aura_levels.get(*index).copied().unwrap_or(0);
+                    format!("{} ({:.0}%)", bucket.label, count as f64 * 100.0 / total.max(1) as f64)
+                })
+                .unwrap_or_default(),
+            SegmentValue::Last => String::new(),
+        })
+        .y_desc("Генераций")
+        .label_style((FONT, 20))
+        .axis_desc_style((FONT, 20))
+        .draw()
+        .map_err(|e| anyhow!("{}", e))?;
+    chart
+        .draw_series(stats.aura_levels.iter().enumerate().map(|(index, count)| {
+            let [r, g, b] = buckets[index].color;
+            let mut bar = Rectangle::new(
+                [(SegmentValue::Exact(index), 0), (SegmentValue::Exact(index + 1), *count as u32)],
+                RGBColor(r, g, b).filled(),
+            );
+            bar.set_margin(0, 0, 40, 40);
+            bar
+        }))
+        .map_err(|e| anyhow!("{}", e))?;
+    root.present().map_err(|e| anyhow!("{}", e))?;
+    Ok(())
+}
+
+/// Top clubs by generations, rank 1 at the top
+fn clubs_chart(path: &str, stats: &ReportStats) -> Result<()> {
+    let root = BitMapBackend::new(path, (WIDTH, HEIGHT)).into_drawing_area();
+    root.fill(&WHITE).map_err(|e| anyhow!("{}", e))?;
+    let clubs: Vec<_> = stats.club_stats.iter().take(CLUB_CHART_LIMIT).collect();
+    let max = clubs.iter().map(|club| club.total_generations as u32).max().unwrap_or(0);
+    // Bar `i` from the bottom is the club at position `len - 1 - i`
+    let club_at = |index: usize| clubs.get(clubs.len() - 1 - index.min(clubs.len() - 1));
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(format!("Топ-{} комплексов по генерациям", clubs.len()), (FONT, 36))
+        .margin(20)
+        .x_label_area_size(50)
+        .y_label_area_size(300)
+        .build_cartesian_2d(0u32..axis_max(max), (0..clubs.len() - 1).into_segmented())
+        .map_err(|e| anyhow!("{}", e))?;
+    chart
+        .configure_mesh()
+        .disable_y_mesh()
+        .y_labels(clubs.len())
+        .y_label_formatter(&|value| match value {
+            SegmentValue::CenterOf(index) | SegmentValue::Exact(index) => club_at(*index)
+                .map(|club| format!("{}. {}", club.rank, club.club_name))
+                .unwrap_or_default(),
+            SegmentValue::Last => String::new(),
+        })
+        .x_desc("Генераций")
+        .label_style((FONT, 18))
+        .axis_desc_style((FONT, 20))
+        .draw()
+        .map_err(|e| anyhow!("{}", e))?;
+    chart
+        .draw_series(
+            Histogram::horizontal(&chart)
+                .style(ACCENT.filled())
+                .margin(6)
+                .data((0..clubs.len()).filter_map(|index| {
+                    club_at(index).map(|club| (index, club.total_generations as u32))
+                })),
+        )
+        .map_err(|e| anyhow!("{}", e))?;
+    root.present().map_err(|e| anyhow!("{}", e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::club_directory::test_clubs;
+    use crate::stats::{StatsCollector, StatsSettings};
+    use serde_json::json;
+
+    #[test]
+    fn test_render_chart_images() {
+        let clubs = test_clubs(&[("c1", "Москва Сити"), ("c2", "Казань Арена")]);
+        let settings = StatsSettings::default();
+        let mut stats = StatsCollector::new(&clubs, &settings);
+        let mut chart_data = ChartData::new("CreatedAt1");
+        for (club_id, day, aura) in [("c1", "01", "55%"), ("c1", "01", "75%"), ("c2", "02", "90%")] {
+            let record = json!({
+                "Id": 1, "club_id": club_id, "phone": "89990000001", "status": "done",
+                "text_aura": {"percent": aura}, "CreatedAt1": format!("2024-01-{} 10:00:00+00:00", day)
+            });
+            stats.add_record(&record);
+            chart_data.add_record(&record);
+        }
+
+        let images = render_chart_images(&chart_data, &stats.finish(), "test_charts").unwrap();
+        let captions: Vec<&str> = images.iter().map(|image| image.caption.as_str()).collect();
+        assert_eq!(captions, vec!["Генерации по часам", "Генерации по дням", "Уровни ауры", "Рейтинг комплексов"]);
+        for image in &images {
+            let png = std::fs::read(&image.path).unwrap();
+            std::fs::remove_file(&image.path).unwrap();
+            assert!(png.starts_with(b"\x89PNG"));
+        }
+    }
+}
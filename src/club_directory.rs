@@ -0,0 +1,187 @@
+use anyhow::Result;
+use log::{error, info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::nocodb::NocoDBClient;
+
+/// Club metadata from the clubs table
+#[derive(Debug, Clone)]
+pub struct Club {
+    pub club_id: String,
+    pub name: String,
+    pub city: Option<String>,
+    pub address: Option<String>,
+    pub active: bool,
+    pub timezone: Option<String>,
+}
+
+/// Clubs keyed by `club_id`
+pub type ClubMap = HashMap<String, Club>;
+
+impl Club {
+    /// Build a club from a clubs table row; rows without `club_id` or `name` are skipped
+    pub fn from_record(record: &Value) -> Option<Self> {
+        let obj = record.as_object()?;
+        let text = |field: &str| {
+            obj.get(field)
+                .and_then(|v| v.as_str())
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+
+        // Missing flag means the club is active
+        let active = match obj.get("active").or_else(|| obj.get("is_active")) {
+            Some(Value::Bool(b)) => *b,
+            Some(Value::Number(n)) => n.as_i64() != Some(0),
+            Some(Value::String(s)) => !matches!(s.trim().to_lowercase().as_str(), "false" | "0" | "no" | "нет"),
+            _ => true,
+        };
+
+        Some(Club {
+            club_id: text("club_id")?,
+            name: text("name")?,
+            city: text("city"),
+            address: text("address"),
+            active,
+            timezone: text("timezone"),
+        })
+    }
+}
+
+struct CachedClubs {
+    clubs: Arc<ClubMap>,
+    loaded_at: Instant,
+}
+
+/// Cached club directory.
+///
+/// Fresh entries are served from memory. Once the TTL expires the stale copy is
+/// still returned immediately while a background refresh runs, so reports keep
+/// working when NocoDB is unreachable.
+pub struct ClubDirectory {
+    client: NocoDBClient,
+    ttl: Duration,
+    cache: RwLock<Option<CachedClubs>>,
+    refresh_lock: Mutex<()>,
+}
+
+impl ClubDirectory {
+    pub fn new(client: NocoDBClient, ttl: Duration) -> Self {
+        Self {
+            client,
+            ttl,
+            cache: RwLock::new(None),
+            refresh_lock: Mutex::new(()),
+        }
+    }
+
+    /// Get the club directory, loading it on first use
+    pub async fn get(self: &Arc<Self>) -> Result<Arc<ClubMap>> {
+        let cached = self
+            .cache
+            .read()
+            .await
+            .as_ref()
+            .map(|c| (c.clubs.clone(), c.loaded_at.elapsed() < self.ttl));
+
+        match cached {
+            Some((clubs, true)) => Ok(clubs),
+            Some((clubs, false)) => {
+                // Serve stale data and revalidate in background
+                let directory = self.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = directory.revalidate().await {
+                        warn!("Failed to refresh club directory, keeping cached copy: {}", e);
+                    }
+                });
+                Ok(clubs)
+            }
+            None => self.refresh().await,
+        }
+    }
+
+    /// Reload the directory from NocoDB, replacing the cached copy
+    pub async fn refresh(&self) -> Result<Arc<ClubMap>> {
+        let _guard = self.refresh_lock.lock().await;
+        self.load().await
+    }
+
+    /// Refresh unless another refresh is already running
+    async fn revalidate(&self) -> Result<()> {
+        let Ok(_guard) = self.refresh_lock.try_lock() else {
+            return Ok(());
+        };
+        self.load().await.map(|_| ())
+    }
+
+    async fn load(&self) -> Result<Arc<ClubMap>> {
+        let records = self.client.fetch_club_records().await.map_err(|e| {
+            error!("Failed to load club directory: {}", e);
+            e
+        })?;
+
+        let clubs: ClubMap = records
+            .iter()
+            .filter_map(Club::from_record)
+            .map(|club| (club.club_id.clone(), club))
+            .collect();
+        let clubs = Arc::new(clubs);
+
+        info!(
+            "Club directory loaded: {} clubs ({} active)",
+            clubs.len(),
+            clubs.values().filter(|c| c.active).count()
+        );
+
+        *self.cache.write().await = Some(CachedClubs {
+            clubs: clubs.clone(),
+            loaded_at: Instant::now(),
+        });
+
+        Ok(clubs)
+    }
+}
+
+/// Active clubs built from `(club_id, name)` pairs, for tests that only need a directory
+#[cfg(test)]
+pub(crate) fn test_clubs(clubs: &[(&str, &str)]) -> ClubMap {
+    clubs
+        .iter()
+        .map(|(id, name)| {
+            let club = Club::from_record(&serde_json::json!({"club_id": id, "name": name})).unwrap();
+            (id.to_string(), club)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_club_from_record() {
+        let club = Club::from_record(&json!({
+            "club_id": "c1",
+            "name": " Club 1 ",
+            "city": "Москва",
+            "address": "",
+            "active": "false",
+            "timezone": "Europe/Moscow"
+        }))
+        .unwrap();
+
+        assert_eq!(club.name, "Club 1");
+        assert_eq!(club.city.as_deref(), Some("Москва"));
+        assert_eq!(club.address, None);
+        assert!(!club.active);
+        assert_eq!(club.timezone.as_deref(), Some("Europe/Moscow"));
+
+        assert!(Club::from_record(&json!({"club_id": "c2"})).is_none());
+        assert!(Club::from_record(&json!({"club_id": "c3", "name": "Club 3"})).unwrap().active);
+    }
+}
@@ -2,6 +2,13 @@ use anyhow::Result;
 use std::env;
 use thiserror::Error;
 
+use crate::archive::derive_password;
+use crate::aura::{AuraBuckets, DEFAULT_AURA_BUCKETS};
+use crate::export_profile::ExportProfiles;
+use crate::phone::PhoneNormalizer;
+use crate::privacy::{Anonymizer, PrivacyMode};
+use crate::subscription::{parse_subscriptions, Subscription};
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub telegram_token: String,
@@ -10,14 +17,53 @@ pub struct Config {
     pub nocodb_table_id: String,
     pub nocodb_clubs_table_id: String,
     pub allowed_user_ids: Vec<i64>,
+    pub admin_user_ids: Vec<i64>, // Users allowed to run admin commands
     pub report_schedule_time: String, // Format: "HH:MM"
     pub date_field_name: String, // Field name for date filtering (e.g., "CreatedAt", "CreatedAt1")
+    pub nocodb_page_size: usize, // Records per NocoDB list request
+    pub nocodb_max_concurrent_requests: usize, // Max parallel page requests
+    pub updated_field_name: String, // Field name with last modification time (e.g., "UpdatedAt")
+    pub mirror_db_path: Option<String>, // Local mirror database; disabled when not set
+    pub mirror_sync_interval_secs: u64,
+    pub mirror_reconcile_interval_secs: u64,
+    pub clubs_cache_ttl_secs: u64, // How long the club directory is considered fresh
+    pub unknown_clubs_mode: UnknownClubsMode,
+    pub dq_max_generation_secs: i64, // Longer done generations are reported by the data quality audit
+    pub dq_duplicate_window_minutes: i64, // Same-phone records closer than this are reported as duplicates
+    pub stuck_process_minutes: i64, // "process" generations older than this are reported as stuck
+    pub failure_alert_percent: f64, // Failed status share that triggers a warning
+    pub stuck_alert_percent: f64, // Stuck generation share that triggers a warning
+    pub generation_start_field: String, // Generation time = end field - start field
+    pub generation_end_field: String,
+    pub generation_time_cap_secs: f64, // Longer generation times are excluded as outliers
+    pub phone_normalizer: PhoneNormalizer, // Built from PHONE_DEFAULT_COUNTRY
+    pub leaderboard_size: usize, // Top/bottom clubs shown in the Telegram summary
+    pub aura_buckets: AuraBuckets, // Built from AURA_BUCKETS
+    pub csv_profiles: ExportProfiles, // Loaded from CSV_PROFILES_PATH; always has "default"
+    pub subscriptions: Vec<Subscription>, // Loaded from SUBSCRIPTIONS_PATH, or one per allowed user
+    pub privacy_mode: PrivacyMode, // Record exports for allowed users
+    pub admin_privacy_mode: PrivacyMode, // Record exports for admins
+    pub privacy_hmac_key: String, // Key of the hashed privacy mode
+    pub encrypt_full_pii: bool, // Deliver full-PII reports as encrypted ZIP archives
+    pub archive_password_secret: String, // Per-chat archive passwords are derived from it
+    pub archive_password_ttl_secs: u64, // /archivepassword replies are deleted after this time
+}
+
+/// What to do with records whose club is missing or not in the clubs table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownClubsMode {
+    /// Count them under a separate "Без комплекса" group
+    Include,
+    /// Leave them out of summary, CSV and PDF
+    Exclude,
 }
 
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("Missing environment variable: {0}")]
     MissingEnvVar(String),
+    #[error("Invalid value for {0}: {1}")]
+    InvalidValue(String, String),
 }
 
 impl Config {
@@ -32,12 +78,171 @@ impl Config {
             .filter_map(|s| s.trim().parse::<i64>().ok())
             .collect();
 
+        let admin_user_ids: Vec<i64> = env::var("ADMIN_USER_IDS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|s| s.trim().parse::<i64>().ok())
+            .collect();
+
         let report_schedule_time = env::var("REPORT_SCHEDULE_TIME")
             .unwrap_or_else(|_| "09:00".to_string());
         
         let date_field_name = env::var("DATE_FIELD_NAME")
             .unwrap_or_else(|_| "CreatedAt1".to_string());
 
+        let nocodb_page_size = env::var("NOCODB_PAGE_SIZE")
+            .ok()
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .unwrap_or(100);
+
+        let nocodb_max_concurrent_requests = env::var("NOCODB_MAX_CONCURRENT_REQUESTS")
+            .ok()
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .unwrap_or(4);
+
+        let updated_field_name = env::var("UPDATED_FIELD_NAME")
+            .unwrap_or_else(|_| "UpdatedAt".to_string());
+
+        let mirror_db_path = env::var("MIRROR_DB_PATH")
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty());
+
+        let mirror_sync_interval_secs = env::var("MIRROR_SYNC_INTERVAL_SECS")
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(300);
+
+        let mirror_reconcile_interval_secs = env::var("MIRROR_RECONCILE_INTERVAL_SECS")
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(6 * 60 * 60);
+
+        let clubs_cache_ttl_secs = env::var("CLUBS_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(3600);
+
+        let unknown_clubs_mode = match env::var("UNKNOWN_CLUBS_MODE")
+            .unwrap_or_else(|_| "exclude".to_string())
+            .trim()
+            .to_lowercase()
+            .as_str()
+        {
+            "include" => UnknownClubsMode::Include,
+            "exclude" => UnknownClubsMode::Exclude,
+            other => {
+                return Err(ConfigError::InvalidValue("UNKNOWN_CLUBS_MODE".to_string(), other.to_string()).into())
+            }
+        };
+
+        let dq_max_generation_secs = env::var("DQ_MAX_GENERATION_SECS")
+            .ok()
+            .and_then(|s| s.trim().parse::<i64>().ok())
+            .unwrap_or(3600);
+
+        let dq_duplicate_window_minutes = env::var("DQ_DUPLICATE_WINDOW_MINUTES")
+            .ok()
+            .and_then(|s| s.trim().parse::<i64>().ok())
+            .unwrap_or(5);
+
+        let stuck_process_minutes = env::var("STUCK_PROCESS_MINUTES")
+            .ok()
+            .and_then(|s| s.trim().parse::<i64>().ok())
+            .unwrap_or(30);
+
+        let failure_alert_percent = env::var("FAILURE_ALERT_PERCENT")
+            .ok()
+            .and_then(|s| s.trim().parse::<f64>().ok())
+            .unwrap_or(5.0);
+
+        let stuck_alert_percent = env::var("STUCK_ALERT_PERCENT")
+            .ok()
+            .and_then(|s| s.trim().parse::<f64>().ok())
+            .unwrap_or(5.0);
+
+        let generation_time_fields = env::var("GENERATION_TIME_FIELDS")
+            .unwrap_or_else(|_| "CreatedAt,UpdatedAt".to_string());
+        let (generation_start_field, generation_end_field) = match generation_time_fields
+            .split(',')
+            .map(|s| s.trim())
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [start, end] if !start.is_empty() && !end.is_empty() => (start.to_string(), end.to_string()),
+            _ => {
+                return Err(ConfigError::InvalidValue("GENERATION_TIME_FIELDS".to_string(), generation_time_fields).into())
+            }
+        };
+
+        let generation_time_cap_secs = env::var("GENERATION_TIME_CAP_SECS")
+            .ok()
+            .and_then(|s| s.trim().parse::<f64>().ok())
+            .unwrap_or(1800.0);
+
+        let phone_default_country = env::var("PHONE_DEFAULT_COUNTRY")
+            .unwrap_or_else(|_| "RU".to_string());
+        let phone_normalizer = PhoneNormalizer::for_country(&phone_default_country).ok_or_else(|| {
+            ConfigError::InvalidValue(
+                "PHONE_DEFAULT_COUNTRY".to_string(),
+                format!(
+                    "{} (supported: {})",
+                    phone_default_country,
+                    PhoneNormalizer::supported_countries().join(", ")
+                ),
+            )
+        })?;
+
+        let leaderboard_size = env::var("LEADERBOARD_SIZE")
+            .ok()
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .unwrap_or(3);
+
+        let aura_buckets_spec = env::var("AURA_BUCKETS")
+            .unwrap_or_else(|_| DEFAULT_AURA_BUCKETS.to_string());
+        let aura_buckets = AuraBuckets::parse(&aura_buckets_spec).map_err(|e| {
+            ConfigError::InvalidValue("AURA_BUCKETS".to_string(), format!("{} ({})", aura_buckets_spec, e))
+        })?;
+
+        let csv_profiles = match read_optional_file("CSV_PROFILES_PATH")? {
+            Some((path, json)) => ExportProfiles::parse(&json).map_err(|e| {
+                ConfigError::InvalidValue("CSV_PROFILES_PATH".to_string(), format!("{} ({})", path, e))
+            })?,
+            None => ExportProfiles::default(),
+        };
+
+        let subscriptions = match read_optional_file("SUBSCRIPTIONS_PATH")? {
+            Some((path, json)) => parse_subscriptions(&json, &csv_profiles).map_err(|e| {
+                ConfigError::InvalidValue("SUBSCRIPTIONS_PATH".to_string(), format!("{} ({})", path, e))
+            })?,
+            None => allowed_user_ids.iter().map(|id| Subscription::for_chat(*id)).collect(),
+        };
+
+        let privacy_mode = parse_privacy_mode("PRIVACY_MODE")?;
+        let admin_privacy_mode = parse_privacy_mode("ADMIN_PRIVACY_MODE")?;
+        let privacy_hmac_key = env::var("PRIVACY_HMAC_KEY").unwrap_or_default();
+        let uses_hashing = [privacy_mode, admin_privacy_mode]
+            .into_iter()
+            .chain(subscriptions.iter().filter_map(|subscription| subscription.privacy))
+            .any(|mode| mode == PrivacyMode::Hashed);
+        if uses_hashing && privacy_hmac_key.trim().is_empty() {
+            return Err(ConfigError::MissingEnvVar("PRIVACY_HMAC_KEY".to_string()).into());
+        }
+
+        let encrypt_full_pii = env::var("ENCRYPT_FULL_PII")
+            .ok()
+            .map(|s| matches!(s.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+            .unwrap_or(false);
+        let archive_password_secret = env::var("ARCHIVE_PASSWORD_SECRET").unwrap_or_default();
+        let archive_password_ttl_secs = env::var("ARCHIVE_PASSWORD_TTL_SECS")
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(60);
+        let uses_encryption = encrypt_full_pii || subscriptions.iter().any(|subscription| subscription.encrypted == Some(true));
+        if uses_encryption && archive_password_secret.trim().is_empty() {
+            return Err(ConfigError::MissingEnvVar("ARCHIVE_PASSWORD_SECRET".to_string()).into());
+        }
+
         Ok(Config {
             telegram_token: env::var("TELEGRAM_BOT_TOKEN")
                 .map_err(|_| ConfigError::MissingEnvVar("TELEGRAM_BOT_TOKEN".to_string()))?,
@@ -50,8 +255,95 @@ impl Config {
             nocodb_clubs_table_id: env::var("NOCODB_CLUBS_TABLE_ID")
                 .map_err(|_| ConfigError::MissingEnvVar("NOCODB_CLUBS_TABLE_ID".to_string()))?,
             allowed_user_ids,
+            admin_user_ids,
             report_schedule_time,
             date_field_name,
+            nocodb_page_size,
+            nocodb_max_concurrent_requests,
+            updated_field_name,
+            mirror_db_path,
+            mirror_sync_interval_secs,
+            mirror_reconcile_interval_secs,
+            clubs_cache_ttl_secs,
+            unknown_clubs_mode,
+            dq_max_generation_secs,
+            dq_duplicate_window_minutes,
+            stuck_process_minutes,
+            failure_alert_percent,
+            stuck_alert_percent,
+            generation_start_field,
+            generation_end_field,
+            generation_time_cap_secs,
+            phone_normalizer,
+            leaderboard_size,
+            aura_buckets,
+            csv_profiles,
+            subscriptions,
+            privacy_mode,
+            admin_privacy_mode,
+            privacy_hmac_key,
+            encrypt_full_pii,
+            archive_password_secret,
+            archive_password_ttl_secs,
         })
     }
+
+    /// Check whether the user may run admin commands
+    pub fn is_admin(&self, user_id: i64) -> bool {
+        self.admin_user_ids.contains(&user_id)
+    }
+
+    /// Privacy mode of the chat's role
+    pub fn privacy_for(&self, chat_id: i64) -> PrivacyMode {
+        if self.is_admin(chat_id) {
+            self.admin_privacy_mode
+        } else {
+            self.privacy_mode
+        }
+    }
+
+    /// Privacy mode of a subscription: its own setting or the chat's role mode
+    pub fn subscription_privacy(&self, subscription: &Subscription) -> PrivacyMode {
+        subscription.privacy.unwrap_or_else(|| self.privacy_for(subscription.chat_id))
+    }
+
+    pub fn anonymizer(&self, mode: PrivacyMode) -> Anonymizer {
+        Anonymizer::new(mode, self.privacy_hmac_key.as_bytes())
+    }
+
+    /// Archive password for the chat if its reports in this privacy mode are delivered encrypted
+    pub fn archive_password(&self, chat_id: i64, mode: PrivacyMode) -> Option<String> {
+        (self.encrypt_full_pii && mode == PrivacyMode::Full).then(|| self.chat_archive_password(chat_id))
+    }
+
+    /// Archive password for the subscription if its reports are delivered encrypted
+    pub fn subscription_archive_password(&self, subscription: &Subscription) -> Option<String> {
+        match subscription.encrypted {
+            Some(true) => Some(self.chat_archive_password(subscription.chat_id)),
+            Some(false) => None,
+            None => self.archive_password(subscription.chat_id, self.subscription_privacy(subscription)),
+        }
+    }
+
+    /// Password of the chat's archives, handed over by an admin out of band
+    pub fn chat_archive_password(&self, chat_id: i64) -> String {
+        derive_password(self.archive_password_secret.as_bytes(), chat_id)
+    }
+}
+
+/// Privacy mode from an environment variable; `full` when not set
+fn parse_privacy_mode(var: &str) -> Result<PrivacyMode, ConfigError> {
+    let value = env::var(var).unwrap_or_else(|_| "full".to_string());
+    PrivacyMode::from_name(&value).ok_or_else(|| ConfigError::InvalidValue(var.to_string(), value))
+}
+
+/// Read the file named by an environment variable; `None` when the variable is not set
+fn read_optional_file(var: &str) -> Result<Option<(String, String)>, ConfigError> {
+    let Some(path) = env::var(var).ok().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()) else {
+        return Ok(None);
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(Some((path, content))),
+        Err(e) => Err(ConfigError::InvalidValue(var.to_string(), format!("{} ({})", path, e))),
+    }
 }
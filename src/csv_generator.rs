@@ -2,21 +2,264 @@ use anyhow::Result;
 use csv::Writer;
 use serde_json::Value;
 use std::fs::File;
-use std::collections::HashMap;
-use chrono::{Local, DateTime};
 use chrono_tz::Europe::Moscow;
 use log::info;
 
+use crate::club_directory::ClubMap;
+use crate::data_quality::record_id;
+use crate::date_utils::parse_record_datetime;
+use crate::export_profile::{CsvColumn, ExportProfile};
+use crate::phone::PhoneNormalizer;
+use crate::privacy::Anonymizer;
+use crate::report_files::{persist, temp_path};
+use crate::aura::AuraBuckets;
+use crate::stats::{extract_percent_value, ReportStats, StatsSettings, NETWORK_TOTAL_NAME, UNASSIGNED_CLUB_NAME};
+
 pub struct CsvGenerator;
 
+/// Incremental writer of the records CSV in the layout of an export profile, fed one record at a time
+pub struct CsvReportWriter {
+    writer: Writer<File>,
+    output_path: String,
+    profile: ExportProfile,
+    /// Profile columns left after the privacy mode
+    columns: Vec<CsvColumn>,
+    anonymizer: Anonymizer,
+    phones: PhoneNormalizer,
+    aura_buckets: AuraBuckets,
+    generation_start_field: String,
+    generation_end_field: String,
+    rows: usize,
+}
+
+impl CsvReportWriter {
+    /// Create the output file and write the BOM (if the profile asks for it) and header row.
+    /// Personal data columns are masked, hashed or left out according to the anonymizer.
+    pub fn create(
+        output_path: &str,
+        profile: &ExportProfile,
+        settings: &StatsSettings,
+        anonymizer: &Anonymizer,
+    ) -> Result<Self> {
+        info!(
+            "Generating CSV report to: {} (profile: {}, privacy: {})",
+            output_path,
+            profile.name,
+            anonymizer.mode().name()
+        );
+        let mut writer = CsvGenerator::create_writer(output_path, profile.delimiter, profile.bom)?;
+
+        let (columns, headers): (Vec<CsvColumn>, Vec<&str>) = profile
+            .columns
+            .iter()
+            .filter(|(column, _)| !anonymizer.drops(*column))
+            .map(|(column, header)| (*column, header.as_str()))
+            .unzip();
+        writer.write_record(&headers)?;
+
+        Ok(Self {
+            writer,
+            output_path: output_path.to_string(),
+            profile: profile.clone(),
+            columns,
+            anonymizer: anonymizer.clone(),
+            phones: settings.phones.clone(),
+            aura_buckets: settings.aura_buckets.clone(),
+            generation_start_field: settings.generation_start_field.clone(),
+            generation_end_field: settings.generation_end_field.clone(),
+            rows: 0,
+        })
+    }
+
+    /// Append a single record to the report
+    pub fn write_record(&mut self, record: &Value, clubs: &ClubMap) -> Result<()> {
+        if let Some(obj) = record.as_object() {
+            let row = self.build_row(obj, clubs);
+            self.writer.write_record(&row)?;
+            self.rows += 1;
+        }
+        Ok(())
+    }
+
+    /// Flush the file, move it into place and return its path
+    pub fn finish(mut self) -> Result<String> {
+        self.writer.flush()?;
+        drop(self.writer);
+        if self.rows == 0 {
+            info!("No data to write to CSV");
+        } else {
+            info!("CSV report generated successfully with {} records", self.rows);
+        }
+        persist(&self.output_path)
+    }
+
+    /// Build a report row with the profile's columns
+    fn build_row(&self, obj: &serde_json::Map<String, Value>, clubs: &ClubMap) -> Vec<String> {
+        let text = |field: &str| obj.get(field).and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let timestamp = |field: &str| {
+            obj.get(field)
+                .and_then(|v| v.as_str())
+                .map(|value| CsvGenerator::convert_to_moscow_time(value, &self.profile.date_format))
+                .unwrap_or_default()
+        };
+        let club = obj
+            .get("club_id")
+            .and_then(|v| v.as_str())
+            .and_then(|club_id| clubs.get(club_id));
+
+        self.columns
+            .iter()
+            .map(|column| match column {
+                CsvColumn::Id => record_id(obj),
+                // E.164 (unparsable values are kept as is), masked or hashed
+                CsvColumn::Phone => obj
+                    .get("phone")
+                    .and_then(|v| self.anonymizer.phone(&self.phones, v))
+                    .unwrap_or_default(),
+                CsvColumn::Name => text("name"),
+                CsvColumn::DateVisit => timestamp("date_visit"),
+                CsvColumn::Duration => obj
+                    .get("duration")
+                    .map(|v| match v {
+                        Value::Number(n) => self.profile.format_decimal(&n.to_string()),
+                        Value::String(s) => s.clone(),
+                        _ => String::new(),
+                    })
+                    .unwrap_or_default(),
+                CsvColumn::Club => club
+                    .map(|club| club.name.clone())
+                    // Only reached when orphaned records are included in the report
+                    .unwrap_or_else(|| UNASSIGNED_CLUB_NAME.to_string()),
+                CsvColumn::City => club.and_then(|club| club.city.clone()).unwrap_or_default(),
+                CsvColumn::Aura => self.profile.format_decimal(&CsvGenerator::extract_aura_percent(obj)),
+                CsvColumn::AuraLevel => extract_percent_value(obj)
+                    .map(|percent| self.aura_buckets.bucket_of(percent).label.clone())
+                    .unwrap_or_default(),
+                CsvColumn::BirthDate => text("birth_date"),
+                CsvColumn::Sex => text("sex"),
+                CsvColumn::Status => text("status"),
+                CsvColumn::StartedAt => timestamp(&self.generation_start_field),
+                CsvColumn::FinishedAt => timestamp(&self.generation_end_field),
+                CsvColumn::GenerationSecs => {
+                    let parse = |field: &str| obj.get(field).and_then(|v| v.as_str()).and_then(parse_record_datetime);
+                    match (parse(&self.generation_start_field), parse(&self.generation_end_field)) {
+                        (Some(start), Some(end)) => {
+                            let secs = end.signed_duration_since(start).num_milliseconds() as f64 / 1000.0;
+                            self.profile.format_decimal(&format!("{:.1}", secs))
+                        }
+                        _ => String::new(),
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
 impl CsvGenerator {
-    /// Convert UTC datetime string to Moscow timezone
-    fn convert_to_moscow_time(utc_str: &str) -> String {
+    /// Create a CSV file that opens correctly in Excel: UTF-8 BOM and `;` delimiter
+    fn create_excel_writer(output_path: &str) -> Result<Writer<File>> {
+        Self::create_writer(output_path, b';', true)
+    }
+
+    /// Create a CSV file with the given delimiter, optionally starting with a UTF-8 BOM. The file
+    /// is written to the temporary path of `output_path` until it is persisted.
+    fn create_writer(output_path: &str, delimiter: u8, bom: bool) -> Result<Writer<File>> {
+        let mut file = File::create(temp_path(output_path))?;
+
+        // UTF-8 BOM for correct encoding detection on Windows/Android
+        if bom {
+            use std::io::Write;
+            file.write_all(&[0xEF, 0xBB, 0xBF])?;
+        }
+
+        Ok(csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .from_writer(file))
+    }
+
+    /// Generate the per-club KPI table, one row per club in rank order followed by the network total
+    pub fn generate_club_stats(stats: &ReportStats, output_path: &str) -> Result<String> {
+        info!("Generating clubs CSV report to: {}", output_path);
+        let mut writer = Self::create_excel_writer(output_path)?;
+
+        let mut headers: Vec<String> = [
+            "Место", "Изменение места", "Комплекс", "Город", "Генераций", "Доля, %", "Клиентов",
+            "Генераций на клиента", "Done, %", "Средняя аура, %", "Медиана ауры, %",
+            "Стандартное отклонение ауры",
+        ]
+        .iter()
+        .map(|header| header.to_string())
+        .collect();
+        headers.extend(stats.aura_buckets.iter().map(|bucket| bucket.label.clone()));
+        headers.extend(["Среднее время генерации, сек".to_string(), "Медиана времени генерации, сек".to_string()]);
+        writer.write_record(&headers)?;
+
+        for club in &stats.club_stats {
+            let mut row = vec![
+                club.rank.to_string(),
+                club.rank_change.map(|change| change.to_string()).unwrap_or_default(),
+                club.club_name.clone(),
+                club.city.clone().unwrap_or_default(),
+                club.total_generations.to_string(),
+                format!("{:.1}", club.percentage),
+                club.unique_clients.to_string(),
+                format!("{:.2}", club.generations_per_client),
+                format!("{:.1}", club.done_rate),
+                club.aura.as_ref().map(|aura| format!("{:.1}", aura.mean)).unwrap_or_default(),
+                club.aura.as_ref().map(|aura| format!("{:.1}", aura.median)).unwrap_or_default(),
+                club.aura.as_ref().map(|aura| format!("{:.1}", aura.std_dev)).unwrap_or_default(),
+            ];
+            row.extend(club.aura_levels.iter().map(|count| count.to_string()));
+            row.extend([
+                club.generation_time.as_ref().map(|t| format!("{:.1}", t.mean)).unwrap_or_default(),
+                club.generation_time.as_ref().map(|t| format!("{:.1}", t.median)).unwrap_or_default(),
+            ]);
+            writer.write_record(&row)?;
+        }
+
+        // Network total
+        let mut total = vec![
+            String::new(),
+            String::new(),
+            NETWORK_TOTAL_NAME.to_string(),
+            String::new(),
+            stats.total_records.to_string(),
+            "100.0".to_string(),
+            stats.unique_clients.to_string(),
+            if stats.unique_clients > 0 {
+                format!("{:.2}", stats.total_records as f64 / stats.unique_clients as f64)
+            } else {
+                format!("{:.2}", 0.0)
+            },
+            if stats.total_records > 0 {
+                format!("{:.1}", stats.done_count as f64 / stats.total_records as f64 * 100.0)
+            } else {
+                format!("{:.1}", 0.0)
+            },
+            stats.aura.as_ref().map(|aura| format!("{:.1}", aura.mean)).unwrap_or_default(),
+            stats.aura.as_ref().map(|aura| format!("{:.1}", aura.median)).unwrap_or_default(),
+            stats.aura.as_ref().map(|aura| format!("{:.1}", aura.std_dev)).unwrap_or_default(),
+        ];
+        total.extend(stats.aura_levels.iter().map(|count| count.to_string()));
+        total.extend([
+            stats.generation_time.as_ref().map(|t| format!("{:.1}", t.mean)).unwrap_or_default(),
+            stats.generation_time.as_ref().map(|t| format!("{:.1}", t.median)).unwrap_or_default(),
+        ]);
+        writer.write_record(&total)?;
+
+        writer.flush()?;
+        drop(writer);
+        info!("Clubs CSV report generated with {} clubs", stats.club_stats.len());
+        persist(output_path)
+    }
+
+    /// Convert UTC datetime string to Moscow timezone in the given format
+    fn convert_to_moscow_time(utc_str: &str, format: &str) -> String {
         // Try to parse the UTC datetime string
-        if let Ok(dt) = DateTime::parse_from_str(utc_str, "%Y-%m-%d %H:%M:%S%z") {
+        if let Some(dt) = parse_record_datetime(utc_str) {
             // Convert to Moscow timezone
             let moscow_time = dt.with_timezone(&Moscow);
-            return moscow_time.format("%Y-%m-%d %H:%M:%S").to_string();
+            return moscow_time.format(format).to_string();
         }
         
         // If parsing fails, return original string
@@ -75,143 +318,12 @@ impl CsvGenerator {
         
         String::new()
     }
-
-    /// Generate CSV report with specific fields for AuroScope
-    pub fn generate(data: &[Value], output_path: &str, club_names: &HashMap<String, String>) -> Result<String> {
-        info!("Generating CSV report to: {}", output_path);
-        
-        let mut file = File::create(output_path)?;
-        
-        // Write UTF-8 BOM for correct encoding detection on Windows/Android
-        use std::io::Write;
-        file.write_all(&[0xEF, 0xBB, 0xBF])?;
-        
-        // Use semicolon as delimiter for Windows Excel compatibility
-        let mut writer = csv::WriterBuilder::new()
-            .delimiter(b';')
-            .from_writer(file);
-
-        // Define headers for AuroScope report in Russian
-        let headers = vec!["Телефон", "Имя", "Дата визита", "Продолжительность", "Комплекс", "Аура", "Дата рождения", "Пол"];
-        writer.write_record(&headers)?;
-
-        if data.is_empty() {
-            info!("No data to write to CSV");
-            writer.flush()?;
-            return Ok(output_path.to_string());
-        }
-
-        // Write data rows with only specified fields
-        for record in data {
-            if let Some(obj) = record.as_object() {
-                let row: Vec<String> = vec![
-                    // phone (can be number or string)
-                    obj.get("phone")
-                        .map(|v| match v {
-                            Value::Number(n) => n.to_string(),
-                            Value::String(s) => s.clone(),
-                            _ => String::new(),
-                        })
-                        .unwrap_or_default(),
-                    // name
-                    obj.get("name")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string(),
-                    // date_visit (convert from UTC to Moscow time)
-                    obj.get("date_visit")
-                        .and_then(|v| v.as_str())
-                        .map(|s| Self::convert_to_moscow_time(s))
-                        .unwrap_or_default(),
-                    // duration
-                    obj.get("duration")
-                        .map(|v| match v {
-                            Value::Number(n) => n.to_string(),
-                            Value::String(s) => s.clone(),
-                            _ => String::new(),
-                        })
-                        .unwrap_or_default(),
-                    // club_name (lookup club_id in club_names map)
-                    obj.get("club_id")
-                        .and_then(|v| v.as_str())
-                        .and_then(|club_id| club_names.get(club_id))
-                        .cloned()
-                        .unwrap_or_else(|| {
-                            // If not found, return the original club_id
-                            obj.get("club_id")
-                                .and_then(|v| v.as_str())
-                                .unwrap_or("")
-                                .to_string()
-                        }),
-                    // aura (extract percent from aura or text_aura)
-                    Self::extract_aura_percent(obj),
-                    // birth_date
-                    obj.get("birth_date")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string(),
-                    // sex
-                    obj.get("sex")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string(),
-                ];
-                writer.write_record(&row)?;
-            }
-        }
-
-        writer.flush()?;
-        info!("CSV report generated successfully with {} records", data.len());
-        Ok(output_path.to_string())
-    }
-
-    /// Generate CSV with specific fields
-    /// This method will be customized based on your requirements
-    pub fn generate_custom(data: &[Value], fields: &[&str], output_path: &str) -> Result<String> {
-        info!("Generating custom CSV report with {} fields", fields.len());
-        
-        let file = File::create(output_path)?;
-        let mut writer = Writer::from_writer(file);
-
-        // Write headers
-        writer.write_record(fields)?;
-
-        // Write data rows with only specified fields
-        for record in data {
-            if let Some(obj) = record.as_object() {
-                let row: Vec<String> = fields
-                    .iter()
-                    .map(|&field| {
-                        obj.get(field)
-                            .and_then(|v| match v {
-                                Value::String(s) => Some(s.clone()),
-                                Value::Number(n) => Some(n.to_string()),
-                                Value::Bool(b) => Some(b.to_string()),
-                                Value::Null => Some(String::new()),
-                                _ => Some(v.to_string()),
-                            })
-                            .unwrap_or_default()
-                    })
-                    .collect();
-                writer.write_record(&row)?;
-            }
-        }
-
-        writer.flush()?;
-        info!("Custom CSV report generated successfully");
-        Ok(output_path.to_string())
-    }
-
-    /// Generate filename with timestamp
-    pub fn generate_filename(prefix: &str) -> String {
-        let timestamp = Local::now().format("%Y%m%d_%H%M%S");
-        format!("{}_{}.csv", prefix, timestamp)
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
     use serde_json::json;
 
     #[test]
@@ -221,7 +333,61 @@ mod tests {
             json!({"id": 2, "name": "Test2", "value": 200}),
         ];
 
-        let result = CsvGenerator::generate(&data, "test_output.csv");
-        assert!(result.is_ok());
+        let mut writer = CsvReportWriter::create(
+            "test_output.csv",
+            &ExportProfile::default(),
+            &StatsSettings::default(),
+            &Anonymizer::full(),
+        )
+        .unwrap();
+        for record in &data {
+            writer.write_record(record, &HashMap::new()).unwrap();
+        }
+        assert!(writer.finish().is_ok());
+        let _ = std::fs::remove_file("test_output.csv");
+    }
+
+    #[test]
+    fn test_export_profile_layout() {
+        let profiles = crate::export_profile::ExportProfiles::parse(
+            r#"{"ops": {"columns": ["id", {"field": "status", "header": "State"}, "started_at", "generation_secs", "aura"],
+                "delimiter": ",", "bom": false, "date_format": "%d.%m.%Y %H:%M", "decimal_separator": ","}}"#,
+        )
+        .unwrap();
+        let output_path = "test_output_profile.csv";
+        let mut writer =
+            CsvReportWriter::create(output_path, profiles.get("ops").unwrap(), &StatsSettings::default(), &Anonymizer::full())
+                .unwrap();
+        let record = json!({
+            "Id": 7, "status": "done", "CreatedAt": "2024-01-01 10:00:00+00:00",
+            "UpdatedAt": "2024-01-01 10:00:42+00:00", "text_aura": {"percent": "75.5%"}
+        });
+        writer.write_record(&record, &HashMap::new()).unwrap();
+        writer.finish().unwrap();
+
+        let content = std::fs::read_to_string(output_path).unwrap();
+        std::fs::remove_file(output_path).unwrap();
+        assert_eq!(
+            content,
+            "Id,State,Начало генерации,\"Время генерации, сек\",Аура\n7,done,01.01.2024 13:00,\"42,0\",\"75,5\"\n"
+        );
+    }
+
+    #[test]
+    fn test_masked_privacy_mode() {
+        let output_path = "test_output_masked.csv";
+        let anonymizer = Anonymizer::new(crate::privacy::PrivacyMode::Masked, &[]);
+        let mut writer =
+            CsvReportWriter::create(output_path, &ExportProfile::default(), &StatsSettings::default(), &anonymizer)
+                .unwrap();
+        let record = json!({"phone": "89991234567", "name": "Анна", "birth_date": "17.05.1990", "sex": "female"});
+        writer.write_record(&record, &HashMap::new()).unwrap();
+        writer.finish().unwrap();
+
+        let content = std::fs::read_to_string(output_path).unwrap();
+        std::fs::remove_file(output_path).unwrap();
+        let lines: Vec<&str> = content.trim_start_matches('\u{feff}').lines().collect();
+        assert_eq!(lines[0], "Телефон;Дата визита;Продолжительность;Комплекс;Аура;Уровень ауры;Пол");
+        assert_eq!(lines[1], "+7 999 ***-**-67;;;Без комплекса;;;female");
     }
 }
@@ -0,0 +1,348 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::date_utils::parse_record_datetime;
+use crate::phone::PhoneNormalizer;
+use crate::stats::extract_percent_value;
+
+/// Number of sample record ids kept per check
+const SAMPLE_LIMIT: usize = 5;
+
+/// Dates this far ahead of the current time are not reported as future dates (clock skew)
+const FUTURE_DATE_TOLERANCE_MINUTES: i64 = 5;
+
+/// Known generation statuses
+const KNOWN_STATUSES: &[&str] = &["done", "process"];
+
+/// A single data quality check run against every record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityCheck {
+    MissingPhone,
+    MalformedPhone,
+    InvalidAura,
+    AuraOutOfRange,
+    UnexpectedStatus,
+    NegativeDuration,
+    AbsurdDuration,
+    FutureDate,
+    DuplicatePhone,
+}
+
+impl QualityCheck {
+    /// All checks in display order
+    pub const ALL: [QualityCheck; 9] = [
+        QualityCheck::MissingPhone,
+        QualityCheck::MalformedPhone,
+        QualityCheck::InvalidAura,
+        QualityCheck::AuraOutOfRange,
+        QualityCheck::UnexpectedStatus,
+        QualityCheck::NegativeDuration,
+        QualityCheck::AbsurdDuration,
+        QualityCheck::FutureDate,
+        QualityCheck::DuplicatePhone,
+    ];
+
+    /// Human-readable check name
+    pub fn title(&self) -> &'static str {
+        match self {
+            QualityCheck::MissingPhone => "Нет телефона",
+            QualityCheck::MalformedPhone => "Телефон не распознан",
+            QualityCheck::InvalidAura => "Не удалось разобрать ауру",
+            QualityCheck::AuraOutOfRange => "Аура вне диапазона 0-100",
+            QualityCheck::UnexpectedStatus => "Неизвестный статус",
+            QualityCheck::NegativeDuration => "Окончание генерации раньше начала",
+            QualityCheck::AbsurdDuration => "Слишком долгая генерация",
+            QualityCheck::FutureDate => "Дата в будущем",
+            QualityCheck::DuplicatePhone => "Повтор телефона за несколько минут",
+        }
+    }
+}
+
+/// Result of a check that found problems
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QualityFinding {
+    pub check: QualityCheck,
+    pub count: usize,
+    /// First few offending record ids
+    pub sample_ids: Vec<String>,
+}
+
+/// Thresholds for the data quality checks
+#[derive(Debug, Clone)]
+pub struct DataQualitySettings {
+    /// Field with the record date: checked for future dates and used to find duplicate visits
+    pub date_field_name: String,
+    /// Field with the last modification time, also checked for future dates
+    pub updated_field_name: String,
+    /// Timestamp fields whose difference is the generation duration
+    pub generation_start_field: String,
+    pub generation_end_field: String,
+    /// Generations of done records longer than this are reported
+    pub max_generation_secs: i64,
+    /// Records for the same phone closer than this are reported as duplicates
+    pub duplicate_window_minutes: i64,
+    /// Phones that cannot be normalized are reported as malformed
+    pub phones: PhoneNormalizer,
+}
+
+impl Default for DataQualitySettings {
+    fn default() -> Self {
+        Self {
+            date_field_name: "CreatedAt1".to_string(),
+            updated_field_name: "UpdatedAt".to_string(),
+            generation_start_field: "CreatedAt".to_string(),
+            generation_end_field: "UpdatedAt".to_string(),
+            max_generation_secs: 3600,
+            duplicate_window_minutes: 5,
+            phones: PhoneNormalizer::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CheckCounter {
+    count: usize,
+    sample_ids: Vec<String>,
+}
+
+/// Runs data quality checks one record at a time
+pub struct DataQualityAudit {
+    settings: DataQualitySettings,
+    now: DateTime<Utc>,
+    counters: HashMap<QualityCheck, CheckCounter>,
+    // Visits by phone for duplicate detection, evaluated in finish()
+    phone_visits: HashMap<String, Vec<(DateTime<Utc>, String)>>,
+}
+
+impl DataQualityAudit {
+    pub fn new(settings: DataQualitySettings) -> Self {
+        Self {
+            settings,
+            now: Utc::now(),
+            counters: HashMap::new(),
+            phone_visits: HashMap::new(),
+        }
+    }
+
+    /// Check a single record
+    pub fn add_record(&mut self, record: &Map<String, Value>) {
+        let id = record_id(record);
+
+        // Phone
+        match record.get("phone") {
+            None | Some(Value::Null) => self.report(QualityCheck::MissingPhone, &id),
+            Some(Value::String(s)) if s.trim().is_empty() => self.report(QualityCheck::MissingPhone, &id),
+            Some(phone) => match self.settings.phones.normalize(phone) {
+                Some(normalized) => {
+                    if let Some(date) = timestamp(record, &self.settings.date_field_name) {
+                        self.phone_visits.entry(normalized).or_default().push((date, id.clone()));
+                    }
+                }
+                None => self.report(QualityCheck::MalformedPhone, &id),
+            },
+        }
+
+        // Aura
+        if has_aura(record) {
+            match extract_percent_value(record) {
+                Some(percent) if !(0.0..=100.0).contains(&percent) => {
+                    self.report(QualityCheck::AuraOutOfRange, &id)
+                }
+                Some(_) => {}
+                None => self.report(QualityCheck::InvalidAura, &id),
+            }
+        }
+
+        // Status
+        let status = record_status(record);
+        if !KNOWN_STATUSES.contains(&status) {
+            self.report(QualityCheck::UnexpectedStatus, &id);
+        }
+
+        // Generation duration
+        if let (Some(start), Some(end)) = (
+            timestamp(record, &self.settings.generation_start_field),
+            timestamp(record, &self.settings.generation_end_field),
+        ) {
+            let duration = end.signed_duration_since(start);
+            if duration < Duration::zero() {
+                self.report(QualityCheck::NegativeDuration, &id);
+            } else if status == "done" && duration.num_seconds() > self.settings.max_generation_secs {
+                self.report(QualityCheck::AbsurdDuration, &id);
+            }
+        }
+
+        // Future dates
+        let latest = self.now + Duration::minutes(FUTURE_DATE_TOLERANCE_MINUTES);
+        if [&self.settings.date_field_name, &self.settings.updated_field_name]
+            .into_iter()
+            .filter_map(|field| timestamp(record, field))
+            .any(|date| date > latest)
+        {
+            self.report(QualityCheck::FutureDate, &id);
+        }
+    }
+
+    /// Finalize the checks; only checks with problems are returned, in display order
+    pub fn finish(mut self) -> Vec<QualityFinding> {
+        let window = Duration::minutes(self.settings.duplicate_window_minutes);
+        for mut visits in std::mem::take(&mut self.phone_visits).into_values() {
+            visits.sort();
+            for pair in visits.windows(2) {
+                if pair[1].0 - pair[0].0 <= window {
+                    self.report(QualityCheck::DuplicatePhone, &pair[1].1);
+                }
+            }
+        }
+
+        QualityCheck::ALL
+            .iter()
+            .filter_map(|check| {
+                let mut counter = self.counters.remove(check)?;
+                counter.sample_ids.sort_by(|a, b| natural_id_order(a, b));
+                Some(QualityFinding {
+                    check: *check,
+                    count: counter.count,
+                    sample_ids: counter.sample_ids,
+                })
+            })
+            .collect()
+    }
+
+    fn report(&mut self, check: QualityCheck, id: &str) {
+        let counter = self.counters.entry(check).or_default();
+        counter.count += 1;
+        if counter.sample_ids.len() < SAMPLE_LIMIT {
+            counter.sample_ids.push(id.to_string());
+        }
+    }
+}
+
+/// Record id as text ("?" when missing)
+pub fn record_id(record: &Map<String, Value>) -> String {
+    match record.get("Id") {
+        Some(Value::Number(n)) => n.to_string(),
+        Some(Value::String(s)) => s.clone(),
+        _ => "?".to_string(),
+    }
+}
+
+/// Generation status with surrounding whitespace removed (empty when missing). Case is kept,
+/// so "Done" stays a distinct status in the breakdown and is flagged as unexpected
+pub fn record_status(record: &Map<String, Value>) -> &str {
+    record.get("status").and_then(|v| v.as_str()).unwrap_or("").trim()
+}
+
+/// Whether the record has any aura value to parse
+fn has_aura(record: &Map<String, Value>) -> bool {
+    ["text_aura", "aura"].iter().any(|field| match record.get(*field) {
+        None | Some(Value::Null) => false,
+        Some(Value::String(s)) => !s.trim().is_empty(),
+        Some(_) => true,
+    })
+}
+
+/// Timestamp in the given field, if it parses
+fn timestamp(record: &Map<String, Value>, field: &str) -> Option<DateTime<Utc>> {
+    record.get(field).and_then(|v| v.as_str()).and_then(parse_record_datetime)
+}
+
+/// Order numeric ids numerically and everything else as text
+fn natural_id_order(a: &str, b: &str) -> std::cmp::Ordering {
+    match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn audit(records: &[Value]) -> Vec<QualityFinding> {
+        let mut audit = DataQualityAudit::new(DataQualitySettings::default());
+        for record in records {
+            audit.add_record(record.as_object().unwrap());
+        }
+        audit.finish()
+    }
+
+    fn finding(findings: &[QualityFinding], check: QualityCheck) -> Option<&QualityFinding> {
+        findings.iter().find(|f| f.check == check)
+    }
+
+    #[test]
+    fn test_clean_record_has_no_findings() {
+        let findings = audit(&[json!({
+            "Id": 1,
+            "phone": "+7 (999) 000-00-01",
+            "status": "done",
+            "text_aura": "{\"percent\": \"75%\"}",
+            "CreatedAt": "2024-01-01 10:00:00",
+            "UpdatedAt": "2024-01-01 10:01:00",
+            "CreatedAt1": "2024-01-01 10:00:00+00:00"
+        })]);
+        assert!(findings.is_empty(), "{:?}", findings);
+    }
+
+    #[test]
+    fn test_detects_problems() {
+        let findings = audit(&[
+            json!({"Id": 1, "status": "done", "text_aura": "{broken", "CreatedAt": "2024-01-01 10:00:00"}),
+            json!({"Id": 2, "phone": "abc", "status": "failed", "text_aura": {"percent": 140}}),
+            json!({"Id": 3, "phone": "79990000001", "status": "done", "CreatedAt1": "2024-01-01 10:00:00",
+                   "CreatedAt": "2024-01-01 10:00:00", "UpdatedAt": "2024-01-01 09:00:00"}),
+            json!({"Id": 4, "phone": 79990000001u64, "status": "done", "CreatedAt1": "2024-01-01 10:03:00",
+                   "CreatedAt": "2024-01-01 10:03:00", "UpdatedAt": "2024-01-01 13:00:00"}),
+            json!({"Id": 5, "phone": "79990000002", "status": "process", "CreatedAt1": "2999-01-01 00:00:00"}),
+        ]);
+
+        assert_eq!(finding(&findings, QualityCheck::MissingPhone).unwrap().sample_ids, vec!["1"]);
+        assert_eq!(finding(&findings, QualityCheck::MalformedPhone).unwrap().sample_ids, vec!["2"]);
+        assert_eq!(finding(&findings, QualityCheck::InvalidAura).unwrap().sample_ids, vec!["1"]);
+        assert_eq!(finding(&findings, QualityCheck::AuraOutOfRange).unwrap().sample_ids, vec!["2"]);
+        assert_eq!(finding(&findings, QualityCheck::UnexpectedStatus).unwrap().count, 1);
+        assert_eq!(finding(&findings, QualityCheck::NegativeDuration).unwrap().sample_ids, vec!["3"]);
+        assert_eq!(finding(&findings, QualityCheck::AbsurdDuration).unwrap().sample_ids, vec!["4"]);
+        assert_eq!(finding(&findings, QualityCheck::FutureDate).unwrap().sample_ids, vec!["5"]);
+        assert_eq!(finding(&findings, QualityCheck::DuplicatePhone).unwrap().sample_ids, vec!["4"]);
+    }
+
+    #[test]
+    fn test_checks_use_configured_fields() {
+        let mut audit = DataQualityAudit::new(DataQualitySettings {
+            date_field_name: "VisitAt".to_string(),
+            updated_field_name: "ChangedAt".to_string(),
+            generation_start_field: "StartedAt".to_string(),
+            generation_end_field: "FinishedAt".to_string(),
+            ..DataQualitySettings::default()
+        });
+        audit.add_record(json!({"Id": 1, "phone": "79990000001", "status": "done",
+            "VisitAt": "2024-01-01 10:00:00", "ChangedAt": "2999-01-01 00:00:00",
+            "StartedAt": "2024-01-01 10:00:00", "FinishedAt": "2024-01-01 09:00:00",
+            "CreatedAt": "2024-01-01 10:00:00", "UpdatedAt": "2024-01-01 10:01:00"}).as_object().unwrap());
+        audit.add_record(json!({"Id": 2, "phone": "79990000001", "status": "done",
+            "VisitAt": "2024-01-01 10:02:00", "CreatedAt": "2024-01-01 10:00:00",
+            "UpdatedAt": "2024-01-01 09:00:00"}).as_object().unwrap());
+
+        let findings = audit.finish();
+        assert_eq!(finding(&findings, QualityCheck::NegativeDuration).unwrap().sample_ids, vec!["1"]);
+        assert_eq!(finding(&findings, QualityCheck::FutureDate).unwrap().sample_ids, vec!["1"]);
+        assert_eq!(finding(&findings, QualityCheck::DuplicatePhone).unwrap().sample_ids, vec!["2"]);
+    }
+
+    #[test]
+    fn test_padded_status_is_trimmed() {
+        let mut audit = DataQualityAudit::new(DataQualitySettings::default());
+        audit.add_record(json!({"Id": 1, "status": " done\n", "text_aura": {"percent": 70},
+            "CreatedAt": "2024-01-01 10:00:00", "UpdatedAt": "2024-01-01 12:00:00"}).as_object().unwrap());
+
+        let findings = audit.finish();
+        assert!(finding(&findings, QualityCheck::UnexpectedStatus).is_none());
+        assert_eq!(finding(&findings, QualityCheck::AbsurdDuration).unwrap().sample_ids, vec!["1"]);
+    }
+}
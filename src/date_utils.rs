@@ -1,4 +1,4 @@
-use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use chrono::{DateTime, Duration, TimeZone, Timelike, Utc};
 use chrono_tz::Europe::Moscow;
 
 #[derive(Debug, Clone)]
@@ -8,6 +8,18 @@ pub struct DateRange {
     pub label: String,
 }
 
+impl DateRange {
+    /// Range of the same length ending right before this one
+    pub fn previous(&self) -> DateRange {
+        let end = self.start - Duration::seconds(1);
+        DateRange {
+            start: end - (self.end - self.start),
+            end,
+            label: format!("Предыдущий период ({})", self.label),
+        }
+    }
+}
+
 pub enum Period {
     Today,
     Yesterday,
@@ -19,6 +31,33 @@ pub enum Period {
 }
 
 impl Period {
+    /// Parse a period from its command name (e.g. "week"); empty input is `None`
+    pub fn from_name(name: &str) -> Option<Period> {
+        match name.trim().to_lowercase().as_str() {
+            "today" => Some(Period::Today),
+            "yesterday" => Some(Period::Yesterday),
+            "week" => Some(Period::Week),
+            "month" => Some(Period::Month),
+            "quarter" => Some(Period::Quarter),
+            "halfyear" => Some(Period::HalfYear),
+            "year" => Some(Period::Year),
+            _ => None,
+        }
+    }
+
+    /// Command name of the period, the inverse of [`Period::from_name`]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Period::Today => "today",
+            Period::Yesterday => "yesterday",
+            Period::Week => "week",
+            Period::Month => "month",
+            Period::Quarter => "quarter",
+            Period::HalfYear => "halfyear",
+            Period::Year => "year",
+        }
+    }
+
     pub fn get_date_range(&self) -> DateRange {
         let now_msk = Moscow.from_utc_datetime(&Utc::now().naive_utc());
         
@@ -160,6 +199,17 @@ impl Period {
     }
 }
 
+/// Parse a NocoDB timestamp ("YYYY-MM-DD HH:MM:SS" in UTC or with an offset) into UTC
+pub fn parse_record_datetime(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(dt) = chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S") {
+        return Some(DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc));
+    }
+    if let Ok(dt) = DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%z") {
+        return Some(dt.with_timezone(&Utc));
+    }
+    None
+}
+
 /// Get Moscow time for scheduler
 pub fn get_moscow_time() -> DateTime<chrono_tz::Tz> {
     Moscow.from_utc_datetime(&Utc::now().naive_utc())
@@ -0,0 +1,194 @@
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::date_utils::parse_record_datetime;
+
+/// Age groups as (label, lowest age), ordered by age
+const AGE_GROUPS: &[(&str, u32)] = &[
+    ("до 18", 0),
+    ("18-24", 18),
+    ("25-34", 25),
+    ("35-44", 35),
+    ("45-54", 45),
+    ("55+", 55),
+];
+
+/// Label for records without a usable birth date or sex
+pub const UNKNOWN_GROUP_LABEL: &str = "Не указано";
+
+/// Clients in one demographic group with their aura levels
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DemographicGroup {
+    pub label: String,
+    pub count: usize,
+    /// Share of all records in the report
+    pub percentage: f64,
+    /// Records per aura bucket
+    pub aura_levels: Vec<usize>,
+}
+
+/// Age and gender split of the period's records
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DemographicsStats {
+    /// In age order, followed by the unknown group if present
+    pub age_groups: Vec<DemographicGroup>,
+    /// Male, female, then unknown if present
+    pub genders: Vec<DemographicGroup>,
+}
+
+#[derive(Debug, Clone)]
+struct GroupCounter {
+    count: usize,
+    aura_levels: Vec<usize>,
+}
+
+impl GroupCounter {
+    fn new(aura_bucket_count: usize) -> Self {
+        Self {
+            count: 0,
+            aura_levels: vec![0; aura_bucket_count],
+        }
+    }
+
+    fn add(&mut self, aura: Option<usize>) {
+        self.count += 1;
+        if let Some(level) = aura {
+            self.aura_levels[level] += 1;
+        }
+    }
+}
+
+/// Accumulates demographics one record at a time
+#[derive(Debug)]
+pub struct DemographicsCollector {
+    total: usize,
+    // One counter per AGE_GROUPS entry plus unknown
+    age_groups: Vec<GroupCounter>,
+    // Male, female, unknown
+    genders: Vec<GroupCounter>,
+}
+
+impl DemographicsCollector {
+    pub fn new(aura_bucket_count: usize) -> Self {
+        Self {
+            total: 0,
+            age_groups: vec![GroupCounter::new(aura_bucket_count); AGE_GROUPS.len() + 1],
+            genders: vec![GroupCounter::new(aura_bucket_count); 3],
+        }
+    }
+
+    /// Add a record; `visit_field` holds the visit time used to compute the age and
+    /// `aura` is the index of the record's aura bucket
+    pub fn add_record(&mut self, record: &Map<String, Value>, visit_field: &str, aura: Option<usize>) {
+        self.total += 1;
+
+        let visit = ["date_visit", visit_field]
+            .iter()
+            .find_map(|field| record.get(*field).and_then(|v| v.as_str()).and_then(parse_record_datetime));
+        let age_group = match (record.get("birth_date").and_then(|v| v.as_str()).and_then(parse_birth_date), visit) {
+            (Some(birth_date), Some(visit)) => age_at(birth_date, visit).map(|age| {
+                AGE_GROUPS
+                    .iter()
+                    .rposition(|(_, lowest)| age >= *lowest)
+                    .unwrap_or(0)
+            }),
+            _ => None,
+        };
+        self.age_groups[age_group.unwrap_or(AGE_GROUPS.len())].add(aura);
+
+        let gender = record.get("sex").and_then(|v| v.as_str()).map(gender_index).unwrap_or(2);
+        self.genders[gender].add(aura);
+    }
+
+    pub fn finish(self) -> DemographicsStats {
+        let total = self.total;
+        let group = |label: &str, counter: &GroupCounter| DemographicGroup {
+            label: label.to_string(),
+            count: counter.count,
+            percentage: if total > 0 {
+                counter.count as f64 / total as f64 * 100.0
+            } else {
+                0.0
+            },
+            aura_levels: counter.aura_levels.clone(),
+        };
+
+        let age_labels = AGE_GROUPS.iter().map(|(label, _)| *label).chain([UNKNOWN_GROUP_LABEL]);
+        let age_groups = age_labels
+            .zip(self.age_groups.iter())
+            .enumerate()
+            // Known age groups are always listed so charts keep a stable axis
+            .filter(|(i, (_, counter))| *i < AGE_GROUPS.len() || counter.count > 0)
+            .map(|(_, (label, counter))| group(label, counter))
+            .collect();
+
+        let genders = ["Мужчины", "Женщины", UNKNOWN_GROUP_LABEL]
+            .into_iter()
+            .zip(self.genders.iter())
+            .filter(|(label, counter)| *label != UNKNOWN_GROUP_LABEL || counter.count > 0)
+            .map(|(label, counter)| group(label, counter))
+            .collect();
+
+        DemographicsStats { age_groups, genders }
+    }
+}
+
+/// Parse a birth date in ISO ("1990-05-17", optionally with time) or Russian ("17.05.1990") format
+pub fn parse_birth_date(value: &str) -> Option<NaiveDate> {
+    let value = value.trim();
+    let date_part = value.split([' ', 'T']).next().unwrap_or(value);
+    NaiveDate::parse_from_str(date_part, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date_part, "%d.%m.%Y"))
+        .ok()
+}
+
+/// Full years at the visit; `None` for birth dates after the visit or older than 120 years
+fn age_at(birth_date: NaiveDate, visit: DateTime<Utc>) -> Option<u32> {
+    let visit = visit.date_naive();
+    let mut age = visit.year() - birth_date.year();
+    if (visit.month(), visit.day()) < (birth_date.month(), birth_date.day()) {
+        age -= 1;
+    }
+    u32::try_from(age).ok().filter(|age| *age <= 120)
+}
+
+/// 0 = male, 1 = female, 2 = unknown
+fn gender_index(sex: &str) -> usize {
+    match sex.trim().to_lowercase().as_str() {
+        "m" | "male" | "man" | "м" | "муж" | "мужской" | "мужчина" => 0,
+        "f" | "female" | "woman" | "ж" | "жен" | "женский" | "женщина" => 1,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_age_groups_and_genders() {
+        let mut collector = DemographicsCollector::new(3);
+        let records = [
+            json!({"birth_date": "2000-06-15", "sex": "male", "date_visit": "2024-06-14 10:00:00"}),
+            json!({"birth_date": "15.06.2000", "sex": "Ж", "CreatedAt1": "2024-06-15 10:00:00+03:00"}),
+            json!({"birth_date": "bad", "sex": ""}),
+        ];
+        let auras = [Some(2), Some(0), None];
+        for (record, aura) in records.iter().zip(auras) {
+            collector.add_record(record.as_object().unwrap(), "CreatedAt1", aura);
+        }
+
+        let stats = collector.finish();
+        let group = |label: &str| stats.age_groups.iter().find(|g| g.label == label).unwrap();
+        // Turns 24 on the second visit day
+        assert_eq!(group("18-24").count, 2);
+        assert_eq!(group("18-24").aura_levels, vec![1, 0, 1]);
+        assert_eq!(group(UNKNOWN_GROUP_LABEL).count, 1);
+        assert_eq!(stats.age_groups.len(), AGE_GROUPS.len() + 1);
+
+        let genders: Vec<usize> = stats.genders.iter().map(|g| g.count).collect();
+        assert_eq!(genders, vec![1, 1, 1]);
+    }
+}
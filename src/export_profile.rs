@@ -0,0 +1,303 @@
+use chrono::format::{Item, StrftimeItems};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Profile used when a command or subscription does not name one
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// A column of the records CSV
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvColumn {
+    Id,
+    /// Phone in E.164
+    Phone,
+    Name,
+    /// Visit time in Moscow time
+    DateVisit,
+    Duration,
+    /// Club name from the club directory
+    Club,
+    City,
+    /// Aura percentage
+    Aura,
+    /// Aura bucket label
+    AuraLevel,
+    BirthDate,
+    Sex,
+    Status,
+    /// Generation start timestamp (first of `GENERATION_TIME_FIELDS`) in Moscow time
+    StartedAt,
+    /// Generation end timestamp (second of `GENERATION_TIME_FIELDS`) in Moscow time
+    FinishedAt,
+    /// Generation time in seconds
+    GenerationSecs,
+}
+
+impl CsvColumn {
+    pub const ALL: [CsvColumn; 15] = [
+        CsvColumn::Id,
+        CsvColumn::Phone,
+        CsvColumn::Name,
+        CsvColumn::DateVisit,
+        CsvColumn::Duration,
+        CsvColumn::Club,
+        CsvColumn::City,
+        CsvColumn::Aura,
+        CsvColumn::AuraLevel,
+        CsvColumn::BirthDate,
+        CsvColumn::Sex,
+        CsvColumn::Status,
+        CsvColumn::StartedAt,
+        CsvColumn::FinishedAt,
+        CsvColumn::GenerationSecs,
+    ];
+
+    /// Name of the column in profile definitions
+    pub fn key(self) -> &'static str {
+        match self {
+            CsvColumn::Id => "id",
+            CsvColumn::Phone => "phone",
+            CsvColumn::Name => "name",
+            CsvColumn::DateVisit => "date_visit",
+            CsvColumn::Duration => "duration",
+            CsvColumn::Club => "club",
+            CsvColumn::City => "city",
+            CsvColumn::Aura => "aura",
+            CsvColumn::AuraLevel => "aura_level",
+            CsvColumn::BirthDate => "birth_date",
+            CsvColumn::Sex => "sex",
+            CsvColumn::Status => "status",
+            CsvColumn::StartedAt => "started_at",
+            CsvColumn::FinishedAt => "finished_at",
+            CsvColumn::GenerationSecs => "generation_secs",
+        }
+    }
+
+    /// Header used when the profile does not set one
+    pub fn default_header(self) -> &'static str {
+        match self {
+            CsvColumn::Id => "Id",
+            CsvColumn::Phone => "Телефон",
+            CsvColumn::Name => "Имя",
+            CsvColumn::DateVisit => "Дата визита",
+            CsvColumn::Duration => "Продолжительность",
+            CsvColumn::Club => "Комплекс",
+            CsvColumn::City => "Город",
+            CsvColumn::Aura => "Аура",
+            CsvColumn::AuraLevel => "Уровень ауры",
+            CsvColumn::BirthDate => "Дата рождения",
+            CsvColumn::Sex => "Пол",
+            CsvColumn::Status => "Статус",
+            CsvColumn::StartedAt => "Начало генерации",
+            CsvColumn::FinishedAt => "Окончание генерации",
+            CsvColumn::GenerationSecs => "Время генерации, сек",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|column| column.key() == key)
+    }
+}
+
+/// Layout and formatting of the records CSV
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportProfile {
+    pub name: String,
+    /// Columns in output order with their headers
+    pub columns: Vec<(CsvColumn, String)>,
+    pub delimiter: u8,
+    /// Write a UTF-8 BOM so that Excel detects the encoding
+    pub bom: bool,
+    /// chrono format of timestamps
+    pub date_format: String,
+    pub decimal_separator: char,
+}
+
+impl Default for ExportProfile {
+    /// The standard layout, opened correctly by Excel: `;` delimiter and BOM
+    fn default() -> Self {
+        let columns = [
+            CsvColumn::Phone,
+            CsvColumn::Name,
+            CsvColumn::DateVisit,
+            CsvColumn::Duration,
+            CsvColumn::Club,
+            CsvColumn::Aura,
+            CsvColumn::AuraLevel,
+            CsvColumn::BirthDate,
+            CsvColumn::Sex,
+        ];
+        Self {
+            name: DEFAULT_PROFILE.to_string(),
+            columns: columns
+                .into_iter()
+                .map(|column| (column, column.default_header().to_string()))
+                .collect(),
+            delimiter: b';',
+            bom: true,
+            date_format: "%Y-%m-%d %H:%M:%S".to_string(),
+            decimal_separator: '.',
+        }
+    }
+}
+
+impl ExportProfile {
+    /// Render a number with the profile's decimal separator
+    pub fn format_decimal(&self, value: &str) -> String {
+        if self.decimal_separator == '.' {
+            value.to_string()
+        } else {
+            value.replace('.', &self.decimal_separator.to_string())
+        }
+    }
+}
+
+/// Profile definition in the profiles file; unset options keep the default profile's values
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileSpec {
+    columns: Vec<ColumnSpec>,
+    delimiter: Option<String>,
+    bom: Option<bool>,
+    date_format: Option<String>,
+    decimal_separator: Option<String>,
+}
+
+/// A column given by key alone or with a custom header
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ColumnSpec {
+    Key(String),
+    Labeled { field: String, header: String },
+}
+
+/// Named CSV export profiles, always including [`DEFAULT_PROFILE`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportProfiles {
+    profiles: BTreeMap<String, ExportProfile>,
+}
+
+impl Default for ExportProfiles {
+    fn default() -> Self {
+        let profile = ExportProfile::default();
+        Self {
+            profiles: BTreeMap::from([(profile.name.clone(), profile)]),
+        }
+    }
+}
+
+impl ExportProfiles {
+    /// Parse a JSON object of profiles keyed by name, e.g.
+    /// `{"marketing": {"columns": ["phone", {"field": "name", "header": "Client"}], "delimiter": ","}}`.
+    /// A profile named `default` replaces the standard layout.
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let specs: BTreeMap<String, ProfileSpec> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+        let mut profiles = Self::default();
+        for (name, spec) in specs {
+            let profile = Self::build(&name, spec).map_err(|e| format!("profile \"{}\": {}", name, e))?;
+            profiles.profiles.insert(name, profile);
+        }
+        Ok(profiles)
+    }
+
+    fn build(name: &str, spec: ProfileSpec) -> Result<ExportProfile, String> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err("name may only contain latin letters, digits, '-' and '_'".to_string());
+        }
+        if spec.columns.is_empty() {
+            return Err("no columns defined".to_string());
+        }
+        let defaults = ExportProfile::default();
+
+        let columns = spec
+            .columns
+            .into_iter()
+            .map(|column| {
+                let (key, header) = match column {
+                    ColumnSpec::Key(key) => (key, None),
+                    ColumnSpec::Labeled { field, header } => (field, Some(header)),
+                };
+                let column = CsvColumn::from_key(key.trim()).ok_or_else(|| {
+                    let known: Vec<&str> = CsvColumn::ALL.iter().map(|c| c.key()).collect();
+                    format!("unknown column \"{}\" (known: {})", key, known.join(", "))
+                })?;
+                Ok((column, header.unwrap_or_else(|| column.default_header().to_string())))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let delimiter = match spec.delimiter.as_deref() {
+            None => defaults.delimiter,
+            Some(value) => match value.as_bytes() {
+                [byte] if !matches!(byte, b'"' | b'\n' | b'\r') => *byte,
+                _ => return Err(format!("delimiter must be a single ASCII character, got \"{}\"", value)),
+            },
+        };
+
+        let date_format = spec.date_format.unwrap_or(defaults.date_format);
+        if StrftimeItems::new(&date_format).any(|item| item == Item::Error) {
+            return Err(format!("invalid date format \"{}\"", date_format));
+        }
+
+        let decimal_separator = match spec.decimal_separator.as_deref() {
+            None => defaults.decimal_separator,
+            Some(value) => {
+                let mut chars = value.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => return Err(format!("decimal separator must be a single character, got \"{}\"", value)),
+                }
+            }
+        };
+
+        Ok(ExportProfile {
+            name: name.to_string(),
+            columns,
+            delimiter,
+            bom: spec.bom.unwrap_or(defaults.bom),
+            date_format,
+            decimal_separator,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ExportProfile> {
+        self.profiles.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profiles() {
+        let profiles = ExportProfiles::parse(
+            r#"{
+                "marketing": {"columns": ["phone", {"field": "name", "header": "Клиент"}], "delimiter": ",", "bom": false},
+                "ops": {"columns": ["id", "status", "generation_secs"], "date_format": "%d.%m.%Y %H:%M", "decimal_separator": ","}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(profiles.names().collect::<Vec<_>>(), vec!["default", "marketing", "ops"]);
+
+        let marketing = profiles.get("marketing").unwrap();
+        assert_eq!(
+            marketing.columns,
+            vec![(CsvColumn::Phone, "Телефон".to_string()), (CsvColumn::Name, "Клиент".to_string())]
+        );
+        assert_eq!((marketing.delimiter, marketing.bom), (b',', false));
+
+        let ops = profiles.get("ops").unwrap();
+        assert_eq!(ops.delimiter, b';');
+        assert_eq!(ops.format_decimal("12.5"), "12,5");
+
+        assert!(ExportProfiles::parse(r#"{"x": {"columns": ["email"]}}"#).is_err());
+        assert!(ExportProfiles::parse(r#"{"x": {"columns": ["phone"], "delimiter": ";;"}}"#).is_err());
+        assert!(ExportProfiles::parse(r#"{"x y": {"columns": ["phone"]}}"#).is_err());
+        assert!(ExportProfiles::parse(r#"{"x": {"columns": ["phone"], "date_format": "%Q"}}"#).is_err());
+    }
+}
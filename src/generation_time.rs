@@ -0,0 +1,119 @@
+use serde::Serialize;
+
+/// Number of histogram bins in the generation time distribution
+const HISTOGRAM_BINS: usize = 20;
+
+/// Distribution of generation times (seconds) for done records
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GenerationTimeStats {
+    /// Durations used for the statistics
+    pub count: usize,
+    /// Durations above the outlier cap, left out of the statistics
+    pub excluded_outliers: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub min: f64,
+    pub max: f64,
+    /// Width of a histogram bin in seconds; bin `i` covers `[i * width, (i + 1) * width)`
+    pub bin_width_secs: f64,
+    pub histogram: Vec<usize>,
+}
+
+/// Collects generation durations and drops outliers beyond the cap
+#[derive(Debug, Clone)]
+pub struct GenerationTimeCollector {
+    cap_secs: f64,
+    samples: Vec<f64>,
+    excluded_outliers: usize,
+}
+
+impl GenerationTimeCollector {
+    pub fn new(cap_secs: f64) -> Self {
+        Self {
+            cap_secs,
+            samples: Vec::new(),
+            excluded_outliers: 0,
+        }
+    }
+
+    /// Add a duration in seconds; negative durations are ignored
+    pub fn add(&mut self, secs: f64) {
+        if secs < 0.0 {
+            return;
+        }
+        if secs > self.cap_secs {
+            self.excluded_outliers += 1;
+        } else {
+            self.samples.push(secs);
+        }
+    }
+
+    /// Compute the distribution; `None` when no durations were collected
+    pub fn finish(mut self) -> Option<GenerationTimeStats> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        self.samples.sort_by(|a, b| a.total_cmp(b));
+        let samples = &self.samples;
+
+        let min = samples[0];
+        let max = samples[samples.len() - 1];
+        let bin_width_secs = (max / HISTOGRAM_BINS as f64).ceil().max(1.0);
+        let mut histogram = vec![0; HISTOGRAM_BINS];
+        for &secs in samples {
+            let bin = ((secs / bin_width_secs) as usize).min(HISTOGRAM_BINS - 1);
+            histogram[bin] += 1;
+        }
+
+        Some(GenerationTimeStats {
+            count: samples.len(),
+            excluded_outliers: self.excluded_outliers,
+            mean: samples.iter().sum::<f64>() / samples.len() as f64,
+            median: percentile(samples, 50.0),
+            p90: percentile(samples, 90.0),
+            p95: percentile(samples, 95.0),
+            p99: percentile(samples, 99.0),
+            min,
+            max,
+            bin_width_secs,
+            histogram,
+        })
+    }
+}
+
+/// Nearest-rank percentile of sorted, non-empty samples
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution_excludes_outliers() {
+        let mut collector = GenerationTimeCollector::new(600.0);
+        for secs in 1..=100 {
+            collector.add(secs as f64);
+        }
+        collector.add(5000.0);
+        collector.add(-3.0);
+
+        let stats = collector.finish().unwrap();
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.excluded_outliers, 1);
+        assert_eq!(stats.median, 50.0);
+        assert_eq!(stats.p90, 90.0);
+        assert_eq!(stats.p99, 99.0);
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.max, 100.0);
+        assert_eq!(stats.mean, 50.5);
+        assert_eq!(stats.histogram.iter().sum::<usize>(), 100);
+
+        assert!(GenerationTimeCollector::new(600.0).finish().is_none());
+    }
+}
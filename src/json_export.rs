@@ -0,0 +1,180 @@
+use anyhow::Result;
+use chrono::DateTime;
+use chrono_tz::{Europe::Moscow, Tz};
+use log::info;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::club_directory::ClubMap;
+use crate::date_utils::{get_moscow_time, DateRange};
+use crate::export_profile::CsvColumn;
+use crate::normalized::RecordNormalizer;
+use crate::privacy::Anonymizer;
+use crate::report_files::{persist, temp_path};
+use crate::stats::{ReportStats, StatsSettings};
+
+/// Version of the stats document layout; bumped on incompatible changes
+const SCHEMA_VERSION: u32 = 1;
+
+/// Time zone of all timestamps in the exports
+const TIMEZONE: &str = "Europe/Moscow";
+
+/// Where the records of a report were read from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DataSource {
+    /// Local SQLite mirror
+    Mirror { path: String },
+    /// NocoDB records endpoint
+    Nocodb { url: String },
+}
+
+/// Report statistics with the metadata needed to load them elsewhere
+pub fn stats_document(
+    stats: &ReportStats,
+    date_range: &DateRange,
+    data_source: &DataSource,
+    generated_at: DateTime<Tz>,
+) -> Value {
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "period": {
+            "label": date_range.label,
+            "start": date_range.start.with_timezone(&Moscow).to_rfc3339(),
+            "end": date_range.end.with_timezone(&Moscow).to_rfc3339(),
+        },
+        "timezone": TIMEZONE,
+        "generated_at": generated_at.to_rfc3339(),
+        "data_source": data_source,
+        "stats": stats,
+    })
+}
+
+/// Write the statistics document of a report and return its path
+pub fn write_stats_json(
+    stats: &ReportStats,
+    date_range: &DateRange,
+    data_source: &DataSource,
+    output_path: &str,
+) -> Result<String> {
+    info!("Generating JSON statistics to: {}", output_path);
+    let document = stats_document(stats, date_range, data_source, get_moscow_time());
+    let mut writer = BufWriter::new(File::create(temp_path(output_path))?);
+    serde_json::to_writer_pretty(&mut writer, &document)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    drop(writer);
+    persist(output_path)
+}
+
+/// Incremental writer of normalized records as newline-delimited JSON, one object per line.
+///
+/// Keys are the export column keys plus `club_id`; phones are E.164, timestamps RFC 3339 in
+/// Moscow time and numbers are JSON numbers. Columns dropped by the privacy mode are left out.
+pub struct NdjsonRecordWriter {
+    writer: BufWriter<File>,
+    output_path: String,
+    normalizer: RecordNormalizer,
+    /// Keys of the columns left after the privacy mode
+    keys: Vec<&'static str>,
+    rows: usize,
+}
+
+impl NdjsonRecordWriter {
+    pub fn create(output_path: &str, settings: &StatsSettings, anonymizer: &Anonymizer) -> Result<Self> {
+        info!("Generating NDJSON records to: {} (privacy: {})", output_path, anonymizer.mode().name());
+        let normalizer = RecordNormalizer::new(settings, anonymizer);
+        let mut keys: Vec<&'static str> = normalizer.columns().into_iter().map(CsvColumn::key).collect();
+        keys.push("club_id");
+        Ok(Self {
+            writer: BufWriter::new(File::create(temp_path(output_path))?),
+            output_path: output_path.to_string(),
+            normalizer,
+            keys,
+            rows: 0,
+        })
+    }
+
+    /// Append a single record
+    pub fn write_record(&mut self, record: &Value, clubs: &ClubMap) -> Result<()> {
+        if let Some(obj) = record.as_object() {
+            let mut normalized = match serde_json::to_value(self.normalizer.normalize(obj, clubs))? {
+                Value::Object(map) => map,
+                _ => Map::new(),
+            };
+            normalized.retain(|key, _| self.keys.contains(&key.as_str()));
+            serde_json::to_writer(&mut self.writer, &normalized)?;
+            self.writer.write_all(b"\n")?;
+            self.rows += 1;
+        }
+        Ok(())
+    }
+
+    /// Flush the file, move it into place and return its path
+    pub fn finish(mut self) -> Result<String> {
+        self.writer.flush()?;
+        drop(self.writer);
+        info!("NDJSON records generated successfully with {} records", self.rows);
+        persist(&self.output_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::club_directory::test_clubs;
+    use crate::privacy::PrivacyMode;
+    use crate::stats::StatsCollector;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_stats_document_and_records() {
+        let mut clubs = test_clubs(&[("c1", "Club 1")]);
+        clubs.get_mut("c1").unwrap().city = Some("Москва".to_string());
+        let settings = StatsSettings::default();
+        let record = json!({
+            "Id": 7, "club_id": "c1", "phone": "8 (999) 123-45-67", "name": "Анна", "status": "done",
+            "date_visit": "2024-01-01 10:00:00+00:00", "duration": "15", "text_aura": {"percent": "75%"},
+            "birth_date": "17.05.1990", "sex": "female", "CreatedAt1": "2024-01-01 10:00:00+00:00",
+            "CreatedAt": "2024-01-01 10:00:00+00:00", "UpdatedAt": "2024-01-01 10:01:30+00:00"
+        });
+
+        let mut stats = StatsCollector::new(&clubs, &settings);
+        stats.add_record(&record);
+        let date_range = DateRange {
+            start: Moscow.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap().with_timezone(&chrono::Utc),
+            end: Moscow.with_ymd_and_hms(2024, 1, 1, 23, 59, 59).unwrap().with_timezone(&chrono::Utc),
+            label: "01.01.2024".to_string(),
+        };
+        let source = DataSource::Mirror { path: "data/mirror.db".to_string() };
+        let generated_at = Moscow.with_ymd_and_hms(2024, 1, 2, 9, 0, 0).unwrap();
+        let document = stats_document(&stats.finish(), &date_range, &source, generated_at);
+        assert_eq!(document["period"]["start"], "2024-01-01T00:00:00+03:00");
+        assert_eq!(document["generated_at"], "2024-01-02T09:00:00+03:00");
+        assert_eq!(document["data_source"], json!({"kind": "mirror", "path": "data/mirror.db"}));
+        assert_eq!(document["stats"]["total_records"], 1);
+        assert_eq!(document["stats"]["club_stats"][0]["club_name"], "Club 1");
+
+        let output_path = "test_records.ndjson";
+        let anonymizer = Anonymizer::new(PrivacyMode::Masked, &[]);
+        let mut writer = NdjsonRecordWriter::create(output_path, &settings, &anonymizer).unwrap();
+        writer.write_record(&record, &clubs).unwrap();
+        writer.write_record(&json!({"Id": 8, "club_id": "zz"}), &clubs).unwrap();
+        writer.finish().unwrap();
+
+        let content = std::fs::read_to_string(output_path).unwrap();
+        std::fs::remove_file(output_path).unwrap();
+        let lines: Vec<Value> = content.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["phone"], "+7 999 ***-**-67");
+        assert_eq!(lines[0]["date_visit"], "2024-01-01T13:00:00+03:00");
+        assert_eq!(lines[0]["aura"], 75.0);
+        assert_eq!(lines[0]["duration"], 15.0);
+        assert_eq!(lines[0]["generation_secs"], 90.0);
+        assert_eq!(lines[0]["city"], "Москва");
+        assert!(lines[0].get("name").is_none() && lines[0].get("birth_date").is_none());
+        assert_eq!((&lines[1]["club_id"], &lines[1]["club"]), (&json!("zz"), &Value::Null));
+    }
+}
@@ -1,19 +1,45 @@
 use anyhow::Result;
 use teloxide::prelude::*;
-use teloxide::types::InputFile;
-use log::{info, error};
+use log::{info, error, warn};
 use std::sync::Arc;
+use tokio::time::{sleep, Duration};
 
+mod archive;
+mod chart_images;
+mod aura;
+mod club_directory;
 mod config;
+mod data_quality;
+mod export_profile;
+mod json_export;
 mod nocodb;
+mod normalized;
 mod csv_generator;
 mod pdf_generator;
+mod phone;
+mod privacy;
 mod date_utils;
+mod demographics;
+mod generation_time;
+mod mirror;
+#[cfg(feature = "parquet")]
+mod parquet_export;
+mod report_files;
+mod report_message;
+mod report_output;
 mod report_service;
+mod retention;
 mod scheduler;
+mod stats;
+mod subscription;
+mod xlsx_generator;
 
 use config::Config;
 use date_utils::Period;
+use export_profile::{ExportProfile, DEFAULT_PROFILE};
+use report_files::input_file;
+use report_output::{ReportOutput, ReportOutputs};
+use report_message::{format_club_directory, format_data_quality_message, format_stats_message, split_message};
 use report_service::ReportService;
 use scheduler::Scheduler;
 
@@ -32,11 +58,17 @@ async fn main() -> Result<()> {
     info!("Telegram bot initialized");
 
     // Initialize report service
-    let report_service = Arc::new(ReportService::new(&config));
+    let report_service = Arc::new(ReportService::new(&config)?);
     
     // Create output directory
     std::fs::create_dir_all("reports")?;
 
+    // Keep the local mirror in sync in background (no-op when disabled)
+    let mirror_report_service = report_service.clone();
+    tokio::spawn(async move {
+        mirror_report_service.run_mirror_sync().await;
+    });
+
     // Start scheduler in background
     let scheduler = Scheduler::new(bot.clone(), config.clone(), report_service.clone());
     tokio::spawn(async move {
@@ -71,20 +103,28 @@ enum Command {
     Start,
     #[command(description = "Справка по командам")]
     Help,
-    #[command(description = "Отчет за сегодня")]
-    Today,
+    #[command(description = "Отчет за сегодня: /today [профиль CSV] [summary|charts|csv|xlsx|pdf|parquet|json]")]
+    Today(String),
     #[command(description = "Отчет за вчера")]
-    Yesterday,
+    Yesterday(String),
     #[command(description = "Отчет за текущую неделю")]
-    Week,
+    Week(String),
     #[command(description = "Отчет за текущий месяц")]
-    Month,
+    Month(String),
     #[command(description = "Отчет за текущий квартал")]
-    Quarter,
+    Quarter(String),
     #[command(description = "Отчет за полугодие")]
-    Halfyear,
+    Halfyear(String),
     #[command(description = "Отчет за текущий год")]
-    Year,
+    Year(String),
+    #[command(description = "Проверка качества данных: /dataquality [today|yesterday|week|month|quarter|halfyear|year]")]
+    DataQuality(String),
+    #[command(description = "Файлы по каждому комплексу в одном архиве: /clubsplit [период] [профиль CSV] [pdf]")]
+    ClubSplit(String),
+    #[command(description = "Обновить справочник комплексов (админ)")]
+    RefreshClubs,
+    #[command(description = "Пароль архивов чата (админ): /archivepassword [chat_id]")]
+    ArchivePassword(String),
 }
 
 async fn handle_command(
@@ -114,6 +154,8 @@ async fn handle_command(
                 /quarter - Отчет за текущий квартал\n\
                 /halfyear - Отчет за полугодие\n\
                 /year - Отчет за текущий год\n\n\
+                /dataquality - Проверка качества данных\n\
+                /clubsplit - Файлы по каждому комплексу\n\
                 /help - Подробная справка",
                 config.report_schedule_time
             );
@@ -129,123 +171,332 @@ async fn handle_command(
                 /quarter - Отчет с начала текущего квартала\n\
                 /halfyear - Отчет за текущее полугодие\n\
                 /year - Отчет с начала текущего года\n\n\
-                Каждая команда генерирует:\n\
-                ✅ CSV файл с данными\n\
-                ✅ PDF файл с графиками\n\n\
+                После команды отчета можно указать профиль CSV, например /week marketing. \
+                Доступные профили: {}\n\
+                Можно выбрать, что прислать: summary (сводка), charts (графики), csv, xlsx, pdf, \
+                parquet, json, например /today summary или /week marketing csv pdf\n\n\
+                /dataquality [период] - Проверка качества данных (по умолчанию за сегодня), \
+                например /dataquality week\n\
+                /clubsplit [период] [профиль CSV] [pdf] - CSV (и PDF с флагом pdf) по каждому комплексу \
+                и по всей сети в одном архиве, например /clubsplit month pdf\n\
+                /refreshclubs - Обновить справочник комплексов (для администраторов)\n\
+                /archivepassword [chat_id] - Пароль зашифрованных архивов чата (для администраторов)\n\n\
+                По умолчанию команда присылает:\n\
+                ✅ Сводку и графики\n\
+                ✅ CSV, XLSX и PDF файлы\n\n\
                 📅 Автоматические отчеты отправляются ежедневно в {} МСК",
+                config.csv_profiles.names().collect::<Vec<_>>().join(", "),
                 config.report_schedule_time
             );
             bot.send_message(msg.chat.id, help_text).await?;
         }
-        Command::Today => {
-            generate_and_send_report(bot, msg.chat.id, Period::Today, report_service).await?;
+        Command::Today(args) => {
+            handle_report_command(bot, msg.chat.id, Period::Today, &args, &config, report_service).await?;
         }
-        Command::Yesterday => {
-            generate_and_send_report(bot, msg.chat.id, Period::Yesterday, report_service).await?;
+        Command::Yesterday(args) => {
+            handle_report_command(bot, msg.chat.id, Period::Yesterday, &args, &config, report_service).await?;
         }
-        Command::Week => {
-            generate_and_send_report(bot, msg.chat.id, Period::Week, report_service).await?;
+        Command::Week(args) => {
+            handle_report_command(bot, msg.chat.id, Period::Week, &args, &config, report_service).await?;
         }
-        Command::Month => {
-            generate_and_send_report(bot, msg.chat.id, Period::Month, report_service).await?;
+        Command::Month(args) => {
+            handle_report_command(bot, msg.chat.id, Period::Month, &args, &config, report_service).await?;
         }
-        Command::Quarter => {
-            generate_and_send_report(bot, msg.chat.id, Period::Quarter, report_service).await?;
+        Command::Quarter(args) => {
+            handle_report_command(bot, msg.chat.id, Period::Quarter, &args, &config, report_service).await?;
         }
-        Command::Halfyear => {
-            generate_and_send_report(bot, msg.chat.id, Period::HalfYear, report_service).await?;
+        Command::Halfyear(args) => {
+            handle_report_command(bot, msg.chat.id, Period::HalfYear, &args, &config, report_service).await?;
         }
-        Command::Year => {
-            generate_and_send_report(bot, msg.chat.id, Period::Year, report_service).await?;
+        Command::Year(args) => {
+            handle_report_command(bot, msg.chat.id, Period::Year, &args, &config, report_service).await?;
+        }
+        Command::DataQuality(period_name) => {
+            let period = if period_name.trim().is_empty() {
+                Period::Today
+            } else {
+                match Period::from_name(&period_name) {
+                    Some(period) => period,
+                    None => {
+                        bot.send_message(
+                            msg.chat.id,
+                            "❌ Неизвестный период. Доступно: today, yesterday, week, month, quarter, halfyear, year",
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                }
+            };
+            let date_range = period.get_date_range();
+
+            bot.send_message(msg.chat.id, format!("🔄 Проверяю качество данных: {}", date_range.label))
+                .await?;
+
+            match report_service.audit_data_quality(period).await {
+                Ok(data_quality) => {
+                    bot.send_message(msg.chat.id, format_data_quality_message(&date_range.label, &data_quality))
+                        .parse_mode(teloxide::types::ParseMode::Html)
+                        .await?;
+                }
+                Err(e) => {
+                    error!("Failed to run data quality audit: {}", e);
+                    bot.send_message(
+                        msg.chat.id,
+                        format!("❌ Ошибка при проверке качества данных: {}", e),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Command::ClubSplit(args) => {
+            let mut period = Period::Today;
+            let mut profile_name = DEFAULT_PROFILE;
+            let mut with_pdf = false;
+            for arg in args.split_whitespace() {
+                if let Some(p) = Period::from_name(arg) {
+                    period = p;
+                } else if arg.eq_ignore_ascii_case("pdf") {
+                    with_pdf = true;
+                } else {
+                    profile_name = arg;
+                }
+            }
+            let Some(csv_profile) = config.csv_profiles.get(profile_name) else {
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "❌ Неизвестный профиль CSV: {}. Доступно: {}",
+                        profile_name,
+                        config.csv_profiles.names().collect::<Vec<_>>().join(", ")
+                    ),
+                )
+                .await?;
+                return Ok(());
+            };
+            let date_range = period.get_date_range();
+            let privacy = config.privacy_for(msg.chat.id.0);
+
+            bot.send_message(msg.chat.id, format!("🔄 Готовлю файлы по комплексам: {}", date_range.label))
+                .await?;
+
+            let archive = report_service
+                .generate_club_split(period, "reports", csv_profile, &config.anonymizer(privacy), with_pdf)
+                .await
+                .and_then(|mut split| {
+                    let password = config.archive_password(msg.chat.id.0, privacy);
+                    let archive_path = split.write_archive(password.as_deref())?;
+                    Ok((split, archive_path, password.is_some()))
+                });
+            match archive {
+                Ok((split, archive_path, encrypted)) => {
+                    let caption = format!(
+                        "🗂 Файлы по комплексам: {} (комплексов: {}){}",
+                        date_range.label,
+                        split.club_count,
+                        if encrypted { "\n🔒 AES-256, пароль выдает администратор" } else { "" }
+                    );
+                    bot.send_document(msg.chat.id, input_file(&archive_path)?)
+                        .caption(caption)
+                        .await?;
+                }
+                Err(e) => {
+                    error!("Failed to generate per-club split export: {}", e);
+                    bot.send_message(msg.chat.id, format!("❌ Ошибка при подготовке файлов по комплексам: {}", e))
+                        .await?;
+                }
+            }
+        }
+        Command::RefreshClubs => {
+            if !config.is_admin(msg.chat.id.0) {
+                bot.send_message(msg.chat.id, "❌ Команда доступна только администраторам.")
+                    .await?;
+                return Ok(());
+            }
+
+            match report_service.refresh_clubs().await {
+                Ok(clubs) => {
+                    bot.send_message(msg.chat.id, format_club_directory(&clubs))
+                        .parse_mode(teloxide::types::ParseMode::Html)
+                        .await?;
+                }
+                Err(e) => {
+                    error!("Failed to refresh clubs: {}", e);
+                    bot.send_message(
+                        msg.chat.id,
+                        format!("❌ Не удалось обновить справочник комплексов: {}", e),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Command::ArchivePassword(chat_id) => {
+            if !config.is_admin(msg.chat.id.0) {
+                bot.send_message(msg.chat.id, "❌ Команда доступна только администраторам.")
+                    .await?;
+                return Ok(());
+            }
+            if config.archive_password_secret.trim().is_empty() {
+                bot.send_message(msg.chat.id, "❌ Шифрование архивов не настроено (ARCHIVE_PASSWORD_SECRET).")
+                    .await?;
+                return Ok(());
+            }
+            // Group members and admins of other bots must never see a password
+            if !msg.chat.is_private() {
+                bot.send_message(msg.chat.id, "❌ Пароль выдается только в личном чате с ботом.")
+                    .await?;
+                return Ok(());
+            }
+
+            let chat_id = match chat_id.trim() {
+                "" => Ok(msg.chat.id.0),
+                value => value.parse::<i64>(),
+            };
+            match chat_id {
+                Ok(chat_id) => {
+                    let ttl_secs = config.archive_password_ttl_secs;
+                    let sent = bot
+                        .send_message(
+                            msg.chat.id,
+                            format!(
+                                "🔑 Пароль архивов для чата <code>{}</code>: <code>{}</code>\n\
+                                Передайте его получателю вне Telegram. Сообщение будет удалено через {} с.",
+                                chat_id,
+                                config.chat_archive_password(chat_id),
+                                ttl_secs
+                            ),
+                        )
+                        .parse_mode(teloxide::types::ParseMode::Html)
+                        .await?;
+
+                    // Keep the password out of the chat history
+                    tokio::spawn(async move {
+                        sleep(Duration::from_secs(ttl_secs)).await;
+                        if let Err(e) = bot.delete_message(sent.chat.id, sent.id).await {
+                            warn!("Failed to delete archive password message: {}", e);
+                        }
+                    });
+                }
+                Err(_) => {
+                    bot.send_message(msg.chat.id, "❌ Укажите числовой chat_id, например /archivepassword 123456789")
+                        .await?;
+                }
+            }
         }
     }
 
     Ok(())
 }
 
+/// Parse the arguments of a report command (CSV profile and report outputs in any order) and
+/// send the report
+async fn handle_report_command(
+    bot: Bot,
+    chat_id: ChatId,
+    period: Period,
+    args: &str,
+    config: &Config,
+    report_service: Arc<ReportService>,
+) -> ResponseResult<()> {
+    let mut profile_name = DEFAULT_PROFILE;
+    let mut selected = Vec::new();
+    for arg in args.split_whitespace() {
+        match ReportOutput::from_name(arg) {
+            Some(output) => selected.push(output),
+            None => profile_name = arg,
+        }
+    }
+    let outputs = if selected.is_empty() {
+        ReportOutputs::default()
+    } else {
+        selected.into_iter().collect()
+    };
+    if let Some(output) = outputs.unavailable() {
+        bot.send_message(
+            chat_id,
+            format!("❌ Формат {} недоступен: бот собран без функции {}.", output.name(), output.name()),
+        )
+        .await?;
+        return Ok(());
+    }
+    let Some(csv_profile) = config.csv_profiles.get(profile_name) else {
+        bot.send_message(
+            chat_id,
+            format!(
+                "❌ Неизвестный профиль CSV: {}. Доступно: {}",
+                profile_name,
+                config.csv_profiles.names().collect::<Vec<_>>().join(", ")
+            ),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    generate_and_send_report(bot, chat_id, period, report_service, config, csv_profile, &outputs).await
+}
+
 async fn generate_and_send_report(
     bot: Bot,
     chat_id: ChatId,
     period: Period,
     report_service: Arc<ReportService>,
+    config: &Config,
+    csv_profile: &ExportProfile,
+    outputs: &ReportOutputs,
 ) -> ResponseResult<()> {
     let date_range = period.get_date_range();
-    
+    let privacy = config.privacy_for(chat_id.0);
+    let anonymizer = config.anonymizer(privacy);
+
     bot.send_message(chat_id, format!("🔄 Генерирую отчет: {}", date_range.label))
         .await?;
 
-    match report_service.generate_report(period, "reports").await {
-        Ok((csv_path, pdf_path, stats)) => {
-            // Build club statistics section
-            let mut club_stats_text = String::new();
-            if !stats.club_stats.is_empty() {
-                club_stats_text.push_str("\n\n📍 <b>Статистика по комплексам:</b>\n");
-                for club_stat in &stats.club_stats {
-                    let escaped_name = club_stat.club_name
-                        .replace("&", "&amp;")
-                        .replace("<", "&lt;")
-                        .replace(">", "&gt;");
-                    club_stats_text.push_str(&format!(
-                        "\n🏢 <i>{}</i>\n   Генераций: <b>{}</b> ({:.1}%)\n   Клиентов: <b>{}</b>",
-                        escaped_name,
-                        club_stat.total_generations,
-                        club_stat.percentage,
-                        club_stat.unique_clients
-                    ));
+    match report_service.generate_report(period, "reports", csv_profile, &anonymizer, outputs).await {
+        Ok(mut report) => {
+            if outputs.contains(ReportOutput::Summary) {
+                let stats_message = format_stats_message("Статистика по отчету", &report.stats, config.leaderboard_size);
+
+                for part in split_message(&stats_message) {
+                    bot.send_message(chat_id, part)
+                        .parse_mode(teloxide::types::ParseMode::Html)
+                        .await?;
                 }
             }
-            
-            // Build generation time section
-            let generation_time_text = if stats.avg_generation_time > 0.0 {
-                format!("\n\n⏱ <b>Среднее время генерации (done):</b> {:.1} сек", stats.avg_generation_time)
-            } else {
-                String::new()
-            };
-            
-            // Build status statistics section
-            let status_text = format!(
-                "\n\n📋 <b>Статусы генераций:</b>\n   ✅ Done: <b>{}</b> ({:.1}%)\n   ⏳ Process: <b>{}</b> ({:.1}%)",
-                stats.done_count,
-                stats.done_percentage,
-                stats.process_count,
-                stats.process_percentage
-            );
-            
-            // Send statistics message
-            let stats_message = format!(
-                "📊 <b>Статистика по отчету</b>\n\n\
-                📈 Всего генераций: <b>{}</b>\n\
-                👥 Уникальных клиентов: <b>{}</b>\n\n\
-                🔴 Низкая аура (&lt;60%): <b>{}</b>\n\
-                🟡 Нормальная аура (60-80%): <b>{}</b>\n\
-                🟢 Высокая аура (&gt;80%): <b>{}</b>{}{}{}",
-                stats.total_records,
-                stats.unique_clients,
-                stats.low_aura,
-                stats.normal_aura,
-                stats.high_aura,
-                club_stats_text,
-                generation_time_text,
-                status_text
-            );
-            
-            bot.send_message(chat_id, stats_message)
-                .parse_mode(teloxide::types::ParseMode::Html)
-                .await?;
 
-            bot.send_message(chat_id, "✅ Отчет готов! Отправляю файлы...")
-                .await?;
+            if outputs.contains(ReportOutput::Charts) {
+                chart_images::send_chart_images(&bot, chat_id, &report.chart_images).await?;
+            }
 
-            // Send CSV
-            bot.send_document(chat_id, InputFile::file(&csv_path))
-                .caption("📄 CSV данные")
-                .await?;
+            if !outputs.has_files() {
+                return Ok(());
+            }
 
-            // Send PDF
-            bot.send_document(chat_id, InputFile::file(&pdf_path))
-                .caption("📊 PDF с графиками")
+            bot.send_message(chat_id, "✅ Отчет готов! Отправляю файлы...")
                 .await?;
 
+            if let Some(password) = config.archive_password(chat_id.0, privacy) {
+                // Full PII: all files in one encrypted archive, the password is handed over by an admin
+                match report.write_archive(chat_id.0, &password, outputs) {
+                    Ok(archive_path) => {
+                        report.remove_plain_files();
+                        bot.send_document(chat_id, input_file(&archive_path)?)
+                            .caption("🔒 Архив с отчетом (AES-256). Пароль выдает администратор")
+                            .await?;
+                    }
+                    Err(e) => {
+                        error!("Failed to pack report archive: {}", e);
+                        bot.send_message(chat_id, format!("❌ Ошибка при упаковке архива: {}", e))
+                            .await?;
+                        return Ok(());
+                    }
+                }
+            } else {
+                for (path, caption) in report.files(outputs) {
+                    bot.send_document(chat_id, input_file(path)?)
+                        .caption(caption)
+                        .await?;
+                }
+            }
+
             bot.send_message(chat_id, "✨ Отчет успешно отправлен!")
                 .await?;
         }
@@ -0,0 +1,539 @@
+use anyhow::{anyhow, Result};
+use async_stream::try_stream;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures::{pin_mut, Stream, TryStreamExt};
+use log::info;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::date_utils::{parse_record_datetime, DateRange};
+use crate::nocodb::{NocoDBClient, RecordQuery};
+use crate::retention::VisitHistory;
+
+/// Largest change timestamp already stored locally
+const WATERMARK_KEY: &str = "updated_at_watermark";
+/// Set once the first full download has completed
+const LAST_FULL_SYNC_KEY: &str = "last_full_sync";
+/// Time of the last deletion reconciliation
+const LAST_RECONCILE_KEY: &str = "last_reconcile";
+
+/// Timestamp format stored in the mirror, sortable as text
+const DB_DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Local SQLite copy of the NocoDB generations table.
+///
+/// New and changed rows are pulled incrementally using the `UpdatedAt`
+/// watermark; rows deleted in NocoDB are removed by a periodic reconciliation
+/// that compares the full list of ids.
+pub struct LocalMirror {
+    conn: Arc<Mutex<Connection>>,
+    path: String,
+    date_field_name: String,
+    updated_field_name: String,
+    sync_lock: AsyncMutex<()>,
+}
+
+impl LocalMirror {
+    /// Open (or create) the mirror database at the given path
+    pub fn open(path: &str, date_field_name: &str, updated_field_name: &str) -> Result<Self> {
+        if let Some(parent) = Path::new(path).parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        let mirror = Self::with_connection(Connection::open(path)?, path, date_field_name, updated_field_name)?;
+        info!("Local mirror opened at {}", path);
+        Ok(mirror)
+    }
+
+    /// Create the tables on an open connection and wrap it
+    fn with_connection(conn: Connection, path: &str, date_field_name: &str, updated_field_name: &str) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS generations (
+                id TEXT PRIMARY KEY,
+                record_date TEXT,
+                updated_at TEXT,
+                data TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_generations_record_date ON generations(record_date);
+            CREATE TABLE IF NOT EXISTS sync_state (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );",
+        )?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            path: path.to_string(),
+            date_field_name: date_field_name.to_string(),
+            updated_field_name: updated_field_name.to_string(),
+            sync_lock: AsyncMutex::new(()),
+        })
+    }
+
+    /// Path of the database file
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Run a blocking database operation on the blocking thread pool
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().map_err(|_| anyhow!("Mirror connection lock poisoned"))?;
+            f(&mut conn)
+        })
+        .await?
+    }
+
+    async fn get_state(&self, key: &'static str) -> Result<Option<String>> {
+        self.with_conn(move |conn| {
+            Ok(conn
+                .query_row("SELECT value FROM sync_state WHERE key = ?1", params![key], |row| row.get(0))
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn set_state(&self, key: &'static str, value: String) -> Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO sync_state (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![key, value],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Whether the mirror holds a complete copy and can answer queries
+    pub async fn is_ready(&self) -> Result<bool> {
+        Ok(self.get_state(LAST_FULL_SYNC_KEY).await?.is_some())
+    }
+
+    /// Whether enough time has passed since the last reconciliation
+    pub async fn is_reconcile_due(&self, interval_secs: u64) -> Result<bool> {
+        let last = self
+            .get_state(LAST_RECONCILE_KEY)
+            .await?
+            .and_then(|v| DateTime::parse_from_rfc3339(&v).ok())
+            .map(|dt| dt.with_timezone(&Utc));
+
+        Ok(match last {
+            Some(last) => (Utc::now() - last).num_seconds() >= interval_secs as i64,
+            None => true,
+        })
+    }
+
+    /// Pull records created or updated since the watermark and upsert them locally
+    pub async fn sync(&self, client: &NocoDBClient) -> Result<usize> {
+        let _guard = self.sync_lock.lock().await;
+
+        let watermark = self
+            .get_state(WATERMARK_KEY)
+            .await?
+            .and_then(|v| parse_record_datetime(&v));
+
+        let filter = watermark.map(|w| self.changed_since_filter(w));
+
+        let is_full_sync = filter.is_none();
+        info!(
+            "Starting {} mirror sync (watermark: {:?})",
+            if is_full_sync { "full" } else { "incremental" },
+            watermark
+        );
+
+        let query = RecordQuery {
+            filter,
+            fields: Vec::new(),
+            sort: vec![self.updated_field_name.clone(), "Id".to_string()],
+        };
+
+        let pages = client.stream_records(&query);
+        pin_mut!(pages);
+
+        let mut upserted = 0;
+        let mut new_watermark = watermark;
+        while let Some(page) = pages.try_next().await? {
+            upserted += self.upsert_page(&page, &mut new_watermark).await?;
+        }
+
+        if let Some(w) = new_watermark {
+            self.set_state(WATERMARK_KEY, w.format(DB_DATETIME_FORMAT).to_string()).await?;
+        }
+        if is_full_sync {
+            self.set_state(LAST_FULL_SYNC_KEY, Utc::now().to_rfc3339()).await?;
+        }
+
+        info!("Mirror sync finished: {} records upserted", upserted);
+        Ok(upserted)
+    }
+
+    /// NocoDB filter for records created or updated since the watermark.
+    ///
+    /// exactDate filters have minute precision, so the watermark minute is read again;
+    /// upserts make the overlap harmless. Records that were never updated may have an
+    /// empty UpdatedAt, hence the additional check on the date field.
+    fn changed_since_filter(&self, watermark: DateTime<Utc>) -> String {
+        let w = watermark.format("%Y-%m-%d %H:%M").to_string();
+        format!(
+            "({},ge,exactDate,{})~or({},ge,exactDate,{})",
+            self.updated_field_name, w, self.date_field_name, w
+        )
+    }
+
+    /// Upsert one page of records in a transaction, advancing the watermark to the latest
+    /// change seen; returns the number of records stored
+    async fn upsert_page(&self, page: &[Value], watermark: &mut Option<DateTime<Utc>>) -> Result<usize> {
+        let mut rows = Vec::with_capacity(page.len());
+        for record in page {
+            let Some(id) = record.get("Id").map(|id| id.to_string()) else {
+                continue;
+            };
+            let record_date = self.record_datetime(record, &self.date_field_name);
+            let updated_at = self.record_datetime(record, &self.updated_field_name);
+
+            if let Some(changed) = updated_at.or(record_date) {
+                *watermark = Some(watermark.map_or(changed, |w| w.max(changed)));
+            }
+
+            rows.push((
+                id,
+                record_date.map(|d| d.format(DB_DATETIME_FORMAT).to_string()),
+                updated_at.map(|d| d.format(DB_DATETIME_FORMAT).to_string()),
+                record.to_string(),
+            ));
+        }
+
+        let upserted = rows.len();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT INTO generations (id, record_date, updated_at, data) VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT(id) DO UPDATE SET
+                        record_date = excluded.record_date,
+                        updated_at = excluded.updated_at,
+                        data = excluded.data",
+                )?;
+                for (id, record_date, updated_at, data) in rows {
+                    stmt.execute(params![id, record_date, updated_at, data])?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+        Ok(upserted)
+    }
+
+    /// Remove local records that no longer exist in NocoDB
+    pub async fn reconcile(&self, client: &NocoDBClient) -> Result<usize> {
+        let _guard = self.sync_lock.lock().await;
+        info!("Starting mirror reconciliation");
+
+        let pages = client.stream_ids();
+        pin_mut!(pages);
+
+        let mut remote_ids = HashSet::new();
+        while let Some(page) = pages.try_next().await? {
+            remote_ids.extend(page.iter().filter_map(|r| r.get("Id").map(|id| id.to_string())));
+        }
+
+        let deleted = self.delete_missing(remote_ids).await?;
+
+        self.set_state(LAST_RECONCILE_KEY, Utc::now().to_rfc3339()).await?;
+        info!("Mirror reconciliation finished: {} records deleted", deleted);
+        Ok(deleted)
+    }
+
+    /// Delete local records whose ids are not among the remote ids; returns the number deleted
+    async fn delete_missing(&self, remote_ids: HashSet<String>) -> Result<usize> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let local_ids: Vec<String> = {
+                let mut stmt = tx.prepare("SELECT id FROM generations")?;
+                let ids = stmt
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                ids
+            };
+
+            let mut deleted = 0;
+            {
+                let mut stmt = tx.prepare_cached("DELETE FROM generations WHERE id = ?1")?;
+                for id in local_ids.iter().filter(|id| !remote_ids.contains(*id)) {
+                    deleted += stmt.execute(params![id])?;
+                }
+            }
+            tx.commit()?;
+            Ok(deleted)
+        })
+        .await
+    }
+
+    /// Add the phone and date of every stored record up to `end` to the visit history. Only these
+    /// values are read from SQLite, so the whole history is loaded without parsing records.
+    ///
+    /// With `known_clubs`, records whose `club_id` is missing or not in the set are left out, as
+    /// the statistics do in the "exclude" unknown clubs mode.
+    pub async fn load_visit_history(
+        &self,
+        mut history: VisitHistory,
+        end: DateTime<Utc>,
+        known_clubs: Option<HashSet<String>>,
+    ) -> Result<VisitHistory> {
+        let end = end.format(DB_DATETIME_FORMAT).to_string();
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT json_extract(data, '$.phone'), record_date, json_extract(data, '$.club_id')
+                 FROM generations WHERE record_date IS NOT NULL AND record_date <= ?1",
+            )?;
+            let mut rows = stmt.query(params![end])?;
+            while let Some(row) = rows.next()? {
+                if let Some(known_clubs) = &known_clubs {
+                    let club_id: Option<String> = match row.get::<_, SqlValue>(2)? {
+                        SqlValue::Text(club_id) => Some(club_id),
+                        _ => None,
+                    };
+                    if !club_id.is_some_and(|id| known_clubs.contains(id.trim())) {
+                        continue;
+                    }
+                }
+                let phone = match row.get::<_, SqlValue>(0)? {
+                    SqlValue::Text(phone) => Value::String(phone),
+                    SqlValue::Integer(phone) => Value::from(phone),
+                    _ => continue,
+                };
+                let record_date: String = row.get(1)?;
+                if let Ok(date) = NaiveDateTime::parse_from_str(&record_date, DB_DATETIME_FORMAT) {
+                    history.add_visit(&phone, date.and_utc());
+                }
+            }
+            Ok(history)
+        })
+        .await
+    }
+
+    /// Stream locally stored records within the date range, ordered by date
+    pub fn stream_range<'a>(
+        &'a self,
+        date_range: &'a DateRange,
+        page_size: usize,
+    ) -> impl Stream<Item = Result<Vec<Value>>> + 'a {
+        try_stream! {
+            let start = date_range.start.format(DB_DATETIME_FORMAT).to_string();
+            let end = date_range.end.format(DB_DATETIME_FORMAT).to_string();
+            let limit = page_size.max(1) as i64;
+
+            // Keyset pagination over (record_date, id)
+            let mut after: Option<(String, String)> = None;
+            let mut total_count = 0;
+
+            loop {
+                let (start, end, cursor) = (start.clone(), end.clone(), after.clone());
+                let rows: Vec<(String, String, String)> = self
+                    .with_conn(move |conn| {
+                        let (after_date, after_id) = cursor.unwrap_or_default();
+                        let mut stmt = conn.prepare_cached(
+                            "SELECT record_date, id, data FROM generations
+                             WHERE record_date >= ?1 AND record_date <= ?2
+                               AND (?3 = '' OR record_date > ?3 OR (record_date = ?3 AND id > ?4))
+                             ORDER BY record_date, id
+                             LIMIT ?5",
+                        )?;
+                        let rows = stmt
+                            .query_map(params![start, end, after_date, after_id, limit], |row| {
+                                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                            })?
+                            .collect::<rusqlite::Result<Vec<_>>>()?;
+                        Ok(rows)
+                    })
+                    .await?;
+
+                let rows_count = rows.len();
+                if let Some((record_date, id, _)) = rows.last() {
+                    after = Some((record_date.clone(), id.clone()));
+                }
+
+                let mut page = Vec::with_capacity(rows_count);
+                for (_, _, data) in rows {
+                    page.push(serde_json::from_str::<Value>(&data)?);
+                }
+                total_count += page.len();
+
+                if !page.is_empty() {
+                    yield page;
+                }
+
+                if (rows_count as i64) < limit {
+                    break;
+                }
+            }
+
+            info!("Loaded {} records from local mirror for period: {}", total_count, date_range.label);
+        }
+    }
+
+    fn record_datetime(&self, record: &Value, field: &str) -> Option<DateTime<Utc>> {
+        record
+            .get(field)
+            .and_then(|v| v.as_str())
+            .and_then(parse_record_datetime)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_sync_upserts_changes_and_reconcile_deletes_missing() {
+        let mirror =
+            LocalMirror::with_connection(Connection::open_in_memory().unwrap(), ":memory:", "CreatedAt1", "UpdatedAt")
+                .unwrap();
+        let at = |hour, min| Utc.with_ymd_and_hms(2024, 5, 1, hour, min, 0).unwrap();
+
+        // First sync inserts every record and sets the watermark to the latest change
+        let mut watermark = None;
+        let page = [
+            json!({"Id": 1, "CreatedAt1": "2024-05-01 10:00:00", "UpdatedAt": "2024-05-01 10:01:00", "status": "done"}),
+            json!({"Id": 2, "CreatedAt1": "2024-05-01 10:05:00", "UpdatedAt": "2024-05-01 10:05:00", "status": "process"}),
+            json!({"Id": 3, "CreatedAt1": "2024-05-01 10:10:00", "status": "process"}),
+        ];
+        assert_eq!(mirror.upsert_page(&page, &mut watermark).await.unwrap(), 3);
+        assert_eq!(watermark, Some(at(10, 10)));
+
+        // The next sync asks only for records changed since the watermark minute
+        assert_eq!(
+            mirror.changed_since_filter(at(10, 10)),
+            "(UpdatedAt,ge,exactDate,2024-05-01 10:10)~or(CreatedAt1,ge,exactDate,2024-05-01 10:10)"
+        );
+
+        // A record updated past the watermark replaces its row and moves the watermark on
+        let update = [json!({"Id": 2, "CreatedAt1": "2024-05-01 10:05:00", "UpdatedAt": "2024-05-01 11:00:00", "status": "done"})];
+        assert_eq!(mirror.upsert_page(&update, &mut watermark).await.unwrap(), 1);
+        assert_eq!(watermark, Some(at(11, 0)));
+
+        // Record 3 was deleted in NocoDB
+        let remote_ids = HashSet::from(["1".to_string(), "2".to_string()]);
+        assert_eq!(mirror.delete_missing(remote_ids.clone()).await.unwrap(), 1);
+        assert_eq!(mirror.delete_missing(remote_ids).await.unwrap(), 0);
+
+        let may = DateRange {
+            start: at(0, 0),
+            end: Utc.with_ymd_and_hms(2024, 5, 31, 23, 59, 59).unwrap(),
+            label: "May".to_string(),
+        };
+        let records: Vec<Value> = mirror
+            .stream_range(&may, 10)
+            .try_collect::<Vec<Vec<Value>>>()
+            .await
+            .unwrap()
+            .concat();
+        let ids: Vec<i64> = records.iter().map(|r| r["Id"].as_i64().unwrap()).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(records[1]["status"], "done");
+    }
+
+    #[tokio::test]
+    async fn test_stream_range_pages_through_matching_records() {
+        let path = std::env::temp_dir().join(format!("mirror_test_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mirror = LocalMirror::open(path.to_str().unwrap(), "CreatedAt1", "UpdatedAt").unwrap();
+
+        mirror
+            .with_conn(|conn| {
+                for (id, date) in [(1, "2024-05-01 10:00:00"), (2, "2024-05-01 10:00:00"), (3, "2024-05-02 09:00:00"), (4, "2024-06-01 00:00:00")] {
+                    conn.execute(
+                        "INSERT INTO generations (id, record_date, updated_at, data) VALUES (?1, ?2, ?2, ?3)",
+                        params![id.to_string(), date, format!("{{\"Id\": {}}}", id)],
+                    )?;
+                }
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let date_range = DateRange {
+            start: Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2024, 5, 31, 23, 59, 59).unwrap(),
+            label: "May".to_string(),
+        };
+
+        let pages: Vec<Vec<Value>> = mirror.stream_range(&date_range, 2).try_collect().await.unwrap();
+        let ids: Vec<i64> = pages.iter().flatten().map(|r| r["Id"].as_i64().unwrap()).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_load_visit_history_reads_phones_and_dates() {
+        let path = std::env::temp_dir().join(format!("mirror_history_test_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mirror = LocalMirror::open(path.to_str().unwrap(), "CreatedAt1", "UpdatedAt").unwrap();
+
+        mirror
+            .with_conn(|conn| {
+                let rows = [
+                    (1, "2024-01-10 10:00:00", r#"{"Id": 1, "phone": "79990000001"}"#),
+                    (2, "2024-03-05 10:00:00", r#"{"Id": 2, "phone": 79990000001}"#),
+                    (3, "2024-03-06 10:00:00", r#"{"Id": 3}"#),
+                    (4, "2024-04-01 10:00:00", r#"{"Id": 4, "phone": "79990000002"}"#),
+                ];
+                for (id, date, data) in rows {
+                    conn.execute(
+                        "INSERT INTO generations (id, record_date, updated_at, data) VALUES (?1, ?2, ?2, ?3)",
+                        params![id.to_string(), date, data],
+                    )?;
+                }
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let period = DateRange {
+            start: Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2024, 3, 31, 23, 59, 59).unwrap(),
+            label: "March".to_string(),
+        };
+        let history = VisitHistory::new(Default::default());
+        let stats = mirror.load_visit_history(history, period.end, None).await.unwrap().finish(&period);
+        assert_eq!((stats.clients_in_period, stats.returning_clients), (1, 1));
+        assert_eq!(stats.avg_days_between_visits, Some(55.0));
+
+        // Only the first visit has a known club, so the March visit is left out
+        mirror
+            .with_conn(|conn| {
+                conn.execute(
+                    "UPDATE generations SET data = json_set(data, '$.club_id', ' c1 ') WHERE id = '1'",
+                    [],
+                )?;
+                conn.execute("UPDATE generations SET data = json_set(data, '$.club_id', 'gone') WHERE id = '2'", [])?;
+                Ok(())
+            })
+            .await
+            .unwrap();
+        let known_clubs = Some(HashSet::from(["c1".to_string()]));
+        let history = VisitHistory::new(Default::default());
+        let stats = mirror.load_visit_history(history, period.end, known_clubs).await.unwrap().finish(&period);
+        assert_eq!(stats.clients_in_period, 0);
+        assert_eq!(stats.avg_days_between_visits, None);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,128 @@
+use chrono::{DateTime, NaiveDate};
+use chrono_tz::{Europe::Moscow, Tz};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::aura::AuraBuckets;
+use crate::club_directory::ClubMap;
+use crate::date_utils::parse_record_datetime;
+use crate::demographics::parse_birth_date;
+use crate::export_profile::CsvColumn;
+use crate::phone::PhoneNormalizer;
+use crate::privacy::Anonymizer;
+use crate::stats::{extract_percent_value, StatsSettings};
+
+/// A record in the typed export model shared by the NDJSON and Parquet writers.
+///
+/// Fields are named after the export column keys; personal fields dropped by the privacy mode
+/// are `None`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NormalizedRecord {
+    pub id: Option<i64>,
+    /// E.164, masked or hashed depending on the privacy mode
+    pub phone: Option<String>,
+    pub name: Option<String>,
+    pub date_visit: Option<DateTime<Tz>>,
+    pub duration: Option<f64>,
+    pub club_id: Option<String>,
+    /// Club name from the club directory; `None` for unknown clubs
+    pub club: Option<String>,
+    pub city: Option<String>,
+    /// Aura percentage
+    pub aura: Option<f64>,
+    /// Aura bucket label
+    pub aura_level: Option<String>,
+    pub birth_date: Option<NaiveDate>,
+    pub sex: Option<String>,
+    pub status: Option<String>,
+    pub started_at: Option<DateTime<Tz>>,
+    pub finished_at: Option<DateTime<Tz>>,
+    pub generation_secs: Option<f64>,
+}
+
+/// Builds [`NormalizedRecord`]s from raw records: timestamps in Moscow time, numbers parsed,
+/// personal fields following the anonymizer
+#[derive(Debug, Clone)]
+pub struct RecordNormalizer {
+    anonymizer: Anonymizer,
+    phones: PhoneNormalizer,
+    aura_buckets: AuraBuckets,
+    generation_start_field: String,
+    generation_end_field: String,
+}
+
+impl RecordNormalizer {
+    pub fn new(settings: &StatsSettings, anonymizer: &Anonymizer) -> Self {
+        Self {
+            anonymizer: anonymizer.clone(),
+            phones: settings.phones.clone(),
+            aura_buckets: settings.aura_buckets.clone(),
+            generation_start_field: settings.generation_start_field.clone(),
+            generation_end_field: settings.generation_end_field.clone(),
+        }
+    }
+
+    /// Export columns left after the privacy mode, in model order
+    pub fn columns(&self) -> Vec<CsvColumn> {
+        CsvColumn::ALL
+            .into_iter()
+            .filter(|column| !self.anonymizer.drops(*column))
+            .collect()
+    }
+
+    pub fn normalize(&self, obj: &Map<String, Value>, clubs: &ClubMap) -> NormalizedRecord {
+        let text = |field: &str| {
+            obj.get(field)
+                .and_then(|v| v.as_str())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        let timestamp = |field: &str| {
+            obj.get(field)
+                .and_then(|v| v.as_str())
+                .and_then(parse_record_datetime)
+                .map(|date| date.with_timezone(&Moscow))
+        };
+        let club_id = text("club_id");
+        let club = club_id.as_deref().and_then(|club_id| clubs.get(club_id));
+        let aura = extract_percent_value(obj);
+        let started_at = timestamp(&self.generation_start_field);
+        let finished_at = timestamp(&self.generation_end_field);
+        let keeps = |column: CsvColumn| !self.anonymizer.drops(column);
+
+        NormalizedRecord {
+            id: match obj.get("Id") {
+                Some(Value::Number(n)) => n.as_i64(),
+                Some(Value::String(s)) => s.trim().parse().ok(),
+                _ => None,
+            },
+            phone: obj.get("phone").and_then(|v| self.anonymizer.phone(&self.phones, v)),
+            name: text("name").filter(|_| keeps(CsvColumn::Name)),
+            date_visit: timestamp("date_visit"),
+            duration: match obj.get("duration") {
+                Some(Value::Number(n)) => n.as_f64(),
+                Some(Value::String(s)) => s.trim().parse().ok(),
+                _ => None,
+            },
+            club: club.map(|club| club.name.clone()),
+            city: club.and_then(|club| club.city.clone()),
+            club_id,
+            aura,
+            aura_level: aura.map(|percent| self.aura_buckets.bucket_of(percent).label.clone()),
+            birth_date: obj
+                .get("birth_date")
+                .and_then(|v| v.as_str())
+                .and_then(parse_birth_date)
+                .filter(|_| keeps(CsvColumn::BirthDate)),
+            sex: text("sex"),
+            status: text("status"),
+            generation_secs: match (started_at, finished_at) {
+                (Some(start), Some(end)) => Some(end.signed_duration_since(start).num_milliseconds() as f64 / 1000.0),
+                _ => None,
+            },
+            started_at,
+            finished_at,
+        }
+    }
+}
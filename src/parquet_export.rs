@@ -0,0 +1,238 @@
+use anyhow::Result;
+use arrow_array::types::{Date32Type, Int32Type};
+use arrow_array::{
+    ArrayRef, Date32Array, DictionaryArray, Float64Array, Int64Array, RecordBatch, StringArray,
+    TimestampMicrosecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::DateTime;
+use chrono_tz::Tz;
+use log::info;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde_json::Value;
+use std::fs::File;
+use std::sync::Arc;
+
+use crate::club_directory::ClubMap;
+use crate::export_profile::CsvColumn;
+use crate::normalized::{NormalizedRecord, RecordNormalizer};
+use crate::privacy::Anonymizer;
+use crate::report_files::{persist, temp_path};
+use crate::stats::StatsSettings;
+
+/// Records per row group batch; a year of records is written in a few dozen batches
+const BATCH_SIZE: usize = 8192;
+
+/// Time zone attached to the timestamp columns
+const TIMEZONE: &str = "Europe/Moscow";
+
+/// A column of the Parquet file: the club id or one of the export columns
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ParquetColumn {
+    ClubId,
+    Export(CsvColumn),
+}
+
+impl ParquetColumn {
+    fn field(self) -> Field {
+        let category = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+        let timestamp = DataType::Timestamp(TimeUnit::Microsecond, Some(TIMEZONE.into()));
+        let (name, data_type) = match self {
+            ParquetColumn::ClubId => ("club_id", DataType::Utf8),
+            ParquetColumn::Export(column) => (
+                column.key(),
+                match column {
+                    CsvColumn::Id => DataType::Int64,
+                    CsvColumn::Phone | CsvColumn::Name => DataType::Utf8,
+                    CsvColumn::DateVisit | CsvColumn::StartedAt | CsvColumn::FinishedAt => timestamp,
+                    CsvColumn::Duration | CsvColumn::Aura | CsvColumn::GenerationSecs => DataType::Float64,
+                    CsvColumn::Club | CsvColumn::City | CsvColumn::AuraLevel | CsvColumn::Sex | CsvColumn::Status => {
+                        category
+                    }
+                    CsvColumn::BirthDate => DataType::Date32,
+                },
+            ),
+        };
+        Field::new(name, data_type, true)
+    }
+
+    fn array(self, records: &[NormalizedRecord]) -> ArrayRef {
+        let text = |value: fn(&NormalizedRecord) -> &Option<String>| -> ArrayRef {
+            Arc::new(records.iter().map(|r| value(r).as_deref()).collect::<StringArray>())
+        };
+        let category = |value: fn(&NormalizedRecord) -> &Option<String>| -> ArrayRef {
+            Arc::new(records.iter().map(|r| value(r).as_deref()).collect::<DictionaryArray<Int32Type>>())
+        };
+        let number = |value: fn(&NormalizedRecord) -> Option<f64>| -> ArrayRef {
+            Arc::new(records.iter().map(value).collect::<Float64Array>())
+        };
+        let timestamp = |value: fn(&NormalizedRecord) -> Option<DateTime<Tz>>| -> ArrayRef {
+            Arc::new(
+                records
+                    .iter()
+                    .map(|r| value(r).map(|date| date.timestamp_micros()))
+                    .collect::<TimestampMicrosecondArray>()
+                    .with_timezone(TIMEZONE),
+            )
+        };
+
+        match self {
+            ParquetColumn::ClubId => text(|r| &r.club_id),
+            ParquetColumn::Export(column) => match column {
+                CsvColumn::Id => Arc::new(records.iter().map(|r| r.id).collect::<Int64Array>()),
+                CsvColumn::Phone => text(|r| &r.phone),
+                CsvColumn::Name => text(|r| &r.name),
+                CsvColumn::DateVisit => timestamp(|r| r.date_visit),
+                CsvColumn::Duration => number(|r| r.duration),
+                CsvColumn::Club => category(|r| &r.club),
+                CsvColumn::City => category(|r| &r.city),
+                CsvColumn::Aura => number(|r| r.aura),
+                CsvColumn::AuraLevel => category(|r| &r.aura_level),
+                CsvColumn::BirthDate => Arc::new(
+                    records
+                        .iter()
+                        .map(|r| r.birth_date.map(Date32Type::from_naive_date))
+                        .collect::<Date32Array>(),
+                ),
+                CsvColumn::Sex => category(|r| &r.sex),
+                CsvColumn::Status => category(|r| &r.status),
+                CsvColumn::StartedAt => timestamp(|r| r.started_at),
+                CsvColumn::FinishedAt => timestamp(|r| r.finished_at),
+                CsvColumn::GenerationSecs => number(|r| r.generation_secs),
+            },
+        }
+    }
+}
+
+/// Incremental writer of normalized records to a Snappy-compressed Parquet file.
+///
+/// Timestamps are stored with the Moscow time zone, aura and durations as doubles and
+/// low-cardinality text (club, city, aura level, sex, status) as dictionary columns.
+/// Columns dropped by the privacy mode are left out of the schema.
+pub struct ParquetRecordWriter {
+    writer: ArrowWriter<File>,
+    output_path: String,
+    normalizer: RecordNormalizer,
+    columns: Vec<ParquetColumn>,
+    schema: SchemaRef,
+    pending: Vec<NormalizedRecord>,
+    rows: usize,
+}
+
+impl ParquetRecordWriter {
+    pub fn create(output_path: &str, settings: &StatsSettings, anonymizer: &Anonymizer) -> Result<Self> {
+        info!("Generating Parquet records to: {} (privacy: {})", output_path, anonymizer.mode().name());
+        let normalizer = RecordNormalizer::new(settings, anonymizer);
+        let mut columns = Vec::new();
+        for column in normalizer.columns() {
+            if column == CsvColumn::Club {
+                columns.push(ParquetColumn::ClubId);
+            }
+            columns.push(ParquetColumn::Export(column));
+        }
+        let schema: SchemaRef = Arc::new(Schema::new(
+            columns.iter().map(|column| column.field()).collect::<Vec<_>>(),
+        ));
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+
+        Ok(Self {
+            writer: ArrowWriter::try_new(File::create(temp_path(output_path))?, schema.clone(), Some(properties))?,
+            output_path: output_path.to_string(),
+            normalizer,
+            columns,
+            schema,
+            pending: Vec::with_capacity(BATCH_SIZE),
+            rows: 0,
+        })
+    }
+
+    /// Append a single record
+    pub fn write_record(&mut self, record: &Value, clubs: &ClubMap) -> Result<()> {
+        if let Some(obj) = record.as_object() {
+            self.pending.push(self.normalizer.normalize(obj, clubs));
+            if self.pending.len() >= BATCH_SIZE {
+                self.flush_batch()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the remaining records and the file footer, move the file into place and return its path
+    pub fn finish(mut self) -> Result<String> {
+        self.flush_batch()?;
+        self.writer.close()?;
+        info!("Parquet records generated successfully with {} records", self.rows);
+        persist(&self.output_path)
+    }
+
+    fn flush_batch(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let arrays: Vec<ArrayRef> = self.columns.iter().map(|column| column.array(&self.pending)).collect();
+        self.writer.write(&RecordBatch::try_new(self.schema.clone(), arrays)?)?;
+        self.rows += self.pending.len();
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::club_directory::test_clubs;
+    use crate::privacy::PrivacyMode;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float64Type, TimestampMicrosecondType};
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use serde_json::json;
+
+    #[test]
+    fn test_parquet_round_trip() {
+        let clubs = test_clubs(&[("c1", "Club 1")]);
+
+        let output_path = "test_records.parquet";
+        let anonymizer = Anonymizer::new(PrivacyMode::NoPii, &[]);
+        let mut writer = ParquetRecordWriter::create(output_path, &StatsSettings::default(), &anonymizer).unwrap();
+        writer
+            .write_record(
+                &json!({
+                    "Id": 7, "club_id": "c1", "phone": "89991234567", "name": "Анна", "status": "done",
+                    "date_visit": "2024-01-01 10:00:00+00:00", "text_aura": {"percent": "75%"}, "birth_date": "17.05.1990"
+                }),
+                &clubs,
+            )
+            .unwrap();
+        writer.write_record(&json!({"Id": 8, "club_id": "zz"}), &clubs).unwrap();
+        writer.finish().unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(output_path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        std::fs::remove_file(output_path).unwrap();
+
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 2);
+        let schema = batch.schema();
+        assert!(schema.field_with_name("phone").is_err() && schema.field_with_name("birth_date").is_err());
+        assert_eq!(
+            schema.field_with_name("date_visit").unwrap().data_type(),
+            &DataType::Timestamp(TimeUnit::Microsecond, Some(TIMEZONE.into()))
+        );
+        assert!(matches!(schema.field_with_name("club").unwrap().data_type(), DataType::Dictionary(_, _)));
+
+        let date_visit = batch.column_by_name("date_visit").unwrap().as_primitive::<TimestampMicrosecondType>();
+        assert_eq!(date_visit.value(0), 1_704_103_200_000_000);
+        let aura = batch.column_by_name("aura").unwrap().as_primitive::<Float64Type>();
+        assert_eq!(aura.value(0), 75.0);
+        assert!(aura.is_null(1));
+        assert_eq!(batch.column_by_name("club_id").unwrap().as_string::<i32>().value(1), "zz");
+    }
+}
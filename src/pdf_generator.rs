@@ -7,11 +7,57 @@ use std::collections::HashMap;
 use chrono::{DateTime, Timelike};
 use log::info;
 
+use crate::aura::AuraBuckets;
+use crate::demographics::{DemographicGroup, DemographicsStats};
+use crate::report_files::{persist, temp_path};
+use crate::retention::RetentionStats;
+use crate::stats::{ClubStats, DataQualityStats, ReportStats, StatusBreakdown};
+
 pub struct PdfGenerator;
 
+/// Chart aggregates collected incrementally while records are streamed
+#[derive(Debug)]
+pub struct ChartData {
+    /// Field with the record date, as configured by `DATE_FIELD_NAME`
+    date_field_name: String,
+    hourly_counts: HashMap<u32, u32>,
+}
+
+impl ChartData {
+    pub fn new(date_field_name: &str) -> Self {
+        Self {
+            date_field_name: date_field_name.to_string(),
+            hourly_counts: HashMap::new(),
+        }
+    }
+
+    /// Add a single record to the chart aggregates
+    pub fn add_record(&mut self, record: &Value) {
+        if let Some(obj) = record.as_object() {
+            if let Some(created_at) = obj.get(&self.date_field_name).and_then(|v| v.as_str()) {
+                if let Ok(dt) = DateTime::parse_from_str(created_at, "%Y-%m-%d %H:%M:%S%z") {
+                    let hour = dt.hour();
+                    *self.hourly_counts.entry(hour).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    /// Records per hour of the day, indexed by hour
+    pub fn hourly_totals(&self) -> [u32; 24] {
+        let mut totals = [0; 24];
+        for (hour, count) in &self.hourly_counts {
+            if let Some(total) = totals.get_mut(*hour as usize) {
+                *total = *count;
+            }
+        }
+        totals
+    }
+}
+
 impl PdfGenerator {
     /// Generate PDF report with vector charts (TradingView style)
-    pub fn generate(data: &[Value], output_path: &str) -> Result<String> {
+    pub fn generate(chart_data: &ChartData, stats: &ReportStats, output_path: &str) -> Result<String> {
         info!("Generating PDF report with vector charts to: {}", output_path);
 
         // Create PDF document
@@ -38,80 +84,754 @@ impl PdfGenerator {
         );
 
         // Draw hourly distribution chart
-        Self::draw_hourly_chart(&current_layer, data, &font_bold, &font_regular)?;
+        Self::draw_hourly_chart(&current_layer, &chart_data.hourly_counts, &font_bold, &font_regular)?;
+
+        // Records that could not be attributed to a club
+        Self::draw_data_quality_section(&current_layer, &stats.data_quality, &font_bold, &font_regular);
+
+        // Full club KPI table, continued on further pages when needed
+        Self::draw_clubs_section(&doc, &stats.club_stats, &font_bold, &font_regular);
+
+        // Aura score mean, median and spread with daily trend
+        let aura_layer = Self::add_page(&doc, "Аура: среднее и разброс", &font_bold);
+        Self::draw_aura_section(&aura_layer, stats, &font_bold, &font_regular);
+
+        // Status breakdown on its own page
+        let status_layer = Self::add_page(&doc, "Статусы генераций", &font_bold);
+        Self::draw_status_section(&status_layer, &stats.status_breakdown, &font_bold, &font_regular);
+
+        // Generation time distribution
+        let generation_layer = Self::add_page(&doc, "Время генерации", &font_bold);
+        Self::draw_generation_time_section(&generation_layer, stats, &font_bold, &font_regular);
+
+        // Age and gender split with aura levels
+        let demographics_layer = Self::add_page(&doc, "Демография", &font_bold);
+        Self::draw_demographics_section(
+            &demographics_layer,
+            &stats.demographics,
+            &stats.aura_buckets,
+            &font_bold,
+            &font_regular,
+        );
+
+        // Repeat visitors and cohort retention
+        if let Some(retention) = &stats.retention {
+            let retention_layer = Self::add_page(&doc, "Возвраты и удержание", &font_bold);
+            Self::draw_retention_section(&retention_layer, retention, &font_bold, &font_regular);
+        } else if let Some(reason) = &stats.retention_unavailable {
+            let retention_layer = Self::add_page(&doc, "Возвраты и удержание", &font_bold);
+            retention_layer.use_text(
+                format!("Аналитика возвратов не построена: {}", reason),
+                10.0,
+                Mm(10.0),
+                Mm(265.0),
+                &font_regular,
+            );
+        }
 
         // Save PDF
-        doc.save(&mut BufWriter::new(File::create(output_path)?))?;
+        doc.save(&mut BufWriter::new(File::create(temp_path(output_path))?))?;
         info!("PDF report with vector charts generated successfully");
-        Ok(output_path.to_string())
+        persist(output_path)
     }
 
-    /// Calculate statistics from data
-    fn calculate_statistics(data: &[Value]) -> AuraStatistics {
-        let mut low = 0;
-        let mut normal = 0;
-        let mut high = 0;
-
-        for record in data {
-            if let Some(obj) = record.as_object() {
-                if let Some(percent) = Self::extract_percent(obj) {
-                    if percent < 60.0 {
-                        low += 1;
-                    } else if percent <= 80.0 {
-                        normal += 1;
-                    } else {
-                        high += 1;
-                    }
-                }
+    /// Add an A4 page with a section title at the top
+    fn add_page(doc: &PdfDocumentReference, title: &str, font_bold: &IndirectFontRef) -> PdfLayerReference {
+        let (page, layer) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+        let layer = doc.get_page(page).get_layer(layer);
+        layer.use_text(title, 18.0, Mm(10.0), Mm(280.0), font_bold);
+        layer
+    }
+
+    /// Draw every status as a horizontal bar with its share of all records
+    fn draw_status_section(
+        layer: &PdfLayerReference,
+        breakdown: &StatusBreakdown,
+        font_bold: &IndirectFontRef,
+        font_regular: &IndirectFontRef,
+    ) {
+        let x = 10.0_f64;
+        let label_width = 45.0_f64;
+        let bar_max_width = 110.0_f64;
+        let bar_height = 5.0_f64;
+        let bottom = 60.0_f64;
+        let mut y = 265.0_f64;
+
+        layer.use_text(
+            "Доля каждого статуса от общего числа генераций за период.",
+            9.0,
+            Mm(x as f32),
+            Mm(y as f32),
+            font_regular,
+        );
+        y -= 10.0;
+
+        if breakdown.statuses.is_empty() {
+            layer.use_text("Нет данных за период.", 9.0, Mm(x as f32), Mm(y as f32), font_regular);
+            return;
+        }
+
+        for status in &breakdown.statuses {
+            if y < bottom {
+                break;
             }
+
+            let color = match status.status.as_str() {
+                "done" => Color::Rgb(Rgb::new(0.149, 0.651, 0.604, None)), // #26A69A (teal)
+                "process" => Color::Rgb(Rgb::new(1.0, 0.655, 0.149, None)), // #FFA726 (amber)
+                _ => Color::Rgb(Rgb::new(0.937, 0.325, 0.314, None)),     // #EF5350 (red)
+            };
+
+            layer.use_text(status.label(), 9.0, Mm(x as f32), Mm((y + 1.0) as f32), font_regular);
+
+            let bar_width = (status.percentage / 100.0 * bar_max_width).max(0.5);
+            let bar_x = x + label_width;
+            Self::draw_rect(layer, bar_x, y, bar_width, bar_height, color);
+
+            layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+            layer.use_text(
+                format!("{} ({:.1}%)", status.count, status.percentage),
+                9.0,
+                Mm((bar_x + bar_width + 3.0) as f32),
+                Mm((y + 1.0) as f32),
+                font_regular,
+            );
+            y -= 9.0;
         }
 
-        AuraStatistics {
-            total: data.len(),
-            low_aura: low,
-            normal_aura: normal,
-            high_aura: high,
+        y -= 5.0;
+        layer.use_text(
+            format!(
+                "Ошибки и прочие статусы: {} ({:.1}%)",
+                breakdown.failed_count, breakdown.failed_percentage
+            ),
+            10.0,
+            Mm(x as f32),
+            Mm(y as f32),
+            if breakdown.high_failure_rate { font_bold } else { font_regular },
+        );
+        y -= 6.0;
+        layer.use_text(
+            format!(
+                "Зависли в process дольше {} мин: {} ({:.1}%)",
+                breakdown.stuck_after_minutes, breakdown.stuck_count, breakdown.stuck_percentage
+            ),
+            10.0,
+            Mm(x as f32),
+            Mm(y as f32),
+            if breakdown.high_stuck_rate { font_bold } else { font_regular },
+        );
+        if !breakdown.stuck_sample_ids.is_empty() {
+            y -= 5.0;
+            layer.use_text(
+                format!("Id: {}", breakdown.stuck_sample_ids.join(", ")),
+                8.0,
+                Mm((x + 5.0) as f32),
+                Mm(y as f32),
+                font_regular,
+            );
+        }
+
+        if breakdown.high_failure_rate || breakdown.high_stuck_rate {
+            y -= 8.0;
+            layer.use_text(
+                "Внимание: доля ошибок или зависших генераций превышает порог.",
+                10.0,
+                Mm(x as f32),
+                Mm(y as f32),
+                font_bold,
+            );
         }
     }
 
-    /// Extract percent value from record
-    fn extract_percent(record: &serde_json::Map<String, Value>) -> Option<f64> {
-        if let Some(text_aura) = record.get("text_aura") {
-            if let Some(aura_obj) = text_aura.as_object() {
-                if let Some(percent) = aura_obj.get("percent") {
-                    if let Some(percent_str) = percent.as_str() {
-                        let cleaned = percent_str.trim().trim_end_matches('%');
-                        return cleaned.parse::<f64>().ok();
-                    } else if let Some(percent_num) = percent.as_f64() {
-                        return Some(percent_num);
-                    }
-                }
+    /// Draw generation time percentiles, their histogram and a per-club table
+    fn draw_generation_time_section(
+        layer: &PdfLayerReference,
+        stats: &ReportStats,
+        font_bold: &IndirectFontRef,
+        font_regular: &IndirectFontRef,
+    ) {
+        let x = 10.0_f64;
+        let mut y = 265.0_f64;
+
+        let Some(time) = &stats.generation_time else {
+            layer.use_text("Нет завершенных генераций за период.", 9.0, Mm(x as f32), Mm(y as f32), font_regular);
+            return;
+        };
+
+        let summary = [
+            format!("Генераций (done): {}, исключено выбросов: {}", time.count, time.excluded_outliers),
+            format!("Медиана: {:.1} сек, среднее: {:.1} сек", time.median, time.mean),
+            format!("p90: {:.1} сек, p95: {:.1} сек, p99: {:.1} сек", time.p90, time.p95, time.p99),
+            format!("Мин: {:.1} сек, макс: {:.1} сек", time.min, time.max),
+        ];
+        for line in &summary {
+            layer.use_text(line.as_str(), 10.0, Mm(x as f32), Mm(y as f32), font_regular);
+            y -= 6.0;
+        }
+
+        // Histogram
+        let chart_x = 20.0_f64;
+        let chart_width = 170.0_f64;
+        let chart_height = 50.0_f64;
+        let chart_y = y - chart_height - 10.0;
+        let max_count = time.histogram.iter().max().copied().unwrap_or(1).max(1);
+
+        layer.use_text("Распределение времени генерации", 12.0, Mm(x as f32), Mm((y - 2.0) as f32), font_bold);
+
+        layer.set_outline_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+        layer.set_outline_thickness(1.0);
+        layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(chart_x as f32), Mm(chart_y as f32)), false),
+                (Point::new(Mm((chart_x + chart_width) as f32), Mm(chart_y as f32)), false),
+            ],
+            is_closed: false,
+        });
+
+        let bar_color = Color::Rgb(Rgb::new(0.149, 0.651, 0.604, None)); // #26A69A (teal)
+        layer.set_fill_color(bar_color.clone());
+        layer.set_outline_color(bar_color);
+        layer.set_outline_thickness(0.5);
+
+        let slot_width = chart_width / time.histogram.len() as f64;
+        for (i, &count) in time.histogram.iter().enumerate() {
+            let bar_x = chart_x + i as f64 * slot_width + slot_width * 0.075;
+            if count > 0 {
+                let bar_height = count as f64 / max_count as f64 * chart_height;
+                layer.add_polygon(Polygon {
+                    rings: vec![vec![
+                        (Point::new(Mm(bar_x as f32), Mm(chart_y as f32)), false),
+                        (Point::new(Mm((bar_x + slot_width * 0.85) as f32), Mm(chart_y as f32)), false),
+                        (Point::new(Mm((bar_x + slot_width * 0.85) as f32), Mm((chart_y + bar_height) as f32)), false),
+                        (Point::new(Mm(bar_x as f32), Mm((chart_y + bar_height) as f32)), false),
+                    ]],
+                    mode: printpdf::path::PaintMode::FillStroke,
+                    winding_order: printpdf::path::WindingOrder::NonZero,
+                });
             }
+            // Label every other bin with its lower bound
+            if i % 2 == 0 {
+                layer.use_text(
+                    format!("{:.0}", i as f64 * time.bin_width_secs),
+                    6.0,
+                    Mm(bar_x as f32),
+                    Mm((chart_y - 3.0) as f32),
+                    font_regular,
+                );
+            }
+        }
+        layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+        layer.use_text(
+            format!("{}", max_count),
+            7.0,
+            Mm((chart_x - 8.0) as f32),
+            Mm((chart_y + chart_height - 1.0) as f32),
+            font_regular,
+        );
+        layer.use_text("сек", 7.0, Mm((chart_x + chart_width + 2.0) as f32), Mm((chart_y - 3.0) as f32), font_regular);
+
+        // Per-club table
+        y = chart_y - 15.0;
+        layer.use_text("По комплексам", 12.0, Mm(x as f32), Mm(y as f32), font_bold);
+        y -= 7.0;
+
+        let columns = [x, x + 80.0, x + 100.0, x + 125.0, x + 150.0, x + 175.0];
+        for (column, header) in columns.iter().zip(["Комплекс", "n", "Медиана", "p90", "p95", "Макс"]) {
+            layer.use_text(header, 9.0, Mm(*column as f32), Mm(y as f32), font_bold);
+        }
+        y -= 5.0;
+
+        for club in &stats.club_stats {
+            if y < 15.0 {
+                break;
+            }
+            let Some(time) = &club.generation_time else {
+                continue;
+            };
+            let values = [
+                club.club_name.clone(),
+                time.count.to_string(),
+                format!("{:.1}", time.median),
+                format!("{:.1}", time.p90),
+                format!("{:.1}", time.p95),
+                format!("{:.1}", time.max),
+            ];
+            for (column, value) in columns.iter().zip(values) {
+                layer.use_text(value, 8.0, Mm(*column as f32), Mm(y as f32), font_regular);
+            }
+            y -= 4.5;
         }
-        None
     }
 
-    /// Draw hourly distribution chart using vector graphics (TradingView style)
-    fn draw_hourly_chart(
+    /// Draw the club leaderboard with all KPIs, adding pages while rows remain
+    fn draw_clubs_section(
+        doc: &PdfDocumentReference,
+        clubs: &[ClubStats],
+        font_bold: &IndirectFontRef,
+        font_regular: &IndirectFontRef,
+    ) {
+        let x = 10.0_f64;
+        let columns = [
+            x, x + 8.0, x + 18.0, x + 70.0, x + 97.0, x + 112.0, x + 125.0, x + 138.0, x + 151.0, x + 164.0, x + 178.0,
+        ];
+        let headers = [
+            "№", "+/-", "Комплекс", "Город", "Ген.", "Клиент.", "На кл.", "Done %", "Аура %", "Уровни", "Время, с",
+        ];
+
+        let mut layer = Self::add_page(doc, "Комплексы", font_bold);
+        let mut y = 265.0_f64;
+        if clubs.is_empty() {
+            layer.use_text("Нет данных за период", 10.0, Mm(x as f32), Mm(y as f32), font_regular);
+            return;
+        }
+        layer.use_text(
+            "Место по числу генераций; +/- — изменение места к предыдущему периоду.",
+            9.0,
+            Mm(x as f32),
+            Mm(y as f32),
+            font_regular,
+        );
+        y -= 8.0;
+
+        let draw_header = |layer: &PdfLayerReference, y: f64| {
+            for (column, header) in columns.iter().zip(headers) {
+                layer.use_text(header, 8.0, Mm(*column as f32), Mm(y as f32), font_bold);
+            }
+        };
+        draw_header(&layer, y);
+        y -= 5.0;
+
+        for club in clubs {
+            if y < 15.0 {
+                layer = Self::add_page(doc, "Комплексы (продолжение)", font_bold);
+                y = 265.0;
+                draw_header(&layer, y);
+                y -= 5.0;
+            }
+            let rank_change = match club.rank_change {
+                Some(change) if change > 0 => format!("+{}", change),
+                Some(change) => change.to_string(),
+                None => String::new(),
+            };
+            let values = [
+                club.rank.to_string(),
+                rank_change,
+                truncate_label(&club.club_name, 30),
+                truncate_label(club.city.as_deref().unwrap_or(""), 16),
+                club.total_generations.to_string(),
+                club.unique_clients.to_string(),
+                format!("{:.2}", club.generations_per_client),
+                format!("{:.1}", club.done_rate),
+                club.aura.as_ref().map(|aura| format!("{:.1}", aura.mean)).unwrap_or_else(|| "-".to_string()),
+                club.aura_levels.iter().map(|count| count.to_string()).collect::<Vec<_>>().join("/"),
+                club.generation_time
+                    .as_ref()
+                    .map(|time| format!("{:.1}", time.mean))
+                    .unwrap_or_else(|| "-".to_string()),
+            ];
+            for (column, value) in columns.iter().zip(values) {
+                layer.use_text(value, 7.5, Mm(*column as f32), Mm(y as f32), font_regular);
+            }
+            y -= 4.5;
+        }
+    }
+
+    /// Draw overall aura summary, daily mean/median trend and per-club summary
+    fn draw_aura_section(
         layer: &PdfLayerReference,
-        data: &[Value],
+        stats: &ReportStats,
         font_bold: &IndirectFontRef,
         font_regular: &IndirectFontRef,
-    ) -> Result<()> {
-        // Count records by hour
-        let mut hourly_counts: HashMap<u32, u32> = HashMap::new();
-        
-        for record in data {
-            if let Some(obj) = record.as_object() {
-                if let Some(created_at) = obj.get("CreatedAt1").and_then(|v| v.as_str()) {
-                    if let Ok(dt) = DateTime::parse_from_str(created_at, "%Y-%m-%d %H:%M:%S%z") {
-                        let hour = dt.hour();
-                        *hourly_counts.entry(hour).or_insert(0) += 1;
-                    }
+    ) {
+        let x = 10.0_f64;
+        let mut y = 265.0_f64;
+
+        let Some(aura) = &stats.aura else {
+            layer.use_text("Нет данных об ауре за период", 10.0, Mm(x as f32), Mm(y as f32), font_regular);
+            return;
+        };
+        layer.use_text(
+            format!(
+                "Среднее: {:.1}%   Медиана: {:.1}%   Стандартное отклонение: {:.1}   (n = {})",
+                aura.mean, aura.median, aura.std_dev, aura.count
+            ),
+            10.0,
+            Mm(x as f32),
+            Mm(y as f32),
+            font_regular,
+        );
+        y -= 12.0;
+
+        // Daily trend: mean and median lines over a ±σ band around the mean
+        layer.use_text("Динамика по дням", 12.0, Mm(x as f32), Mm(y as f32), font_bold);
+        y -= 5.0;
+        let days = &stats.daily_aura;
+        let chart_x = x + 10.0;
+        let chart_width = 175.0_f64;
+        let chart_height = 60.0_f64;
+        let chart_y = y - chart_height;
+
+        if days.len() < 2 {
+            layer.use_text(
+                "Недостаточно дней для графика динамики",
+                9.0,
+                Mm(x as f32),
+                Mm((y - 5.0) as f32),
+                font_regular,
+            );
+        } else {
+            let point_x = |i: usize| chart_x + chart_width * i as f64 / (days.len() - 1) as f64;
+            let point_y = |percent: f64| chart_y + chart_height * percent.clamp(0.0, 100.0) / 100.0;
+
+            // Axes and grid every 20%
+            layer.set_outline_thickness(0.3);
+            for step in 0..=5 {
+                let percent = step as f64 * 20.0;
+                let grid_y = point_y(percent);
+                let gray = if step == 0 { 0.0 } else { 0.9 };
+                layer.set_outline_color(Color::Rgb(Rgb::new(gray, gray, gray, None)));
+                layer.add_line(Line {
+                    points: vec![
+                        (Point::new(Mm(chart_x as f32), Mm(grid_y as f32)), false),
+                        (Point::new(Mm((chart_x + chart_width) as f32), Mm(grid_y as f32)), false),
+                    ],
+                    is_closed: false,
+                });
+                layer.use_text(format!("{:.0}", percent), 7.0, Mm(x as f32), Mm((grid_y - 1.0) as f32), font_regular);
+            }
+
+            // ±σ band
+            let band_color = Color::Rgb(Rgb::new(0.8, 0.92, 0.91, None));
+            let mut band: Vec<(Point, bool)> = days
+                .iter()
+                .enumerate()
+                .map(|(i, day)| {
+                    let upper = point_y(day.summary.mean + day.summary.std_dev);
+                    (Point::new(Mm(point_x(i) as f32), Mm(upper as f32)), false)
+                })
+                .collect();
+            band.extend(days.iter().enumerate().rev().map(|(i, day)| {
+                let lower = point_y(day.summary.mean - day.summary.std_dev);
+                (Point::new(Mm(point_x(i) as f32), Mm(lower as f32)), false)
+            }));
+            layer.set_fill_color(band_color.clone());
+            layer.set_outline_color(band_color);
+            layer.add_polygon(Polygon {
+                rings: vec![band],
+                mode: printpdf::path::PaintMode::Fill,
+                winding_order: printpdf::path::WindingOrder::NonZero,
+            });
+
+            // Mean (teal) and median (amber) lines
+            layer.set_outline_thickness(1.2);
+            let means: Vec<f64> = days.iter().map(|day| day.summary.mean).collect();
+            let medians: Vec<f64> = days.iter().map(|day| day.summary.median).collect();
+            for (values, color) in [(means, teal()), (medians, amber())] {
+                layer.set_outline_color(color);
+                layer.add_line(Line {
+                    points: values
+                        .iter()
+                        .enumerate()
+                        .map(|(i, value)| (Point::new(Mm(point_x(i) as f32), Mm(point_y(*value) as f32)), false))
+                        .collect(),
+                    is_closed: false,
+                });
+            }
+
+            // Date labels, at most about ten
+            layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+            let label_step = days.len().div_ceil(10);
+            for (i, day) in days.iter().enumerate().step_by(label_step) {
+                layer.use_text(
+                    day.date.format("%d.%m").to_string(),
+                    6.5,
+                    Mm((point_x(i) - 3.0) as f32),
+                    Mm((chart_y - 4.0) as f32),
+                    font_regular,
+                );
+            }
+
+            // Legend
+            let legend_y = chart_y - 11.0;
+            let legend = [
+                ("Среднее", teal()),
+                ("Медиана", amber()),
+                ("Среднее ± σ", Color::Rgb(Rgb::new(0.8, 0.92, 0.91, None))),
+            ];
+            let mut legend_x = chart_x;
+            for (label, color) in legend {
+                Self::draw_rect(layer, legend_x, legend_y, 4.0, 3.0, color);
+                layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+                layer.use_text(label, 8.0, Mm((legend_x + 6.0) as f32), Mm(legend_y as f32), font_regular);
+                legend_x += 40.0;
+            }
+        }
+
+        // Per-club table
+        y = chart_y - 22.0;
+        layer.use_text("По комплексам", 12.0, Mm(x as f32), Mm(y as f32), font_bold);
+        y -= 7.0;
+        let columns = [x, x + 90.0, x + 115.0, x + 140.0, x + 165.0];
+        for (column, header) in columns.iter().zip(["Комплекс", "n", "Среднее", "Медиана", "σ"]) {
+            layer.use_text(header, 9.0, Mm(*column as f32), Mm(y as f32), font_bold);
+        }
+        y -= 5.0;
+        for club in &stats.club_stats {
+            if y < 15.0 {
+                break;
+            }
+            let Some(aura) = &club.aura else {
+                continue;
+            };
+            let values = [
+                truncate_label(&club.club_name, 45),
+                aura.count.to_string(),
+                format!("{:.1}", aura.mean),
+                format!("{:.1}", aura.median),
+                format!("{:.1}", aura.std_dev),
+            ];
+            for (column, value) in columns.iter().zip(values) {
+                layer.use_text(value, 8.0, Mm(*column as f32), Mm(y as f32), font_regular);
+            }
+            y -= 4.5;
+        }
+    }
+
+    /// Draw age and gender groups as bars split by aura bucket
+    fn draw_demographics_section(
+        layer: &PdfLayerReference,
+        demographics: &DemographicsStats,
+        buckets: &AuraBuckets,
+        font_bold: &IndirectFontRef,
+        font_regular: &IndirectFontRef,
+    ) {
+        let x = 10.0_f64;
+        let mut y = 265.0_f64;
+
+        layer.use_text(
+            "Количество генераций по группам; цвет показывает уровень ауры.",
+            9.0,
+            Mm(x as f32),
+            Mm(y as f32),
+            font_regular,
+        );
+        y -= 7.0;
+
+        // Legend, wrapped to the page width
+        let legend = buckets
+            .iter()
+            .enumerate()
+            .map(|(i, bucket)| (format!("{} ({})", bucket.label, buckets.range_label(i)), Self::bucket_color(bucket.color)))
+            .chain([("Нет данных".to_string(), Color::Rgb(Rgb::new(0.8, 0.8, 0.8, None)))]);
+        let mut legend_x = x;
+        for (label, color) in legend {
+            if legend_x + 45.0 > 200.0 {
+                legend_x = x;
+                y -= 5.0;
+            }
+            Self::draw_rect(layer, legend_x, y, 4.0, 3.0, color);
+            layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+            layer.use_text(label, 8.0, Mm((legend_x + 5.0) as f32), Mm(y as f32), font_regular);
+            legend_x += 45.0;
+        }
+        y -= 12.0;
+
+        layer.use_text("Возрастные группы", 12.0, Mm(x as f32), Mm(y as f32), font_bold);
+        y = Self::draw_group_bars(layer, &demographics.age_groups, buckets, y - 9.0, font_regular);
+
+        layer.use_text("Пол", 12.0, Mm(x as f32), Mm((y - 6.0) as f32), font_bold);
+        Self::draw_group_bars(layer, &demographics.genders, buckets, y - 15.0, font_regular);
+    }
+
+    /// Draw one stacked bar per group and return the y position below the last bar
+    fn draw_group_bars(
+        layer: &PdfLayerReference,
+        groups: &[DemographicGroup],
+        buckets: &AuraBuckets,
+        mut y: f64,
+        font_regular: &IndirectFontRef,
+    ) -> f64 {
+        let x = 10.0_f64;
+        let bar_x = x + 30.0;
+        let bar_max_width = 120.0_f64;
+        let bar_height = 5.0_f64;
+        let max_count = groups.iter().map(|g| g.count).max().unwrap_or(0).max(1);
+
+        for group in groups {
+            layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+            layer.use_text(group.label.as_str(), 9.0, Mm(x as f32), Mm((y + 1.0) as f32), font_regular);
+
+            let scale = bar_max_width / max_count as f64;
+            let mut segment_x = bar_x;
+            let without_aura = group.count - group.aura_levels.iter().sum::<usize>();
+            let segments = group
+                .aura_levels
+                .iter()
+                .zip(buckets.iter())
+                .map(|(&count, bucket)| (count, Self::bucket_color(bucket.color)))
+                .chain([(without_aura, Color::Rgb(Rgb::new(0.8, 0.8, 0.8, None)))]);
+            for (count, color) in segments {
+                if count == 0 {
+                    continue;
                 }
+                let width = count as f64 * scale;
+                Self::draw_rect(layer, segment_x, y, width, bar_height, color);
+                segment_x += width;
+            }
+
+            layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+            layer.use_text(
+                format!("{} ({:.1}%)", group.count, group.percentage),
+                9.0,
+                Mm((segment_x + 3.0) as f32),
+                Mm((y + 1.0) as f32),
+                font_regular,
+            );
+            y -= 8.0;
+        }
+
+        y
+    }
+
+    /// Draw repeat-visitor metrics and the monthly cohort retention heatmap
+    fn draw_retention_section(
+        layer: &PdfLayerReference,
+        retention: &RetentionStats,
+        font_bold: &IndirectFontRef,
+        font_regular: &IndirectFontRef,
+    ) {
+        let x = 10.0_f64;
+        let mut y = 265.0_f64;
+        let black = || Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None));
+
+        let mut summary = vec![
+            format!("Клиентов за период: {}", retention.clients_in_period),
+            format!(
+                "Новых: {}, вернувшихся: {} ({:.1}%)",
+                retention.new_clients, retention.returning_clients, retention.returning_percentage
+            ),
+        ];
+        if let (Some(avg), Some(median)) = (retention.avg_days_between_visits, retention.median_days_between_visits) {
+            summary.push(format!("Дней между визитами: медиана {:.0}, среднее {:.1}", median, avg));
+        }
+        for line in summary {
+            layer.use_text(line, 10.0, Mm(x as f32), Mm(y as f32), font_regular);
+            y -= 6.0;
+        }
+
+        // Visit frequency and gaps side by side
+        y -= 4.0;
+        layer.use_text("Визитов на клиента", 12.0, Mm(x as f32), Mm(y as f32), font_bold);
+        layer.use_text("Дней между визитами", 12.0, Mm((x + 95.0) as f32), Mm(y as f32), font_bold);
+        y -= 8.0;
+        let bar_color = || Color::Rgb(Rgb::new(0.149, 0.651, 0.604, None)); // #26A69A (teal)
+        let rows = retention.visit_frequency.len().max(retention.gap_distribution.len());
+        let max_frequency = retention.visit_frequency.iter().map(|(_, c)| *c).max().unwrap_or(0).max(1);
+        let max_gap = retention.gap_distribution.iter().map(|(_, c)| *c).max().unwrap_or(0).max(1);
+        for row in 0..rows {
+            let columns = [
+                (x, retention.visit_frequency.get(row), max_frequency),
+                (x + 95.0, retention.gap_distribution.get(row), max_gap),
+            ];
+            for (column_x, entry, max_count) in columns {
+                let Some((label, count)) = entry else {
+                    continue;
+                };
+                layer.set_fill_color(black());
+                layer.use_text(label.as_str(), 9.0, Mm(column_x as f32), Mm((y + 1.0) as f32), font_regular);
+                let width = (*count as f64 / max_count as f64 * 55.0).max(0.5);
+                Self::draw_rect(layer, column_x + 15.0, y, width, 4.0, bar_color());
+                layer.set_fill_color(black());
+                layer.use_text(
+                    count.to_string(),
+                    8.0,
+                    Mm((column_x + 17.0 + width) as f32),
+                    Mm((y + 1.0) as f32),
+                    font_regular,
+                );
+            }
+            y -= 7.0;
+        }
+
+        // Cohort heatmap
+        y -= 6.0;
+        layer.use_text("Удержание по когортам (% клиентов, вернувшихся через N месяцев)", 12.0, Mm(x as f32), Mm(y as f32), font_bold);
+        y -= 8.0;
+
+        let cell_width = 12.5_f64;
+        let cell_height = 6.0_f64;
+        let grid_x = x + 30.0;
+        layer.use_text("Когорта", 8.0, Mm(x as f32), Mm(y as f32), font_bold);
+        let months = retention.cohorts.iter().map(|c| c.retention.len()).max().unwrap_or(0);
+        for month in 0..months {
+            layer.use_text(
+                format!("M{}", month),
+                8.0,
+                Mm((grid_x + month as f64 * cell_width + 3.0) as f32),
+                Mm(y as f32),
+                font_bold,
+            );
+        }
+        y -= cell_height + 1.0;
+
+        for cohort in &retention.cohorts {
+            layer.set_fill_color(black());
+            layer.use_text(
+                format!("{} ({})", cohort.month, cohort.size),
+                8.0,
+                Mm(x as f32),
+                Mm((y + 2.0) as f32),
+                font_regular,
+            );
+            for (month, value) in cohort.retention.iter().enumerate() {
+                // White to teal depending on the retained share
+                let t = (value / 100.0) as f32;
+                let color = Color::Rgb(Rgb::new(1.0 - 0.851 * t, 1.0 - 0.349 * t, 1.0 - 0.396 * t, None));
+                let cell_x = grid_x + month as f64 * cell_width;
+                Self::draw_rect(layer, cell_x, y, cell_width - 0.5, cell_height - 0.5, color);
+                layer.set_fill_color(if t > 0.6 { Color::Rgb(Rgb::new(1.0, 1.0, 1.0, None)) } else { black() });
+                layer.use_text(
+                    format!("{:.0}", value),
+                    7.0,
+                    Mm((cell_x + 3.0) as f32),
+                    Mm((y + 1.8) as f32),
+                    font_regular,
+                );
             }
+            y -= cell_height;
         }
+        layer.set_fill_color(black());
+    }
+
+    /// Chart color of an aura bucket
+    fn bucket_color([r, g, b]: [u8; 3]) -> Color {
+        Color::Rgb(Rgb::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, None))
+    }
+
+    /// Draw a filled rectangle
+    fn draw_rect(layer: &PdfLayerReference, x: f64, y: f64, width: f64, height: f64, color: Color) {
+        layer.set_fill_color(color.clone());
+        layer.set_outline_color(color);
+        layer.add_polygon(Polygon {
+            rings: vec![vec![
+                (Point::new(Mm(x as f32), Mm(y as f32)), false),
+                (Point::new(Mm((x + width) as f32), Mm(y as f32)), false),
+                (Point::new(Mm((x + width) as f32), Mm((y + height) as f32)), false),
+                (Point::new(Mm(x as f32), Mm((y + height) as f32)), false),
+            ]],
+            mode: printpdf::path::PaintMode::FillStroke,
+            winding_order: printpdf::path::WindingOrder::NonZero,
+        });
+    }
 
+    /// Draw hourly distribution chart using vector graphics (TradingView style)
+    fn draw_hourly_chart(
+        layer: &PdfLayerReference,
+        hourly_counts: &HashMap<u32, u32>,
+        font_bold: &IndirectFontRef,
+        font_regular: &IndirectFontRef,
+    ) -> Result<()> {
         // Chart dimensions and position (using f64 for calculations, convert to f32 for Mm)
         // Ratio height:width = 1:5
         let chart_x = 10.0_f64;
@@ -228,7 +948,7 @@ impl PdfGenerator {
             
             // Draw hour label under each bar
             layer.use_text(
-                &format!("{}", hour),
+                format!("{}", hour),
                 6.0,
                 Mm((x + bar_width / 2.0 - 1.5) as f32),
                 Mm((chart_y - 3.0) as f32),
@@ -244,7 +964,7 @@ impl PdfGenerator {
             let value = (max_count as f64 / 3.0 * i as f64) as u32;
             let y = chart_y + (chart_height / 3.0) * i as f64;
             layer.use_text(
-                &format!("{}", value),
+                format!("{}", value),
                 7.0,
                 Mm((chart_x - 8.0) as f32),
                 Mm((y - 1.0) as f32),
@@ -255,16 +975,153 @@ impl PdfGenerator {
         Ok(())
     }
 
-    pub fn generate_filename(prefix: &str) -> String {
-        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
-        format!("{}_{}.pdf", prefix, timestamp)
+    /// Draw the data quality section (orphaned records and audit findings) below the hourly chart
+    fn draw_data_quality_section(
+        layer: &PdfLayerReference,
+        data_quality: &DataQualityStats,
+        font_bold: &IndirectFontRef,
+        font_regular: &IndirectFontRef,
+    ) {
+        let x = 10.0_f32;
+        let bottom = 15.0_f32;
+        let mut y = 200.0_f32;
+
+        layer.use_text("Качество данных", 14.0, Mm(x), Mm(y), font_bold);
+        y -= 7.0;
+
+        layer.use_text(
+            format!("Проверено записей: {}", data_quality.audited_records),
+            9.0,
+            Mm(x),
+            Mm(y),
+            font_regular,
+        );
+        y -= 6.0;
+
+        if data_quality.orphaned_records == 0 && data_quality.findings.is_empty() {
+            layer.use_text("Проблем не найдено.", 9.0, Mm(x), Mm(y), font_regular);
+            return;
+        }
+
+        if data_quality.orphaned_records > 0 {
+            layer.use_text(
+                format!(
+                    "Записей без комплекса: {} (без club_id: {})",
+                    data_quality.orphaned_records, data_quality.missing_club_records
+                ),
+                9.0,
+                Mm(x),
+                Mm(y),
+                font_regular,
+            );
+            y -= 5.0;
+
+            if !data_quality.unknown_clubs.is_empty() {
+                layer.use_text("Неизвестные club_id:", 9.0, Mm(x), Mm(y), font_regular);
+                y -= 5.0;
+                for (club_id, count) in data_quality.unknown_clubs.iter().take(10) {
+                    layer.use_text(format!("{} - {}", club_id, count), 8.0, Mm(x + 5.0), Mm(y), font_regular);
+                    y -= 4.0;
+                }
+                if data_quality.unknown_clubs.len() > 10 {
+                    layer.use_text(
+                        format!("... и еще {}", data_quality.unknown_clubs.len() - 10),
+                        8.0,
+                        Mm(x + 5.0),
+                        Mm(y),
+                        font_regular,
+                    );
+                    y -= 4.0;
+                }
+            }
+
+            let note = if data_quality.orphans_included {
+                "Такие записи учтены в отчете как \"Без комплекса\"."
+            } else {
+                "Такие записи исключены из отчета."
+            };
+            layer.use_text(note, 9.0, Mm(x), Mm(y - 1.0), font_regular);
+            y -= 8.0;
+        }
+
+        // Audit findings with sample record ids
+        for finding in &data_quality.findings {
+            if y < bottom {
+                break;
+            }
+            layer.use_text(
+                format!("{}: {}", finding.check.title(), finding.count),
+                9.0,
+                Mm(x),
+                Mm(y),
+                font_bold,
+            );
+            layer.use_text(
+                format!("Id: {}", finding.sample_ids.join(", ")),
+                8.0,
+                Mm(x + 5.0),
+                Mm(y - 4.0),
+                font_regular,
+            );
+            y -= 9.0;
+        }
     }
+
 }
 
-#[derive(Debug)]
-struct AuraStatistics {
-    total: usize,
-    low_aura: usize,
-    normal_aura: usize,
-    high_aura: usize,
+/// Series color for mean lines (#26A69A)
+fn teal() -> Color {
+    Color::Rgb(Rgb::new(0.149, 0.651, 0.604, None))
+}
+
+/// Series color for median lines (#FFA726)
+fn amber() -> Color {
+    Color::Rgb(Rgb::new(1.0, 0.655, 0.149, None))
+}
+
+/// Shorten a label to fit a table column
+fn truncate_label(label: &str, max_chars: usize) -> String {
+    if label.chars().count() <= max_chars {
+        return label.to_string();
+    }
+    let mut short: String = label.chars().take(max_chars - 1).collect();
+    short.push('…');
+    short
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::club_directory::test_clubs;
+    use crate::date_utils::Period;
+    use crate::phone::PhoneNormalizer;
+    use crate::retention::VisitHistory;
+    use crate::stats::{StatsCollector, StatsSettings};
+    use serde_json::json;
+
+    #[test]
+    fn test_generate_pdf() {
+        let clubs = test_clubs(&[("c1", "Club 1")]);
+        let mut stats = StatsCollector::new(&clubs, &StatsSettings::default());
+        let mut chart_data = ChartData::new("CreatedAt1");
+
+        let record = json!({
+            "Id": 1, "club_id": "c1", "phone": "79990000001", "status": "done",
+            "text_aura": {"percent": "75%"}, "birth_date": "1990-01-01", "sex": "female",
+            "CreatedAt": "2024-01-01 10:00:00", "UpdatedAt": "2024-01-01 10:00:42",
+            "CreatedAt1": "2024-01-01 10:00:00+00:00"
+        });
+        stats.add_record(&record);
+        chart_data.add_record(&record);
+
+        let mut history = VisitHistory::new(PhoneNormalizer::default());
+        history.add_visit(&record["phone"], crate::date_utils::parse_record_datetime("2024-01-01 10:00:00+00:00").unwrap());
+        let mut stats = stats.finish();
+        stats.retention = Some(history.finish(&Period::Today.get_date_range()));
+
+        let output_path = "test_output.pdf";
+        PdfGenerator::generate(&chart_data, &stats, output_path).unwrap();
+        assert!(std::fs::metadata(output_path).unwrap().len() > 0);
+        std::fs::remove_file(output_path).unwrap();
+    }
 }
@@ -0,0 +1,171 @@
+use serde_json::Value;
+
+/// Supported default countries: ISO code, calling code and national number length
+const COUNTRIES: &[(&str, &str, usize)] = &[
+    ("RU", "7", 10),
+    ("KZ", "7", 10),
+    ("BY", "375", 9),
+    ("UA", "380", 9),
+    ("UZ", "998", 9),
+    ("KG", "996", 9),
+    ("AM", "374", 8),
+    ("GE", "995", 9),
+    ("AZ", "994", 9),
+];
+
+/// Normalizes phone numbers to E.164 (`+79991234567`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhoneNormalizer {
+    calling_code: &'static str,
+    national_length: usize,
+}
+
+impl Default for PhoneNormalizer {
+    fn default() -> Self {
+        Self::for_country("RU").expect("RU is a supported country")
+    }
+}
+
+impl PhoneNormalizer {
+    /// Normalizer for numbers without a country code; `None` if the ISO code is not supported
+    pub fn for_country(iso_code: &str) -> Option<Self> {
+        let iso_code = iso_code.trim().to_uppercase();
+        COUNTRIES
+            .iter()
+            .find(|(code, _, _)| *code == iso_code)
+            .map(|(_, calling_code, national_length)| Self {
+                calling_code,
+                national_length: *national_length,
+            })
+    }
+
+    /// Supported ISO country codes, for error messages
+    pub fn supported_countries() -> Vec<&'static str> {
+        COUNTRIES.iter().map(|(code, _, _)| *code).collect()
+    }
+
+    /// Normalize a phone value (string or number) to E.164; `None` if it cannot be parsed
+    pub fn normalize(&self, phone: &Value) -> Option<String> {
+        let text = match phone {
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => s.trim().to_string(),
+            _ => return None,
+        };
+
+        // Strip formatting; anything else (letters, extensions) makes the number unparsable
+        if !text.chars().all(|c| c.is_ascii_digit() || " +-().".contains(c)) {
+            return None;
+        }
+        let international = text.starts_with('+') || text.starts_with("00");
+        let mut digits: String = text.chars().filter(|c| c.is_ascii_digit()).collect();
+        if text.starts_with("00") {
+            digits.drain(..2);
+        }
+
+        let national_with_code = self.calling_code.len() + self.national_length;
+        let number = if international {
+            digits
+        } else if digits.len() == self.national_length {
+            format!("{}{}", self.calling_code, digits)
+        } else if digits.len() == national_with_code && digits.starts_with(self.calling_code) {
+            digits
+        } else if self.calling_code == "7" && digits.len() == 11 && digits.starts_with('8') {
+            // Russian/Kazakh trunk prefix: 8 999 ... -> +7 999 ...
+            format!("7{}", &digits[1..])
+        } else if digits.len() == self.national_length + 1 && digits.starts_with('0') {
+            // Trunk prefix 0 used in most other countries
+            format!("{}{}", self.calling_code, &digits[1..])
+        } else {
+            return None;
+        };
+
+        // E.164 allows up to 15 digits; +7 numbers always have 11
+        if !(8..=15).contains(&number.len()) || number.starts_with('0') {
+            return None;
+        }
+        if number.starts_with('7') && number.len() != 11 {
+            return None;
+        }
+
+        Some(format!("+{}", number))
+    }
+
+    /// Key identifying a client: the E.164 number, or the trimmed raw value when it
+    /// cannot be parsed (such numbers are reported by the data quality audit)
+    pub fn client_key(&self, phone: &Value) -> Option<String> {
+        if let Some(normalized) = self.normalize(phone) {
+            return Some(normalized);
+        }
+        match phone {
+            Value::String(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Phone with the middle digits hidden: `+7 999 ***-**-67`. Values that cannot be
+    /// normalized may be an email or a name typed into the phone field, so they are replaced
+    /// by `***` followed by at most their last two digits.
+    pub fn mask(&self, phone: &Value) -> Option<String> {
+        let Some(normalized) = self.normalize(phone) else {
+            let raw = self.client_key(phone)?;
+            let digits: Vec<char> = raw.chars().filter(|c| c.is_ascii_digit()).collect();
+            let tail: String = digits[digits.len().saturating_sub(2)..].iter().collect();
+            return Some(format!("***{}", tail));
+        };
+
+        let digits = &normalized[1..];
+        let calling_code = COUNTRIES
+            .iter()
+            .map(|(_, code, _)| *code)
+            .filter(|code| digits.starts_with(code))
+            .max_by_key(|code| code.len());
+        match calling_code {
+            Some(code) if digits.len() >= code.len() + 7 => {
+                let national = &digits[code.len()..];
+                Some(format!("+{} {} ***-**-{}", code, &national[..3], &national[national.len() - 2..]))
+            }
+            _ => Some(format!("+{}{}", "*".repeat(digits.len() - 2), &digits[digits.len() - 2..])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_normalize_russian_numbers() {
+        let phones = PhoneNormalizer::default();
+        for raw in [json!("+7 (999) 123-45-67"), json!("89991234567"), json!(79991234567u64), json!("9991234567")] {
+            assert_eq!(phones.normalize(&raw).as_deref(), Some("+79991234567"), "{}", raw);
+        }
+
+        assert_eq!(phones.normalize(&json!("+375 29 123-45-67")).as_deref(), Some("+375291234567"));
+        assert_eq!(phones.normalize(&json!("12345")), None);
+        assert_eq!(phones.normalize(&json!("899912345678")), None);
+        assert_eq!(phones.normalize(&json!("call me")), None);
+        assert_eq!(phones.client_key(&json!(" 12345 ")).as_deref(), Some("12345"));
+    }
+
+    #[test]
+    fn test_normalize_with_other_default_country() {
+        let phones = PhoneNormalizer::for_country("by").unwrap();
+        assert_eq!(phones.normalize(&json!("029 123-45-67")).as_deref(), Some("+375291234567"));
+        assert_eq!(phones.normalize(&json!("291234567")).as_deref(), Some("+375291234567"));
+        assert_eq!(phones.normalize(&json!("+7 999 123 45 67")).as_deref(), Some("+79991234567"));
+        assert!(PhoneNormalizer::for_country("XX").is_none());
+    }
+
+    #[test]
+    fn test_mask_redacts_values_that_are_not_phones() {
+        let phones = PhoneNormalizer::default();
+        assert_eq!(phones.mask(&json!("+7 (999) 123-45-67")).as_deref(), Some("+7 999 ***-**-67"));
+        assert_eq!(phones.mask(&json!("anna.petrova@mail.ru")).as_deref(), Some("***"));
+        assert_eq!(phones.mask(&json!("Анна Петрова")).as_deref(), Some("***"));
+        assert_eq!(phones.mask(&json!("anna1990@mail.ru")).as_deref(), Some("***90"));
+        assert_eq!(phones.mask(&json!("12345")).as_deref(), Some("***45"));
+        assert_eq!(phones.mask(&json!("")), None);
+    }
+}
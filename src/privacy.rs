@@ -0,0 +1,136 @@
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::export_profile::CsvColumn;
+use crate::phone::PhoneNormalizer;
+
+/// How much personal data (phone, name, birth date) the record exports contain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivacyMode {
+    /// Records as they are
+    Full,
+    /// Phones masked (`+7 999 ***-**-67`), names and birth dates removed
+    Masked,
+    /// Phones replaced with a keyed HMAC, stable across reports; names and birth dates removed
+    Hashed,
+    /// Phones, names and birth dates removed
+    NoPii,
+    /// No record exports at all, only aggregated statistics
+    Aggregate,
+}
+
+impl PrivacyMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "full" => Some(PrivacyMode::Full),
+            "masked" => Some(PrivacyMode::Masked),
+            "hashed" => Some(PrivacyMode::Hashed),
+            "no_pii" => Some(PrivacyMode::NoPii),
+            "aggregate" => Some(PrivacyMode::Aggregate),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PrivacyMode::Full => "full",
+            PrivacyMode::Masked => "masked",
+            PrivacyMode::Hashed => "hashed",
+            PrivacyMode::NoPii => "no_pii",
+            PrivacyMode::Aggregate => "aggregate",
+        }
+    }
+
+    /// Whether per-record files (records CSV, XLSX records sheet) are produced
+    pub fn exports_records(self) -> bool {
+        self != PrivacyMode::Aggregate
+    }
+}
+
+/// Applies a privacy mode to the personal fields of exported records
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+    mode: PrivacyMode,
+    hmac_key: Vec<u8>,
+}
+
+impl Anonymizer {
+    /// `hmac_key` is only used by [`PrivacyMode::Hashed`]
+    pub fn new(mode: PrivacyMode, hmac_key: &[u8]) -> Self {
+        Self {
+            mode,
+            hmac_key: hmac_key.to_vec(),
+        }
+    }
+
+    /// Anonymizer that leaves records unchanged
+    #[cfg(test)]
+    pub fn full() -> Self {
+        Self::new(PrivacyMode::Full, &[])
+    }
+
+    pub fn mode(&self) -> PrivacyMode {
+        self.mode
+    }
+
+    /// Whether the column is left out of record exports
+    pub fn drops(&self, column: CsvColumn) -> bool {
+        match self.mode {
+            PrivacyMode::Full => false,
+            PrivacyMode::Masked | PrivacyMode::Hashed => matches!(column, CsvColumn::Name | CsvColumn::BirthDate),
+            PrivacyMode::NoPii | PrivacyMode::Aggregate => {
+                matches!(column, CsvColumn::Phone | CsvColumn::Name | CsvColumn::BirthDate)
+            }
+        }
+    }
+
+    /// Exported phone value: E.164, masked or hashed depending on the mode
+    pub fn phone(&self, phones: &PhoneNormalizer, phone: &Value) -> Option<String> {
+        match self.mode {
+            PrivacyMode::Full => phones.client_key(phone),
+            PrivacyMode::Masked => phones.mask(phone),
+            PrivacyMode::Hashed => phones.client_key(phone).map(|key| self.hash(&key)),
+            PrivacyMode::NoPii | PrivacyMode::Aggregate => None,
+        }
+    }
+
+    /// First 128 bits of HMAC-SHA256 in hex
+    fn hash(&self, value: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.hmac_key).expect("HMAC accepts keys of any length");
+        mac.update(value.as_bytes());
+        mac.finalize().into_bytes()[..16].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_phone_modes() {
+        let phones = PhoneNormalizer::default();
+        let phone = json!("8 (999) 123-45-67");
+
+        assert_eq!(Anonymizer::full().phone(&phones, &phone).as_deref(), Some("+79991234567"));
+
+        let masked = Anonymizer::new(PrivacyMode::Masked, &[]);
+        assert_eq!(masked.phone(&phones, &phone).as_deref(), Some("+7 999 ***-**-67"));
+        assert_eq!(masked.phone(&phones, &json!("+375291234567")).as_deref(), Some("+375 291 ***-**-67"));
+        assert_eq!(masked.phone(&phones, &json!("12345")).as_deref(), Some("***45"));
+        assert!(masked.drops(CsvColumn::Name) && !masked.drops(CsvColumn::Phone));
+
+        // Same client, same key: equal hashes regardless of formatting; another key differs
+        let hashed = Anonymizer::new(PrivacyMode::Hashed, b"secret");
+        let hash = hashed.phone(&phones, &phone).unwrap();
+        assert_eq!(hash.len(), 32);
+        assert_eq!(hashed.phone(&phones, &json!("+79991234567")), Some(hash.clone()));
+        assert_ne!(Anonymizer::new(PrivacyMode::Hashed, b"other").phone(&phones, &phone), Some(hash));
+
+        assert_eq!(Anonymizer::new(PrivacyMode::NoPii, &[]).phone(&phones, &phone), None);
+        assert!(!PrivacyMode::Aggregate.exports_records());
+    }
+}
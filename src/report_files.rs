@@ -0,0 +1,119 @@
+use anyhow::Result;
+use chrono_tz::Europe::Moscow;
+use log::warn;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use teloxide::types::InputFile;
+
+use crate::date_utils::{get_moscow_time, DateRange, Period};
+
+/// Requests started since the bot was launched; makes request ids unique within a millisecond
+static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Start and end dates of a range in Moscow time, e.g. "20240101-20240131"
+pub fn range_stamp(date_range: &DateRange) -> String {
+    format!(
+        "{}-{}",
+        date_range.start.with_timezone(&Moscow).format("%Y%m%d"),
+        date_range.end.with_timezone(&Moscow).format("%Y%m%d")
+    )
+}
+
+/// Directory holding the files of one report request. It is removed with everything in it when
+/// dropped, so report files (full personal data included) are gone once the request is sent.
+pub struct RequestDir {
+    path: String,
+}
+
+impl RequestDir {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Drop for RequestDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            warn!("Failed to remove request directory {}: {}", self.path, e);
+        }
+    }
+}
+
+/// Create the directory holding the files of one report request, e.g.
+/// `reports/week_20240101-20240107_20240103-101500123-4`: period, date range, scope (such as
+/// the split export) and a request id, so concurrent requests never share a file
+pub fn create_request_dir(
+    output_dir: &str,
+    period: &Period,
+    date_range: &DateRange,
+    scope: Option<&str>,
+) -> Result<RequestDir> {
+    let request_id = format!(
+        "{}-{}",
+        get_moscow_time().format("%Y%m%d-%H%M%S%3f"),
+        REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let mut name = format!("{}_{}", period.name(), range_stamp(date_range));
+    if let Some(scope) = scope {
+        name = format!("{}_{}", name, scope);
+    }
+    let dir = format!("{}/{}_{}", output_dir, name, request_id);
+    std::fs::create_dir_all(&dir)?;
+    Ok(RequestDir { path: dir })
+}
+
+/// Path a file is written to before [`persist`] moves it into place: a hidden file next to it
+/// with the same extension, so writers that pick the format by extension still work
+pub fn temp_path(path: &str) -> String {
+    let path = Path::new(path);
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    path.with_file_name(format!(".tmp-{}", name)).to_string_lossy().to_string()
+}
+
+/// Atomically replace `path` with its fully written temporary file and return the path
+pub fn persist(path: &str) -> Result<String> {
+    std::fs::rename(temp_path(path), path)?;
+    Ok(path.to_string())
+}
+
+/// Report file loaded into memory for upload, so the upload does not depend on the file staying
+/// unchanged on disk
+pub fn input_file(path: &str) -> std::io::Result<InputFile> {
+    let name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string());
+    Ok(InputFile::memory(std::fs::read(path)?).file_name(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_dirs_and_atomic_files() {
+        let date_range = Period::Week.get_date_range();
+        let first = create_request_dir("test_reports", &Period::Week, &date_range, None).unwrap();
+        let second = create_request_dir("test_reports", &Period::Week, &date_range, Some("split")).unwrap();
+        assert_ne!(first.path(), second.path());
+        assert!(first.path().starts_with(&format!("test_reports/week_{}_", range_stamp(&date_range))));
+        assert!(second.path().contains("_split_"));
+
+        let path = format!("{}/report.csv", first.path());
+        assert_eq!(temp_path(&path), format!("{}/.tmp-report.csv", first.path()));
+        std::fs::write(temp_path(&path), "id\n1\n").unwrap();
+        assert!(!Path::new(&path).exists());
+        assert_eq!(persist(&path).unwrap(), path);
+        assert!(!Path::new(&temp_path(&path)).exists());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "id\n1\n");
+        assert_eq!(temp_path("report.pdf"), ".tmp-report.pdf");
+
+        // Dropping the request removes its directory and the files in it
+        let first_dir = first.path().to_string();
+        drop(first);
+        assert!(!Path::new(&first_dir).exists());
+        drop(second);
+
+        std::fs::remove_dir_all("test_reports").unwrap();
+    }
+}
@@ -0,0 +1,412 @@
+use crate::aura::AuraBuckets;
+use crate::club_directory::ClubMap;
+use crate::stats::{ClubStats, DataQualityStats, ReportStats};
+
+/// Daily aura values are listed in the message for periods up to this many days
+const DAILY_AURA_MESSAGE_DAYS: usize = 7;
+
+/// Longest text Telegram accepts in one message, in characters
+const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+
+/// Escape text for Telegram HTML parse mode
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Split a message into parts that each fit into one Telegram message. Parts break between
+/// lines, so HTML tags (which never span lines in the generated messages) stay balanced; only
+/// a single line longer than the limit is cut inside.
+pub fn split_message(text: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut lines: Vec<&str> = Vec::new();
+    let mut len = 0;
+    for line in text.split('\n') {
+        let mut rest = line;
+        loop {
+            let cut = rest.char_indices().nth(TELEGRAM_MESSAGE_LIMIT).map(|(i, _)| i);
+            let (piece, tail) = rest.split_at(cut.unwrap_or(rest.len()));
+            let piece_len = piece.chars().count();
+            let joined_len = if lines.is_empty() { piece_len } else { len + 1 + piece_len };
+            if joined_len > TELEGRAM_MESSAGE_LIMIT {
+                parts.push(lines.join("\n"));
+                lines.clear();
+                len = piece_len;
+            } else {
+                len = joined_len;
+            }
+            lines.push(piece);
+            if cut.is_none() {
+                break;
+            }
+            rest = tail;
+        }
+    }
+    parts.push(lines.join("\n"));
+    // Telegram rejects empty messages
+    parts.retain(|part| !part.trim().is_empty());
+    parts
+}
+
+/// Build the Telegram statistics message (HTML) for a report.
+///
+/// With more than `2 * leaderboard_size` clubs only the top and bottom clubs are listed; with
+/// `leaderboard_size` 0 every club is, so the message is sent through `split_message`.
+pub fn format_stats_message(title: &str, stats: &ReportStats, leaderboard_size: usize) -> String {
+    // Build club leaderboard section
+    let mut club_stats_text = String::new();
+    let clubs = &stats.club_stats;
+    if !clubs.is_empty() {
+        if leaderboard_size > 0 && clubs.len() > 2 * leaderboard_size {
+            club_stats_text.push_str(&format!("\n\n📍 <b>Рейтинг комплексов ({}):</b>\n", clubs.len()));
+            club_stats_text.push_str(&format!("\n🏆 <b>Топ-{}:</b>", leaderboard_size));
+            for club_stat in &clubs[..leaderboard_size] {
+                club_stats_text.push_str(&format_club_entry(club_stat, &stats.aura_buckets));
+            }
+            club_stats_text.push_str(&format!("\n\n🔻 <b>Последние {}:</b>", leaderboard_size));
+            for club_stat in &clubs[clubs.len() - leaderboard_size..] {
+                club_stats_text.push_str(&format_club_entry(club_stat, &stats.aura_buckets));
+            }
+            club_stats_text.push_str("\n   <i>Все комплексы — в PDF и CSV по комплексам</i>");
+        } else {
+            club_stats_text.push_str("\n\n📍 <b>Рейтинг комплексов:</b>\n");
+            for club_stat in clubs {
+                club_stats_text.push_str(&format_club_entry(club_stat, &stats.aura_buckets));
+            }
+        }
+    }
+
+    // Build aura bucket section
+    let aura_levels_text = stats
+        .aura_buckets
+        .iter()
+        .zip(&stats.aura_levels)
+        .enumerate()
+        .map(|(i, (bucket, count))| {
+            format!(
+                "{} {} ({}): <b>{}</b>",
+                bucket.emoji,
+                escape_html(&bucket.label),
+                escape_html(&stats.aura_buckets.range_label(i)),
+                count
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // Build aura score section
+    let mut aura_text = String::new();
+    if let Some(aura) = &stats.aura {
+        aura_text.push_str(&format!(
+            "\n\n📐 <b>Аура, %:</b> среднее <b>{:.1}</b>, медиана <b>{:.1}</b>, σ {:.1} (n = {})",
+            aura.mean, aura.median, aura.std_dev, aura.count
+        ));
+        if (2..=DAILY_AURA_MESSAGE_DAYS).contains(&stats.daily_aura.len()) {
+            for day in &stats.daily_aura {
+                aura_text.push_str(&format!(
+                    "\n   {}: среднее {:.1}, медиана {:.1}, σ {:.1}",
+                    day.date.format("%d.%m"),
+                    day.summary.mean,
+                    day.summary.median,
+                    day.summary.std_dev
+                ));
+            }
+        } else if stats.daily_aura.len() > DAILY_AURA_MESSAGE_DAYS {
+            aura_text.push_str("\n   <i>Динамика по дням — в PDF</i>");
+        }
+    }
+
+    // Build generation time section
+    let generation_time_text = match &stats.generation_time {
+        Some(time) => {
+            let mut text = format!(
+                "\n\n⏱ <b>Время генерации (done), сек:</b>\n   \
+                Медиана: <b>{:.1}</b>, среднее: {:.1}\n   \
+                p90: {:.1}, p95: {:.1}, p99: {:.1}\n   \
+                Мин: {:.1}, макс: {:.1} (n = {})",
+                time.median, time.mean, time.p90, time.p95, time.p99, time.min, time.max, time.count
+            );
+            if time.excluded_outliers > 0 {
+                text.push_str(&format!("\n   Исключено выбросов: {}", time.excluded_outliers));
+            }
+            text
+        }
+        None => String::new(),
+    };
+
+    // Build status statistics section
+    let breakdown = &stats.status_breakdown;
+    let mut status_text = String::from("\n\n📋 <b>Статусы генераций:</b>");
+    for status in &breakdown.statuses {
+        let icon = match status.status.as_str() {
+            "done" => "✅",
+            "process" => "⏳",
+            _ => "❌",
+        };
+        status_text.push_str(&format!(
+            "\n   {} {}: <b>{}</b> ({:.1}%)",
+            icon,
+            escape_html(status.label()),
+            status.count,
+            status.percentage
+        ));
+    }
+    if breakdown.stuck_count > 0 {
+        status_text.push_str(&format!(
+            "\n   🕒 Зависли в process дольше {} мин: <b>{}</b> ({:.1}%)\n      Id: <code>{}</code>",
+            breakdown.stuck_after_minutes,
+            breakdown.stuck_count,
+            breakdown.stuck_percentage,
+            escape_html(&breakdown.stuck_sample_ids.join(", "))
+        ));
+    }
+    if breakdown.high_failure_rate {
+        status_text.push_str(&format!(
+            "\n   🚨 <b>Высокая доля ошибок: {:.1}%</b>",
+            breakdown.failed_percentage
+        ));
+    }
+    if breakdown.high_stuck_rate {
+        status_text.push_str(&format!(
+            "\n   🚨 <b>Высокая доля зависших генераций: {:.1}%</b>",
+            breakdown.stuck_percentage
+        ));
+    }
+
+    // Build demographics section
+    let mut demographics_text = String::new();
+    if stats.total_records > 0 {
+        demographics_text.push_str("\n\n👤 <b>Возраст:</b>");
+        for group in stats.demographics.age_groups.iter().filter(|g| g.count > 0) {
+            demographics_text.push_str(&format!(
+                "\n   {}: <b>{}</b> ({:.1}%)",
+                escape_html(&group.label),
+                group.count,
+                group.percentage
+            ));
+        }
+        let genders: Vec<String> = stats
+            .demographics
+            .genders
+            .iter()
+            .map(|g| format!("{}: <b>{}</b> ({:.1}%)", escape_html(&g.label), g.count, g.percentage))
+            .collect();
+        demographics_text.push_str(&format!("\n⚧ <b>Пол:</b> {}", genders.join(", ")));
+    }
+
+    // Build retention section
+    let mut retention_text = String::new();
+    if let Some(retention) = stats.retention.as_ref().filter(|r| r.clients_in_period > 0) {
+        retention_text.push_str(&format!(
+            "\n\n🔁 <b>Возвраты клиентов:</b>\n   Новых: <b>{}</b>, вернувшихся: <b>{}</b> ({:.1}%)",
+            retention.new_clients, retention.returning_clients, retention.returning_percentage
+        ));
+        if let (Some(avg), Some(median)) = (retention.avg_days_between_visits, retention.median_days_between_visits) {
+            retention_text.push_str(&format!(
+                "\n   Дней между визитами: медиана <b>{:.0}</b>, среднее {:.1}",
+                median, avg
+            ));
+        }
+    } else if let Some(reason) = &stats.retention_unavailable {
+        retention_text.push_str(&format!(
+            "\n\n🔁 <b>Возвраты клиентов:</b> <i>не посчитаны, {}</i>",
+            escape_html(reason)
+        ));
+    }
+
+    // Build data quality section
+    let data_quality = &stats.data_quality;
+    let data_quality_text = if data_quality.orphaned_records > 0 || !data_quality.findings.is_empty() {
+        format!(
+            "\n\n⚠️ <b>Качество данных:</b>{}\n   <i>Подробнее: /dataquality</i>",
+            format_data_quality_details(data_quality, false)
+        )
+    } else {
+        String::new()
+    };
+
+    format!(
+        "📊 <b>{}</b>\n\n\
+        📈 Всего генераций: <b>{}</b>\n\
+        👥 Уникальных клиентов: <b>{}</b>\n\n\
+        {}{}{}{}{}{}{}{}",
+        title,
+        stats.total_records,
+        stats.unique_clients,
+        aura_levels_text,
+        aura_text,
+        club_stats_text,
+        generation_time_text,
+        status_text,
+        demographics_text,
+        retention_text,
+        data_quality_text
+    )
+}
+
+/// One club of the leaderboard with its KPIs
+fn format_club_entry(club: &ClubStats, buckets: &AuraBuckets) -> String {
+    let mut label = escape_html(&club.club_name);
+    if let Some(city) = &club.city {
+        label.push_str(&format!(" ({})", escape_html(city)));
+    }
+    let rank_change = match club.rank_change {
+        Some(change) if change > 0 => format!(" ▲{}", change),
+        Some(change) if change < 0 => format!(" ▼{}", -change),
+        Some(_) => " =".to_string(),
+        None => String::new(),
+    };
+
+    let mut text = format!(
+        "\n{}. 🏢 <i>{}</i>{}\n   Генераций: <b>{}</b> ({:.1}%), клиентов: <b>{}</b>, на клиента: {:.2}\n   Done: {:.1}%",
+        club.rank,
+        label,
+        rank_change,
+        club.total_generations,
+        club.percentage,
+        club.unique_clients,
+        club.generations_per_client,
+        club.done_rate
+    );
+    if let Some(aura) = &club.aura {
+        let levels: Vec<String> = buckets
+            .iter()
+            .zip(&club.aura_levels)
+            .map(|(bucket, count)| format!("{}{}", bucket.emoji, count))
+            .collect();
+        text.push_str(&format!(
+            "\n   Аура: ср. <b>{:.1}%</b>, медиана {:.1}%, σ {:.1} ({})",
+            aura.mean, aura.median, aura.std_dev, levels.join(" ")
+        ));
+    }
+    if let Some(time) = &club.generation_time {
+        text.push_str(&format!(
+            "\n   Генерация: медиана <b>{:.1}</b> сек, среднее {:.1} сек, p95 {:.1} сек",
+            time.median, time.mean, time.p95
+        ));
+    }
+    text
+}
+
+/// Build the data quality audit message (HTML) for the /dataquality command
+pub fn format_data_quality_message(period_label: &str, data_quality: &DataQualityStats) -> String {
+    let mut text = format!(
+        "🔍 <b>Качество данных</b>\n{}\n\nПроверено записей: <b>{}</b>",
+        escape_html(period_label),
+        data_quality.audited_records
+    );
+
+    if data_quality.orphaned_records == 0 && data_quality.findings.is_empty() {
+        text.push_str("\n\n✅ Проблем не найдено");
+    } else {
+        text.push_str(&format_data_quality_details(data_quality, true));
+    }
+
+    text
+}
+
+/// Data quality lines; sample record ids are listed only when `with_samples` is set
+fn format_data_quality_details(data_quality: &DataQualityStats, with_samples: bool) -> String {
+    let mut text = String::new();
+
+    if data_quality.orphaned_records > 0 {
+        text.push_str(&format!(
+            "\n   Записей без комплекса: <b>{}</b> (без club_id: {})",
+            data_quality.orphaned_records, data_quality.missing_club_records
+        ));
+        if !data_quality.unknown_clubs.is_empty() {
+            let unknown: Vec<String> = data_quality
+                .unknown_clubs
+                .iter()
+                .take(10)
+                .map(|(club_id, count)| format!("<code>{}</code> ({})", escape_html(club_id), count))
+                .collect();
+            text.push_str(&format!("\n   Неизвестные club_id: {}", unknown.join(", ")));
+            if data_quality.unknown_clubs.len() > 10 {
+                text.push_str(&format!(" и еще {}", data_quality.unknown_clubs.len() - 10));
+            }
+        }
+        text.push_str(if data_quality.orphans_included {
+            "\n   <i>Учтены в отчете как «Без комплекса»</i>"
+        } else {
+            "\n   <i>Исключены из отчета</i>"
+        });
+    }
+
+    for finding in &data_quality.findings {
+        text.push_str(&format!("\n   {}: <b>{}</b>", finding.check.title(), finding.count));
+        if with_samples {
+            text.push_str(&format!("\n      Id: <code>{}</code>", escape_html(&finding.sample_ids.join(", "))));
+        }
+    }
+
+    text
+}
+
+/// Build the club directory listing (HTML) shown after a refresh
+pub fn format_club_directory(clubs: &ClubMap) -> String {
+    let mut sorted: Vec<_> = clubs.values().collect();
+    sorted.sort_by(|a, b| b.active.cmp(&a.active).then_with(|| a.name.cmp(&b.name)));
+
+    let mut text = format!(
+        "✅ <b>Справочник комплексов обновлен</b>\nВсего: <b>{}</b>, активных: <b>{}</b>\n",
+        clubs.len(),
+        clubs.values().filter(|c| c.active).count()
+    );
+
+    for club in sorted {
+        text.push_str(&format!(
+            "\n{} <b>{}</b> <code>{}</code>",
+            if club.active { "🏢" } else { "⛔" },
+            escape_html(&club.name),
+            escape_html(&club.club_id)
+        ));
+        let details: Vec<String> = [&club.city, &club.address, &club.timezone]
+            .into_iter()
+            .flatten()
+            .map(|s| escape_html(s))
+            .collect();
+        if !details.is_empty() {
+            text.push_str(&format!("\n   {}", details.join(", ")));
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::club_directory::test_clubs;
+    use crate::stats::{StatsCollector, StatsSettings};
+    use serde_json::json;
+
+    #[test]
+    fn test_split_message_keeps_lines_within_limit() {
+        assert_eq!(split_message("<b>Итого</b>\n\nстрока"), vec!["<b>Итого</b>\n\nстрока"]);
+        assert!(split_message("\n\n").is_empty());
+
+        let long_line = "я".repeat(TELEGRAM_MESSAGE_LIMIT + 10);
+        let parts = split_message(&format!("начало\n{}", long_line));
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], "начало");
+        assert_eq!(parts[2].chars().count(), 10);
+
+        // Every club listed with leaderboard size 0 overflows a single message
+        let ids: Vec<String> = (0..300).map(|i| format!("c{:03}", i)).collect();
+        let pairs: Vec<(&str, &str)> = ids.iter().map(|id| (id.as_str(), id.as_str())).collect();
+        let clubs = test_clubs(&pairs);
+        let mut collector = StatsCollector::new(&clubs, &StatsSettings::default());
+        for id in &ids {
+            collector.add_record(&json!({"club_id": id, "phone": "79990000001", "status": "done"}));
+        }
+        let message = format_stats_message("Отчет", &collector.finish(), 0);
+        assert!(message.chars().count() > TELEGRAM_MESSAGE_LIMIT);
+
+        let parts = split_message(&message);
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|part| part.chars().count() <= TELEGRAM_MESSAGE_LIMIT));
+        assert_eq!(parts.join("\n").replace('\n', ""), message.replace('\n', ""));
+    }
+}
@@ -0,0 +1,177 @@
+use serde::Deserialize;
+
+/// A part of a report a chat can ask for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportOutput {
+    /// Statistics message
+    Summary,
+    /// PNG charts sent as photos
+    Charts,
+    /// Records CSV and per-club CSV
+    Csv,
+    Xlsx,
+    Pdf,
+    /// Typed records; only produced with the `parquet` feature
+    Parquet,
+    /// JSON statistics and NDJSON records
+    Json,
+}
+
+impl ReportOutput {
+    pub const ALL: [ReportOutput; 7] = [
+        ReportOutput::Summary,
+        ReportOutput::Charts,
+        ReportOutput::Csv,
+        ReportOutput::Xlsx,
+        ReportOutput::Pdf,
+        ReportOutput::Parquet,
+        ReportOutput::Json,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|output| output.name() == name.trim().to_lowercase())
+    }
+
+    /// Whether this build can produce the output; Parquet needs the `parquet` feature
+    pub fn is_available(self) -> bool {
+        self != ReportOutput::Parquet || cfg!(feature = "parquet")
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ReportOutput::Summary => "summary",
+            ReportOutput::Charts => "charts",
+            ReportOutput::Csv => "csv",
+            ReportOutput::Xlsx => "xlsx",
+            ReportOutput::Pdf => "pdf",
+            ReportOutput::Parquet => "parquet",
+            ReportOutput::Json => "json",
+        }
+    }
+}
+
+/// Set of report outputs; the report service only produces what is selected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportOutputs {
+    selected: [bool; ReportOutput::ALL.len()],
+}
+
+impl Default for ReportOutputs {
+    /// Everything except the machine-readable JSON and Parquet files, which are asked for
+    /// explicitly
+    fn default() -> Self {
+        Self::none()
+            .with(ReportOutput::Summary)
+            .with(ReportOutput::Charts)
+            .with(ReportOutput::Csv)
+            .with(ReportOutput::Xlsx)
+            .with(ReportOutput::Pdf)
+    }
+}
+
+impl ReportOutputs {
+    pub fn none() -> Self {
+        Self { selected: [false; ReportOutput::ALL.len()] }
+    }
+
+    pub fn with(mut self, output: ReportOutput) -> Self {
+        self.selected[output as usize] = true;
+        self
+    }
+
+    pub fn contains(&self, output: ReportOutput) -> bool {
+        self.selected[output as usize]
+    }
+
+    /// Outputs selected in either set
+    pub fn union(mut self, other: ReportOutputs) -> Self {
+        for output in ReportOutput::ALL {
+            if other.contains(output) {
+                self = self.with(output);
+            }
+        }
+        self
+    }
+
+    /// Whether any report file (as opposed to messages and photos) is selected that this
+    /// build produces
+    pub fn has_files(&self) -> bool {
+        [ReportOutput::Csv, ReportOutput::Xlsx, ReportOutput::Pdf, ReportOutput::Parquet, ReportOutput::Json]
+            .into_iter()
+            .any(|output| self.contains(output) && output.is_available())
+    }
+
+    /// First selected output this build cannot produce
+    pub fn unavailable(&self) -> Option<ReportOutput> {
+        ReportOutput::ALL
+            .into_iter()
+            .find(|output| self.contains(*output) && !output.is_available())
+    }
+
+    /// Whether repeat-visitor analytics are computed: they read the whole visit history, so only
+    /// the detailed outputs (XLSX, PDF, JSON) ask for them and the summary alone goes without
+    pub fn needs_retention(&self) -> bool {
+        [ReportOutput::Xlsx, ReportOutput::Pdf, ReportOutput::Json]
+            .into_iter()
+            .any(|output| self.contains(output))
+    }
+
+    /// Whether club rank changes are computed: they need a second pass over the previous period,
+    /// so only the outputs with the full club table ask for them
+    pub fn needs_rank_changes(&self) -> bool {
+        [ReportOutput::Csv, ReportOutput::Xlsx, ReportOutput::Pdf, ReportOutput::Json]
+            .into_iter()
+            .any(|output| self.contains(output))
+    }
+
+    /// Names of the selected outputs, e.g. "summary, pdf"
+    pub fn names(&self) -> String {
+        ReportOutput::ALL
+            .into_iter()
+            .filter(|output| self.contains(*output))
+            .map(ReportOutput::name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl FromIterator<ReportOutput> for ReportOutputs {
+    fn from_iter<I: IntoIterator<Item = ReportOutput>>(outputs: I) -> Self {
+        outputs.into_iter().fold(Self::none(), ReportOutputs::with)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_outputs() {
+        let outputs: ReportOutputs = ["summary", "PDF"].into_iter().filter_map(ReportOutput::from_name).collect();
+        assert!(outputs.contains(ReportOutput::Summary) && outputs.contains(ReportOutput::Pdf));
+        assert!(!outputs.contains(ReportOutput::Csv));
+        assert_eq!(outputs.names(), "summary, pdf");
+        assert!(outputs.has_files());
+
+        assert!(outputs.needs_retention() && outputs.needs_rank_changes());
+
+        // Summary and charts alone skip the visit history and the previous period
+        let summary = ReportOutputs::none().with(ReportOutput::Summary);
+        assert!(!summary.has_files());
+        assert!(!summary.needs_retention() && !summary.needs_rank_changes());
+        let charts = summary.with(ReportOutput::Charts);
+        assert!(!charts.needs_retention() && !charts.needs_rank_changes());
+        assert!(!summary.with(ReportOutput::Csv).needs_retention());
+        assert!(summary.union(ReportOutputs::default()).contains(ReportOutput::Xlsx));
+        assert!(!ReportOutputs::default().contains(ReportOutput::Json));
+        assert!(!ReportOutputs::default().contains(ReportOutput::Parquet));
+        assert_eq!(ReportOutputs::default().unavailable(), None);
+
+        // Parquet only counts as a file when the build can write it
+        let parquet = ReportOutputs::none().with(ReportOutput::Parquet);
+        assert_eq!(parquet.has_files(), cfg!(feature = "parquet"));
+        assert_eq!(parquet.unavailable().is_some(), !cfg!(feature = "parquet"));
+        assert_eq!(ReportOutput::from_name("week"), None);
+    }
+}
@@ -1,392 +1,878 @@
 use anyhow::Result;
-use log::info;
+use async_stream::try_stream;
+use futures::{pin_mut, Stream, TryStreamExt};
+use log::{error, info, warn};
 use serde_json::Value;
-use std::collections::{HashSet, HashMap};
-
-use crate::config::Config;
-use crate::csv_generator::CsvGenerator;
-use crate::date_utils::{DateRange, Period};
-use crate::nocodb::NocoDBClient;
-use crate::pdf_generator::PdfGenerator;
-
-#[derive(Debug, Clone)]
-pub struct ClubStats {
-    pub club_id: String,
-    pub club_name: String,
-    pub total_generations: usize,
-    pub unique_clients: usize,
-    pub percentage: f64,
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::time::{sleep, Duration};
+
+use crate::archive::write_zip;
+use crate::chart_images::{render_chart_images, ChartImage};
+use crate::club_directory::{ClubDirectory, ClubMap};
+use crate::config::{Config, UnknownClubsMode};
+use crate::csv_generator::{CsvGenerator, CsvReportWriter};
+use crate::date_utils::{parse_record_datetime, DateRange, Period};
+use crate::export_profile::{ExportProfile, DEFAULT_PROFILE};
+use crate::json_export::{write_stats_json, DataSource, NdjsonRecordWriter};
+use crate::retention::{RetentionStats, VisitHistory};
+use crate::mirror::LocalMirror;
+use crate::nocodb::{NocoDBClient, RecordQuery};
+#[cfg(feature = "parquet")]
+use crate::parquet_export::ParquetRecordWriter;
+use crate::pdf_generator::{ChartData, PdfGenerator};
+use crate::privacy::Anonymizer;
+use crate::report_files::{create_request_dir, range_stamp, RequestDir};
+use crate::report_output::{ReportOutput, ReportOutputs};
+use crate::xlsx_generator::XlsxReportWriter;
+use crate::stats::{
+    club_assignment, ClubAssignment, ClubRankCounter, DataQualityStats, ReportStats, StatsCollector,
+    StatsSettings, NETWORK_TOTAL_NAME, UNASSIGNED_CLUB_NAME,
+};
+
+/// Columns needed to calculate summary statistics (the date, update and generation timestamps
+/// are added from the settings)
+const SUMMARY_FIELDS: &[&str] = &[
+    "Id", "status", "club_id", "phone", "text_aura", "aura", "birth_date", "sex", "date_visit",
+];
+
+/// Columns exported to the CSV report (generation timestamps are added from the settings)
+const CSV_FIELDS: &[&str] = &[
+    "Id", "phone", "name", "date_visit", "duration", "club_id",
+    "text_aura", "aura", "birth_date", "sex", "status",
+];
+
+/// How long a report waits for the mirror to catch up with NocoDB before serving what it has;
+/// covers a hanging NocoDB and a long reconciliation holding the sync lock
+const REPORT_SYNC_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Columns needed to rank clubs in the previous period
+const RANK_FIELDS: &[&str] = &["Id", "club_id"];
+
+/// Files and statistics of a generated report; outputs that were not selected are `None`
+pub struct GeneratedReport {
+    /// Records CSV; not produced in the aggregate-only privacy mode
+    pub csv_path: Option<String>,
+    /// Per-club KPI table
+    pub clubs_csv_path: Option<String>,
+    /// Workbook with raw records and statistics sheets
+    pub xlsx_path: Option<String>,
+    pub pdf_path: Option<String>,
+    /// Typed records for analytics; only with the `parquet` feature and not in the
+    /// aggregate-only privacy mode
+    pub parquet_path: Option<String>,
+    /// Statistics with period and data source metadata, for automated ingestion
+    pub json_path: Option<String>,
+    /// Normalized records, one JSON object per line; not produced in the aggregate-only privacy mode
+    pub ndjson_path: Option<String>,
+    /// Key charts as PNG, sent as photos next to the summary; empty if rendering failed
+    pub chart_images: Vec<ChartImage>,
+    pub stats: ReportStats,
+    /// Request directory and base name of the report files, e.g.
+    /// "reports/today_20240101-20240101_20240101-101500123-0/report_20240101"
+    path_prefix: String,
+    /// Removes the report files when the report is dropped after sending
+    _request_dir: RequestDir,
 }
 
-#[derive(Debug, Clone)]
-pub struct ReportStats {
-    pub total_records: usize,
-    pub unique_clients: usize,
-    pub low_aura: usize,      // < 60
-    pub normal_aura: usize,   // 60-80
-    pub high_aura: usize,     // > 80
-    pub club_stats: Vec<ClubStats>,
-    pub avg_generation_time: f64,  // Average time in seconds (only for done status)
-    pub done_count: usize,    // Count of records with status "done"
-    pub process_count: usize, // Count of records with status "process"
-    pub done_percentage: f64, // Percentage of done records
-    pub process_percentage: f64, // Percentage of process records
+impl GeneratedReport {
+    /// Report files of the selected outputs in sending order, each with its caption
+    pub fn files(&self, outputs: &ReportOutputs) -> Vec<(&str, &'static str)> {
+        let mut files = Vec::new();
+        if outputs.contains(ReportOutput::Csv) {
+            files.extend(self.csv_path.as_deref().map(|path| (path, "📄 CSV данные")));
+            files.extend(self.clubs_csv_path.as_deref().map(|path| (path, "🏢 CSV по комплексам")));
+        }
+        if outputs.contains(ReportOutput::Xlsx) {
+            files.extend(self.xlsx_path.as_deref().map(|path| (path, "📗 Excel (XLSX)")));
+        }
+        if outputs.contains(ReportOutput::Pdf) {
+            files.extend(self.pdf_path.as_deref().map(|path| (path, "📊 PDF с графиками")));
+        }
+        if outputs.contains(ReportOutput::Parquet) {
+            files.extend(self.parquet_path.as_deref().map(|path| (path, "🗃 Parquet для аналитики")));
+        }
+        if outputs.contains(ReportOutput::Json) {
+            files.extend(self.json_path.as_deref().map(|path| (path, "🧾 JSON статистика")));
+            files.extend(self.ndjson_path.as_deref().map(|path| (path, "🧾 NDJSON записи")));
+        }
+        files
+    }
+
+    /// Pack the files of the selected outputs into an AES-256 encrypted ZIP for one chat and
+    /// return its path
+    pub fn write_archive(&self, chat_id: i64, password: &str, outputs: &ReportOutputs) -> Result<String> {
+        let files: Vec<&str> = self.files(outputs).into_iter().map(|(path, _)| path).collect();
+        write_zip(&files, &format!("{}_{}.zip", self.path_prefix, chat_id), Some(password))
+    }
+
+    /// Delete the unencrypted report files once every chat that needs them has its archive;
+    /// the charts stay, they carry aggregates only
+    pub fn remove_plain_files(&mut self) {
+        let paths = [
+            self.csv_path.take(),
+            self.clubs_csv_path.take(),
+            self.xlsx_path.take(),
+            self.pdf_path.take(),
+            self.parquet_path.take(),
+            self.json_path.take(),
+            self.ndjson_path.take(),
+        ];
+        remove_files(&paths.into_iter().flatten().collect::<Vec<_>>());
+    }
+}
+
+/// Files of a per-club split export: the network total and one set of files per club
+pub struct ClubSplitReport {
+    /// Network total first, then clubs by name; empty once packed into the archive
+    pub files: Vec<String>,
+    /// Clubs with at least one record
+    pub club_count: usize,
+    /// Period part of the file names, e.g. "20240101-20240131"
+    pub period_stamp: String,
+    /// Request directory holding the files; removed when the report is dropped
+    split_dir: RequestDir,
+}
+
+impl ClubSplitReport {
+    /// Pack the files into one ZIP archive (AES-256 encrypted when a password is given) and
+    /// delete the packed files, so only the archive is left
+    pub fn write_archive(&mut self, password: Option<&str>) -> Result<String> {
+        let files: Vec<&str> = self.files.iter().map(String::as_str).collect();
+        let archive_path =
+            write_zip(&files, &format!("{}/report_split_{}.zip", self.split_dir.path(), self.period_stamp), password)?;
+        remove_files(&std::mem::take(&mut self.files));
+        Ok(archive_path)
+    }
+}
+
+/// Delete report files, logging the ones that could not be removed
+fn remove_files(paths: &[String]) {
+    for path in paths {
+        if let Err(e) = std::fs::remove_file(path) {
+            warn!("Failed to remove report file {}: {}", path, e);
+        }
+    }
+}
+
+/// Accumulators of one file set of a split export
+struct SplitPart<'a> {
+    name: String,
+    csv: Option<CsvReportWriter>,
+    csv_path: String,
+    stats: StatsCollector<'a>,
+    chart_data: ChartData,
+}
+
+impl SplitPart<'_> {
+    fn add_record(&mut self, record: &Value, clubs: &ClubMap) -> Result<()> {
+        self.stats.add_record(record);
+        self.chart_data.add_record(record);
+        if let Some(csv) = &mut self.csv {
+            csv.write_record(record, clubs)?;
+        }
+        Ok(())
+    }
 }
 
 pub struct ReportService {
     nocodb_client: NocoDBClient,
+    club_directory: Arc<ClubDirectory>,
     date_field_name: String,
+    mirror: Option<LocalMirror>,
+    stats_settings: StatsSettings,
+    page_size: usize,
+    mirror_sync_interval_secs: u64,
+    mirror_reconcile_interval_secs: u64,
 }
 
 impl ReportService {
-    pub fn new(config: &Config) -> Self {
+    pub fn new(config: &Config) -> Result<Self> {
         let nocodb_client = NocoDBClient::new(
             config.nocodb_url.clone(),
             config.nocodb_token.clone(),
             config.nocodb_table_id.clone(),
             config.nocodb_clubs_table_id.clone(),
-        );
+            config.nocodb_page_size,
+            config.nocodb_max_concurrent_requests,
+        )?;
+
+        let club_directory = Arc::new(ClubDirectory::new(
+            nocodb_client.clone(),
+            Duration::from_secs(config.clubs_cache_ttl_secs),
+        ));
+
+        let mirror = match &config.mirror_db_path {
+            Some(path) => Some(LocalMirror::open(
+                path,
+                &config.date_field_name,
+                &config.updated_field_name,
+            )?),
+            None => None,
+        };
 
-        Self { 
+        Ok(Self { 
             nocodb_client,
+            club_directory,
             date_field_name: config.date_field_name.clone(),
+            mirror,
+            stats_settings: StatsSettings::from_config(config),
+            page_size: config.nocodb_page_size,
+            mirror_sync_interval_secs: config.mirror_sync_interval_secs,
+            mirror_reconcile_interval_secs: config.mirror_reconcile_interval_secs,
+        })
+    }
+
+    /// Force reload of the club directory
+    pub async fn refresh_clubs(&self) -> Result<Arc<ClubMap>> {
+        self.club_directory.refresh().await
+    }
+
+    /// Keep the local mirror in sync with NocoDB. Returns immediately if the mirror is disabled.
+    pub async fn run_mirror_sync(&self) {
+        let Some(mirror) = &self.mirror else {
+            return;
+        };
+
+        info!(
+            "Mirror sync started. Interval: {}s, reconciliation interval: {}s",
+            self.mirror_sync_interval_secs, self.mirror_reconcile_interval_secs
+        );
+
+        loop {
+            if let Err(e) = mirror.sync(&self.nocodb_client).await {
+                error!("Mirror sync failed: {}", e);
+            }
+
+            match mirror.is_reconcile_due(self.mirror_reconcile_interval_secs).await {
+                Ok(true) => {
+                    if let Err(e) = mirror.reconcile(&self.nocodb_client).await {
+                        error!("Mirror reconciliation failed: {}", e);
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => error!("Failed to read mirror state: {}", e),
+            }
+
+            sleep(Duration::from_secs(self.mirror_sync_interval_secs)).await;
         }
     }
 
-    /// Generate full report (CSV + PDF) for a given period
+    /// Generate the selected outputs of a report (CSV and clubs CSV, XLSX, PDF, charts, JSON and
+    /// NDJSON, and with the `parquet` feature Parquet) for a given period; statistics are always
+    /// computed. The records CSV uses the given export profile, personal data in record exports
+    /// follows the anonymizer.
     pub async fn generate_report(
         &self,
         period: Period,
         output_dir: &str,
-    ) -> Result<(String, String, ReportStats)> {
+        csv_profile: &ExportProfile,
+        anonymizer: &Anonymizer,
+        outputs: &ReportOutputs,
+    ) -> Result<GeneratedReport> {
         let date_range = period.get_date_range();
-        info!("Generating report for period: {}", date_range.label);
+        info!("Generating report for period: {} (outputs: {})", date_range.label, outputs.names());
 
-        // Fetch club names mapping
-        let club_names = self.nocodb_client.fetch_club_names().await?;
-        info!("Loaded {} club names", club_names.len());
+        // Club directory (cached)
+        let clubs = self.club_directory.get().await?;
 
-        // Fetch data from NocoDB
-        let data = self.fetch_data_for_period(&date_range).await?;
+        // Every request gets its own directory, so concurrent reports never overwrite each other
+        let request_dir = create_request_dir(output_dir, &period, &date_range, None)?;
+        let suffix = self.get_filename_suffix(&date_range);
+        let path_prefix = format!("{}/report_{}", request_dir.path(), suffix);
+        let csv_filename = self.csv_filename(request_dir.path(), &date_range, csv_profile);
+        let clubs_csv_filename = format!("{}/report_clubs_{}.csv", request_dir.path(), suffix);
+        let xlsx_filename = format!("{}.xlsx", path_prefix);
+        let pdf_filename = format!("{}.pdf", path_prefix);
+        let json_filename = format!("{}.json", path_prefix);
+        let ndjson_filename = format!("{}.ndjson", path_prefix);
+        #[cfg(feature = "parquet")]
+        let parquet_filename = format!("{}.parquet", path_prefix);
 
-        if data.is_empty() {
+        // Feed every page into statistics and the selected writers as it arrives
+        let exports_records = anonymizer.mode().exports_records();
+        let mut stats = StatsCollector::new(&clubs, &self.stats_settings);
+        let mut csv_writer = if outputs.contains(ReportOutput::Csv) && exports_records {
+            Some(CsvReportWriter::create(&csv_filename, csv_profile, &self.stats_settings, anonymizer)?)
+        } else {
+            None
+        };
+        let mut ndjson_writer = if outputs.contains(ReportOutput::Json) && exports_records {
+            Some(NdjsonRecordWriter::create(&ndjson_filename, &self.stats_settings, anonymizer)?)
+        } else {
+            None
+        };
+        #[cfg(feature = "parquet")]
+        let mut parquet_writer = if outputs.contains(ReportOutput::Parquet) && exports_records {
+            Some(ParquetRecordWriter::create(&parquet_filename, &self.stats_settings, anonymizer)?)
+        } else {
+            None
+        };
+        let mut xlsx_writer = if outputs.contains(ReportOutput::Xlsx) {
+            Some(XlsxReportWriter::create(
+                &xlsx_filename,
+                self.stats_settings.phones.clone(),
+                self.stats_settings.aura_buckets.clone(),
+                anonymizer,
+            )?)
+        } else {
+            None
+        };
+        let with_charts = outputs.contains(ReportOutput::Pdf) || outputs.contains(ReportOutput::Charts);
+        let mut chart_data = ChartData::new(&self.date_field_name);
+
+        // Record columns are only fetched when a record export is produced
+        let generation_fields = self.generation_fields();
+        let chart_fields = self.chart_fields();
+        let mut field_sets: Vec<&[&str]> = vec![SUMMARY_FIELDS];
+        let writes_records = csv_writer.is_some() || ndjson_writer.is_some() || xlsx_writer.is_some();
+        #[cfg(feature = "parquet")]
+        let writes_records = writes_records || parquet_writer.is_some();
+        if writes_records {
+            field_sets.extend([CSV_FIELDS, &generation_fields[..]]);
+        }
+        if with_charts {
+            field_sets.push(&chart_fields[..]);
+        }
+        let pages = self.stream_data_for_period(&date_range, &field_sets);
+        pin_mut!(pages);
+
+        let mut record_count = 0;
+        while let Some(page) = pages.try_next().await? {
+            for record in &page {
+                stats.add_record(record);
+                if self.is_record_included(record, &clubs) {
+                    if let Some(csv_writer) = &mut csv_writer {
+                        csv_writer.write_record(record, &clubs)?;
+                    }
+                    if let Some(ndjson_writer) = &mut ndjson_writer {
+                        ndjson_writer.write_record(record, &clubs)?;
+                    }
+                    #[cfg(feature = "parquet")]
+                    if let Some(parquet_writer) = &mut parquet_writer {
+                        parquet_writer.write_record(record, &clubs)?;
+                    }
+                    if let Some(xlsx_writer) = &mut xlsx_writer {
+                        xlsx_writer.write_record(record, &clubs)?;
+                    }
+                    if with_charts {
+                        chart_data.add_record(record);
+                    }
+                }
+            }
+            record_count += page.len();
+        }
+
+        if record_count == 0 {
             info!("No data found for the period");
         }
 
-        // Calculate statistics
-        let stats = self.calculate_stats(&data, &club_names);
+        let mut stats = stats.finish();
+        if outputs.needs_retention() {
+            match self.retention_stats(&date_range, &clubs).await {
+                Ok(retention) => stats.retention = Some(retention),
+                Err(reason) => stats.retention_unavailable = Some(reason),
+            }
+        }
+        if outputs.needs_rank_changes() {
+            if let Some(previous_ranks) = self.previous_club_ranks(&date_range, &clubs).await {
+                stats.apply_previous_ranks(&previous_ranks);
+            }
+        }
 
-        // Generate CSV with club names
-        let csv_filename = format!("{}/report_{}.csv", output_dir, self.get_filename_suffix(&date_range));
-        let csv_path = CsvGenerator::generate(&data, &csv_filename, &club_names)?;
-        info!("CSV report generated: {}", csv_path);
+        let csv_path = csv_writer.map(|writer| writer.finish()).transpose()?;
+        if let Some(path) = &csv_path {
+            info!("CSV report generated: {}", path);
+        } else if outputs.contains(ReportOutput::Csv) {
+            info!("Records CSV skipped in the aggregate-only privacy mode");
+        }
 
-        // Generate PDF
-        let pdf_filename = format!("{}/report_{}.pdf", output_dir, self.get_filename_suffix(&date_range));
-        let pdf_path = PdfGenerator::generate(&data, &pdf_filename)?;
-        info!("PDF report generated: {}", pdf_path);
+        let clubs_csv_path = if outputs.contains(ReportOutput::Csv) {
+            let path = CsvGenerator::generate_club_stats(&stats, &clubs_csv_filename)?;
+            info!("Clubs CSV report generated: {}", path);
+            Some(path)
+        } else {
+            None
+        };
 
-        Ok((csv_path, pdf_path, stats))
-    }
+        let xlsx_path = xlsx_writer.map(|writer| writer.finish(&stats, &date_range.label)).transpose()?;
+        if let Some(path) = &xlsx_path {
+            info!("XLSX report generated: {}", path);
+        }
 
-    /// Generate only CSV report
-    pub async fn generate_csv_report(&self, period: Period, output_dir: &str) -> Result<String> {
-        let date_range = period.get_date_range();
-        info!("Generating CSV report for period: {}", date_range.label);
-
-        let club_names = self.nocodb_client.fetch_club_names().await?;
-        let data = self.fetch_data_for_period(&date_range).await?;
-        let csv_filename = format!("{}/report_{}.csv", output_dir, self.get_filename_suffix(&date_range));
-        let csv_path = CsvGenerator::generate(&data, &csv_filename, &club_names)?;
-        
-        Ok(csv_path)
+        let pdf_path = if outputs.contains(ReportOutput::Pdf) {
+            let path = PdfGenerator::generate(&chart_data, &stats, &pdf_filename)?;
+            info!("PDF report generated: {}", path);
+            Some(path)
+        } else {
+            None
+        };
+
+        // Charts are a convenience next to the summary and must not fail the report
+        let chart_images = if outputs.contains(ReportOutput::Charts) {
+            render_chart_images(&chart_data, &stats, &path_prefix).unwrap_or_else(|e| {
+                error!("Failed to render chart images: {}", e);
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+
+        #[cfg(feature = "parquet")]
+        let parquet_path = parquet_writer.map(|writer| writer.finish()).transpose()?;
+        #[cfg(not(feature = "parquet"))]
+        let parquet_path = None;
+        if let Some(path) = &parquet_path {
+            info!("Parquet records generated: {}", path);
+        }
+
+        let json_path = if outputs.contains(ReportOutput::Json) {
+            let data_source = self.data_source().await;
+            let path = write_stats_json(&stats, &date_range, &data_source, &json_filename)?;
+            info!("JSON statistics generated: {}", path);
+            Some(path)
+        } else {
+            None
+        };
+
+        let ndjson_path = ndjson_writer.map(|writer| writer.finish()).transpose()?;
+        if let Some(path) = &ndjson_path {
+            info!("NDJSON records generated: {}", path);
+        }
+
+        Ok(GeneratedReport {
+            csv_path,
+            clubs_csv_path,
+            xlsx_path,
+            pdf_path,
+            parquet_path,
+            json_path,
+            ndjson_path,
+            chart_images,
+            stats,
+            path_prefix,
+            _request_dir: request_dir,
+        })
     }
 
-    /// Generate only PDF report
-    pub async fn generate_pdf_report(&self, period: Period, output_dir: &str) -> Result<String> {
+    /// Generate one records CSV (and optionally one PDF) per club plus the network total, named
+    /// by period and club. In the aggregate-only privacy mode only the PDFs are produced.
+    pub async fn generate_club_split(
+        &self,
+        period: Period,
+        output_dir: &str,
+        csv_profile: &ExportProfile,
+        anonymizer: &Anonymizer,
+        with_pdf: bool,
+    ) -> Result<ClubSplitReport> {
         let date_range = period.get_date_range();
-        info!("Generating PDF report for period: {}", date_range.label);
+        info!("Generating per-club split export for period: {}", date_range.label);
+
+        let clubs = self.club_directory.get().await?;
+        let period_stamp = range_stamp(&date_range);
+        let split_dir = create_request_dir(output_dir, &period, &date_range, Some("split"))?;
 
-        let data = self.fetch_data_for_period(&date_range).await?;
-        let pdf_filename = format!("{}/report_{}.pdf", output_dir, self.get_filename_suffix(&date_range));
-        let pdf_path = PdfGenerator::generate(&data, &pdf_filename)?;
-        
-        Ok(pdf_path)
+        let exports_records = anonymizer.mode().exports_records();
+        let new_part = |name: &str, club_id: Option<&str>| -> Result<SplitPart> {
+            let csv_path = format!("{}/{}_{}.csv", split_dir.path(), period_stamp, split_file_name(name, club_id));
+            let csv = if exports_records {
+                Some(CsvReportWriter::create(&csv_path, csv_profile, &self.stats_settings, anonymizer)?)
+            } else {
+                None
+            };
+            Ok(SplitPart {
+                name: name.to_string(),
+                csv,
+                csv_path,
+                stats: StatsCollector::new(&clubs, &self.stats_settings),
+                chart_data: ChartData::new(&self.date_field_name),
+            })
+        };
+
+        let mut network = new_part(NETWORK_TOTAL_NAME, None)?;
+        // Keyed by club id (`None` for records without a known club), so clubs sharing a name
+        // keep separate files
+        let mut club_parts: HashMap<Option<String>, SplitPart> = HashMap::new();
+
+        let generation_fields = self.generation_fields();
+        let chart_fields = self.chart_fields();
+        let field_sets = [SUMMARY_FIELDS, CSV_FIELDS, &generation_fields, &chart_fields];
+        let pages = self.stream_data_for_period(&date_range, &field_sets);
+        pin_mut!(pages);
+        while let Some(page) = pages.try_next().await? {
+            for record in &page {
+                if !self.is_record_included(record, &clubs) {
+                    continue;
+                }
+                network.add_record(record, &clubs)?;
+
+                let club_id = match record.as_object().map(|obj| club_assignment(obj, &clubs)) {
+                    Some(ClubAssignment::Known(club_id)) => Some(club_id.to_string()),
+                    _ => None,
+                };
+                if !club_parts.contains_key(&club_id) {
+                    let part = match &club_id {
+                        Some(club_id) => new_part(&clubs[club_id].name, Some(club_id))?,
+                        None => new_part(UNASSIGNED_CLUB_NAME, None)?,
+                    };
+                    club_parts.insert(club_id.clone(), part);
+                }
+                if let Some(part) = club_parts.get_mut(&club_id) {
+                    part.add_record(record, &clubs)?;
+                }
+            }
+        }
+
+        let club_count = club_parts.len();
+        let mut parts: Vec<SplitPart> = club_parts.into_values().collect();
+        parts.sort_by(|a, b| (&a.name, &a.csv_path).cmp(&(&b.name, &b.csv_path)));
+        parts.insert(0, network);
+
+        let mut files = Vec::new();
+        for part in parts {
+            if let Some(csv) = part.csv {
+                csv.finish()?;
+                files.push(part.csv_path.clone());
+            }
+            if with_pdf || !exports_records {
+                let pdf_path = format!("{}.pdf", part.csv_path.trim_end_matches(".csv"));
+                files.push(PdfGenerator::generate(&part.chart_data, &part.stats.finish(), &pdf_path)?);
+            }
+        }
+
+        info!("Split export generated: {} files for {} clubs", files.len(), club_count);
+        Ok(ClubSplitReport {
+            files,
+            club_count,
+            period_stamp,
+            split_dir,
+        })
     }
 
-    /// Fetch data from NocoDB filtered by date range
-    async fn fetch_data_for_period(&self, date_range: &DateRange) -> Result<Vec<Value>> {
-        info!("Fetching records for period: {}", date_range.label);
-        
-        // Use NocoDB server-side filtering with proper date format
-        // Format: (CreatedAt1,ge,exactDate,YYYY-MM-DD HH:MM)~and(CreatedAt1,le,exactDate,YYYY-MM-DD HH:MM)
-        // Using ge (>=) and le (<=) to include boundary dates
-        let start_str = date_range.start.format("%Y-%m-%d %H:%M").to_string();
-        let end_str = date_range.end.format("%Y-%m-%d %H:%M").to_string();
-        
-        let filter = format!(
-            "({},ge,exactDate,{})~and({},le,exactDate,{})",
-            self.date_field_name, start_str, self.date_field_name, end_str
-        );
-        
-        info!("Using filter: {}", filter);
-        
-        match self.nocodb_client.fetch_records_filtered(&filter).await {
-            Ok(records) => {
-                info!("Fetched {} records for period: {}", records.len(), date_range.label);
-                Ok(records)
-            },
+    /// Calculate repeat-visitor and retention metrics from the full visit history in the local
+    /// mirror. Without a synced mirror they are skipped: downloading the whole table from NocoDB
+    /// for every report is too expensive.
+    ///
+    /// Returns the reason shown in the report instead (and logs it) if the history is not
+    /// available, so the rest of the report is still delivered.
+    async fn retention_stats(&self, date_range: &DateRange, clubs: &ClubMap) -> Result<RetentionStats, String> {
+        let Some(mirror) = &self.mirror else {
+            info!("Retention analytics skipped: the local mirror is disabled");
+            return Err("нужна локальная копия базы (MIRROR_DB_PATH)".to_string());
+        };
+        match mirror.is_ready().await {
+            Ok(true) => {}
+            Ok(false) => {
+                info!("Retention analytics skipped: the local mirror is not fully synced yet");
+                return Err("локальная копия базы еще не синхронизирована".to_string());
+            }
             Err(e) => {
-                info!("Server-side filtering failed ({}), fetching all records and filtering client-side", e);
-                let all_records = self.nocodb_client.fetch_records().await?;
-                let total_count = all_records.len();
-                
-                // Client-side filtering by date
-                let filtered_records: Vec<Value> = all_records
-                    .into_iter()
-                    .filter(|record| {
-                        if let Some(obj) = record.as_object() {
-                            // Use configured date field name
-                            let created_at = obj.get(&self.date_field_name)
-                                .and_then(|v| v.as_str());
-                            
-                            if let Some(date_str) = created_at {
-                                // Try to parse the date
-                                if let Ok(dt) = chrono::NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S") {
-                                    let date = chrono::DateTime::<chrono::Utc>::from_naive_utc_and_offset(dt, chrono::Utc);
-                                    return date >= date_range.start && date <= date_range.end;
-                                } else if let Ok(dt) = chrono::DateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S%z") {
-                                    return dt.with_timezone(&chrono::Utc) >= date_range.start && 
-                                           dt.with_timezone(&chrono::Utc) <= date_range.end;
-                                }
-                            }
-                        }
-                        false
-                    })
-                    .collect();
-                
-                info!("Filtered {} records from {} total for period: {}", 
-                    filtered_records.len(), total_count, date_range.label);
-                Ok(filtered_records)
+                error!("Failed to read mirror state for retention analytics: {}", e);
+                return Err("не удалось прочитать локальную копию базы".to_string());
+            }
+        }
+
+        // Same club filter as the rest of the report
+        let known_clubs = (self.stats_settings.unknown_clubs_mode == UnknownClubsMode::Exclude)
+            .then(|| clubs.keys().cloned().collect());
+        let history = VisitHistory::new(self.stats_settings.phones.clone());
+        match mirror.load_visit_history(history, date_range.end, known_clubs).await {
+            Ok(history) => Ok(history.finish(date_range)),
+            Err(e) => {
+                error!("Failed to load visit history for retention analytics: {}", e);
+                Err("не удалось загрузить историю визитов".to_string())
             }
         }
     }
 
-    /// Calculate statistics from report data
-    pub fn calculate_stats(&self, data: &[Value], club_names: &HashMap<String, String>) -> ReportStats {
-        let mut unique_phones = HashSet::new();
-        let mut low_aura = 0;
-        let mut normal_aura = 0;
-        let mut high_aura = 0;
-        
-        // Statistics by club
-        let mut club_generations: HashMap<String, usize> = HashMap::new();
-        let mut club_unique_phones: HashMap<String, HashSet<String>> = HashMap::new();
-        
-        // Generation time tracking (only for done status)
-        let mut total_generation_time = 0.0;
-        let mut generation_time_count = 0;
-        
-        // Status tracking
-        let mut done_count = 0;
-        let mut process_count = 0;
-
-        for record in data {
-            if let Some(obj) = record.as_object() {
-                // Get status
-                let status = obj.get("status").and_then(|v| v.as_str()).unwrap_or("");
-                
-                // Count statuses
-                if status == "done" {
-                    done_count += 1;
-                } else if status == "process" {
-                    process_count += 1;
-                }
-                
-                // Calculate generation time ONLY for done status
-                if status == "done" {
-                    if let (Some(created), Some(updated)) = (
-                        obj.get("CreatedAt").or_else(|| obj.get("CreatedAt1")).and_then(|v| v.as_str()),
-                        obj.get("UpdatedAt").or_else(|| obj.get("UpdatedAt1")).and_then(|v| v.as_str())
-                    ) {
-                        if let (Ok(created_time), Ok(updated_time)) = (
-                            chrono::DateTime::parse_from_str(created, "%Y-%m-%d %H:%M:%S%z")
-                                .or_else(|_| chrono::NaiveDateTime::parse_from_str(created, "%Y-%m-%d %H:%M:%S")
-                                    .map(|dt| chrono::DateTime::<chrono::Utc>::from_naive_utc_and_offset(dt, chrono::Utc).fixed_offset())),
-                            chrono::DateTime::parse_from_str(updated, "%Y-%m-%d %H:%M:%S%z")
-                                .or_else(|_| chrono::NaiveDateTime::parse_from_str(updated, "%Y-%m-%d %H:%M:%S")
-                                    .map(|dt| chrono::DateTime::<chrono::Utc>::from_naive_utc_and_offset(dt, chrono::Utc).fixed_offset()))
-                        ) {
-                            let duration = updated_time.signed_duration_since(created_time);
-                            total_generation_time += duration.num_milliseconds() as f64 / 1000.0;
-                            generation_time_count += 1;
-                        }
+    /// Club ranks of the period of the same length right before the given one.
+    ///
+    /// Returns `None` (and logs the error) if the records could not be loaded; rank
+    /// changes are then left out of the report.
+    async fn previous_club_ranks(&self, date_range: &DateRange, clubs: &ClubMap) -> Option<HashMap<String, usize>> {
+        let previous_range = date_range.previous();
+        let mut ranks = ClubRankCounter::new(clubs, self.stats_settings.unknown_clubs_mode);
+        let pages = self.stream_data_for_period(&previous_range, &[RANK_FIELDS]);
+        pin_mut!(pages);
+        loop {
+            match pages.try_next().await {
+                Ok(Some(page)) => {
+                    for record in &page {
+                        ranks.add_record(record);
                     }
                 }
-                
-                // Check if club_id exists and is in club_names table
-                let club_id_opt = obj.get("club_id").and_then(|v| v.as_str());
-                let has_valid_club = club_id_opt.map(|id| club_names.contains_key(id)).unwrap_or(false);
-                
-                // Skip records without valid club_id
-                if !has_valid_club {
-                    continue;
-                }
-                
-                // Count unique clients by phone
-                let phone_str = if let Some(phone) = obj.get("phone") {
-                    match phone {
-                        Value::Number(n) => n.to_string(),
-                        Value::String(s) => s.clone(),
-                        _ => String::new(),
-                    }
-                } else {
-                    String::new()
-                };
-                
-                if !phone_str.is_empty() {
-                    unique_phones.insert(phone_str.clone());
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Failed to load the previous period for club rank changes: {}", e);
+                    return None;
                 }
+            }
+        }
 
-                // Parse aura percent from text_aura field
-                if let Some(percent_value) = Self::extract_percent_value(obj) {
-                    if percent_value < 60.0 {
-                        low_aura += 1;
-                    } else if percent_value <= 80.0 {
-                        normal_aura += 1;
-                    } else {
-                        high_aura += 1;
-                    }
-                }
-                
-                // Count by club_id
-                if let Some(club_id) = club_id_opt {
-                    *club_generations.entry(club_id.to_string()).or_insert(0) += 1;
-                    
-                    if !phone_str.is_empty() {
-                        club_unique_phones
-                            .entry(club_id.to_string())
-                            .or_insert_with(HashSet::new)
-                            .insert(phone_str.clone());
-                    }
-                }
+        Some(ranks.finish())
+    }
+
+    /// Run the data quality audit for a given period without generating files
+    pub async fn audit_data_quality(&self, period: Period) -> Result<DataQualityStats> {
+        let date_range = period.get_date_range();
+        info!("Running data quality audit for period: {}", date_range.label);
+
+        let clubs = self.club_directory.get().await?;
+        let mut stats = StatsCollector::new(&clubs, &self.stats_settings);
+        let pages = self.stream_data_for_period(&date_range, &[SUMMARY_FIELDS]);
+        pin_mut!(pages);
+        while let Some(page) = pages.try_next().await? {
+            for record in &page {
+                stats.add_record(record);
             }
         }
 
-        // Calculate club statistics - only for clubs that exist in club_names
-        let total_records: usize = club_generations.values().sum();
-        let mut club_stats: Vec<ClubStats> = club_generations
+        Ok(stats.finish().data_quality)
+    }
+
+    /// Source that record streams read from: the local mirror once it is fully synced,
+    /// NocoDB otherwise
+    async fn data_source(&self) -> DataSource {
+        match &self.mirror {
+            Some(mirror) if matches!(mirror.is_ready().await, Ok(true)) => DataSource::Mirror {
+                path: mirror.path().to_string(),
+            },
+            _ => DataSource::Nocodb {
+                url: self.nocodb_client.records_url(),
+            },
+        }
+    }
+
+    /// Build the list of columns to request, always including the date, update and generation
+    /// time fields
+    fn query_fields(&self, field_sets: &[&[&str]]) -> Vec<String> {
+        let mut fields: Vec<String> = Vec::new();
+        let all = field_sets
             .iter()
-            .filter(|(club_id, _)| club_names.contains_key(*club_id))
-            .map(|(club_id, &generations)| {
-                let unique_clients = club_unique_phones
-                    .get(club_id)
-                    .map(|phones| phones.len())
-                    .unwrap_or(0);
-                
-                let percentage = if total_records > 0 {
-                    (generations as f64 / total_records as f64) * 100.0
+            .flat_map(|set| set.iter().copied())
+            .chain([
+                self.date_field_name.as_str(),
+                self.stats_settings.data_quality.updated_field_name.as_str(),
+                self.stats_settings.generation_start_field.as_str(),
+                self.stats_settings.generation_end_field.as_str(),
+            ]);
+        for field in all {
+            if !fields.iter().any(|f| f == field) {
+                fields.push(field.to_string());
+            }
+        }
+        fields
+    }
+
+    /// Stream pages of records for the date range.
+    ///
+    /// When the local mirror is enabled it is brought up to date first and then
+    /// used as the data source; if NocoDB is unreachable or the sync takes longer than
+    /// [`REPORT_SYNC_TIMEOUT`], the report is served from the last successful sync.
+    fn stream_data_for_period<'a>(
+        &'a self,
+        date_range: &'a DateRange,
+        field_sets: &'a [&'a [&'a str]],
+    ) -> impl Stream<Item = Result<Vec<Value>>> + 'a {
+        try_stream! {
+            let mut local_mirror = None;
+            if let Some(mirror) = &self.mirror {
+                match tokio::time::timeout(REPORT_SYNC_TIMEOUT, mirror.sync(&self.nocodb_client)).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => warn!("Mirror sync failed, serving data from the last successful sync: {}", e),
+                    Err(_) => warn!(
+                        "Mirror sync did not finish within {}s, serving data from the last successful sync",
+                        REPORT_SYNC_TIMEOUT.as_secs()
+                    ),
+                }
+                if mirror.is_ready().await? {
+                    local_mirror = Some(mirror);
                 } else {
-                    0.0
-                };
-                
-                ClubStats {
-                    club_id: club_id.clone(),
-                    club_name: club_names.get(club_id).cloned().unwrap_or_else(|| club_id.clone()),
-                    total_generations: generations,
-                    unique_clients,
-                    percentage,
+                    info!("Local mirror is not fully synced yet, querying NocoDB");
                 }
-            })
-            .collect();
-        
-        // Sort by total_generations descending
-        club_stats.sort_by(|a, b| b.total_generations.cmp(&a.total_generations));
-        
-        let avg_generation_time = if generation_time_count > 0 {
-            total_generation_time / generation_time_count as f64
-        } else {
-            0.0
-        };
-        
-        // Calculate percentages for statuses
-        let total_with_status = done_count + process_count;
-        let done_percentage = if total_with_status > 0 {
-            (done_count as f64 / total_with_status as f64) * 100.0
-        } else {
-            0.0
-        };
-        let process_percentage = if total_with_status > 0 {
-            (process_count as f64 / total_with_status as f64) * 100.0
-        } else {
-            0.0
-        };
+            }
 
-        ReportStats {
-            total_records,
-            unique_clients: unique_phones.len(),
-            low_aura,
-            normal_aura,
-            high_aura,
-            club_stats,
-            avg_generation_time,
-            done_count,
-            process_count,
-            done_percentage,
-            process_percentage,
+            if let Some(mirror) = local_mirror {
+                let pages = mirror.stream_range(date_range, self.page_size);
+                pin_mut!(pages);
+                while let Some(page) = pages.try_next().await? {
+                    yield page;
+                }
+            } else {
+                let pages = self.stream_remote_for_period(date_range, field_sets);
+                pin_mut!(pages);
+                while let Some(page) = pages.try_next().await? {
+                    yield page;
+                }
+            }
         }
     }
 
-    /// Extract percent value from text_aura field
-    fn extract_percent_value(record: &serde_json::Map<String, Value>) -> Option<f64> {
-        // Try text_aura field first
-        if let Some(text_aura) = record.get("text_aura") {
-            if let Some(aura_obj) = text_aura.as_object() {
-                if let Some(percent) = aura_obj.get("percent") {
-                    if let Some(percent_str) = percent.as_str() {
-                        // Parse "90%" to 90.0
-                        let cleaned = percent_str.trim().trim_end_matches('%');
-                        return cleaned.parse::<f64>().ok();
-                    } else if let Some(percent_num) = percent.as_f64() {
-                        return Some(percent_num);
+    /// Stream pages of records from NocoDB filtered by date range, requesting only the given columns.
+    ///
+    /// If server-side filtering fails on the first request, falls back to streaming
+    /// the whole table and filtering by date on the client.
+    fn stream_remote_for_period<'a>(
+        &'a self,
+        date_range: &'a DateRange,
+        field_sets: &'a [&'a [&'a str]],
+    ) -> impl Stream<Item = Result<Vec<Value>>> + 'a {
+        try_stream! {
+            info!("Fetching records for period: {}", date_range.label);
+            
+            // Use NocoDB server-side filtering with proper date format
+            // Format: (CreatedAt1,ge,exactDate,YYYY-MM-DD HH:MM)~and(CreatedAt1,le,exactDate,YYYY-MM-DD HH:MM)
+            // Using ge (>=) and le (<=) to include boundary dates
+            let start_str = date_range.start.format("%Y-%m-%d %H:%M").to_string();
+            let end_str = date_range.end.format("%Y-%m-%d %H:%M").to_string();
+            
+            let filter = format!(
+                "({},ge,exactDate,{})~and({},le,exactDate,{})",
+                self.date_field_name, start_str, self.date_field_name, end_str
+            );
+            
+            info!("Using filter: {}", filter);
+
+            let query = RecordQuery {
+                filter: Some(filter),
+                fields: self.query_fields(field_sets),
+                // Stable ordering keeps offset pagination consistent between pages
+                sort: vec![self.date_field_name.clone(), "Id".to_string()],
+            };
+
+            let pages = self.nocodb_client.stream_records(&query);
+            pin_mut!(pages);
+
+            match pages.try_next().await {
+                Ok(first_page) => {
+                    let mut total_count = 0;
+                    if let Some(first_page) = first_page {
+                        total_count += first_page.len();
+                        yield first_page;
                     }
-                }
-            } else if let Some(aura_str) = text_aura.as_str() {
-                if !aura_str.is_empty() {
-                    if let Ok(parsed) = serde_json::from_str::<Value>(aura_str) {
-                        if let Some(obj) = parsed.as_object() {
-                            if let Some(percent) = obj.get("percent") {
-                                if let Some(percent_str) = percent.as_str() {
-                                    let cleaned = percent_str.trim().trim_end_matches('%');
-                                    return cleaned.parse::<f64>().ok();
-                                } else if let Some(percent_num) = percent.as_f64() {
-                                    return Some(percent_num);
-                                }
-                            }
-                        }
+                    while let Some(page) = pages.try_next().await? {
+                        total_count += page.len();
+                        yield page;
                     }
+                    info!("Fetched {} records for period: {}", total_count, date_range.label);
                 }
-            }
-        }
+                Err(e) => {
+                    info!("Server-side filtering failed ({}), fetching all records and filtering client-side", e);
+                    // A misconfigured column in the field list or the sort fails the same way as
+                    // the filter, so the fallback asks for the plain table
+                    let fallback_query = RecordQuery::default();
+                    let all_pages = self.nocodb_client.stream_records(&fallback_query);
+                    pin_mut!(all_pages);
 
-        // Fallback to aura field
-        if let Some(aura) = record.get("aura") {
-            if let Some(aura_obj) = aura.as_object() {
-                if let Some(percent) = aura_obj.get("percent") {
-                    if let Some(percent_str) = percent.as_str() {
-                        let cleaned = percent_str.trim().trim_end_matches('%');
-                        return cleaned.parse::<f64>().ok();
-                    } else if let Some(percent_num) = percent.as_f64() {
-                        return Some(percent_num);
+                    let mut total_count = 0;
+                    let mut filtered_count = 0;
+                    while let Some(page) = all_pages.try_next().await? {
+                        total_count += page.len();
+                        let filtered: Vec<Value> = page
+                            .into_iter()
+                            .filter(|record| self.is_in_range(record, date_range))
+                            .collect();
+                        filtered_count += filtered.len();
+                        yield filtered;
                     }
+
+                    info!("Filtered {} records from {} total for period: {}", 
+                        filtered_count, total_count, date_range.label);
                 }
             }
         }
+    }
+
+    /// Whether a record goes into CSV and PDF, given the unknown clubs mode
+    fn is_record_included(&self, record: &Value, clubs: &ClubMap) -> bool {
+        if self.stats_settings.unknown_clubs_mode == UnknownClubsMode::Include {
+            return true;
+        }
+        record
+            .as_object()
+            .map(|obj| matches!(club_assignment(obj, clubs), ClubAssignment::Known(_)))
+            .unwrap_or(false)
+    }
 
-        None
+    /// Client-side check that a record's date field falls within the range
+    fn is_in_range(&self, record: &Value, date_range: &DateRange) -> bool {
+        // Use configured date field name
+        record
+            .get(&self.date_field_name)
+            .and_then(|v| v.as_str())
+            .and_then(parse_record_datetime)
+            .map(|date| date >= date_range.start && date <= date_range.end)
+            .unwrap_or(false)
     }
 
     fn get_filename_suffix(&self, date_range: &DateRange) -> String {
         date_range.start.format("%Y%m%d").to_string()
     }
+
+    /// Records CSV path; profiles other than the default one get their name appended
+    fn csv_filename(&self, output_dir: &str, date_range: &DateRange, csv_profile: &ExportProfile) -> String {
+        let suffix = self.get_filename_suffix(date_range);
+        if csv_profile.name == DEFAULT_PROFILE {
+            format!("{}/report_{}.csv", output_dir, suffix)
+        } else {
+            format!("{}/report_{}_{}.csv", output_dir, suffix, csv_profile.name)
+        }
+    }
+
+    /// Columns used by the PDF and PNG charts
+    fn chart_fields(&self) -> [&str; 2] {
+        ["Id", self.date_field_name.as_str()]
+    }
+
+    /// Timestamp fields of the generation time columns
+    fn generation_fields(&self) -> [&str; 2] {
+        [
+            self.stats_settings.generation_start_field.as_str(),
+            self.stats_settings.generation_end_field.as_str(),
+        ]
+    }
+}
+
+/// File name of one part of a split export without the period and extension: the club name
+/// followed by the club id, so clubs sharing a name never share a file, e.g. "Москва_Сити_12";
+/// the network total and unassigned records have no id
+fn split_file_name(name: &str, club_id: Option<&str>) -> String {
+    match club_id {
+        Some(club_id) => format!("{}_{}", file_name_part(name), file_name_part(club_id)),
+        None => file_name_part(name),
+    }
+}
+
+/// Club name usable in a file name: path separators and characters reserved on Windows
+/// are replaced, Cyrillic is kept
+fn file_name_part(name: &str) -> String {
+    let part: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_whitespace() || "/\\:*?\"<>|".contains(c) || c.is_control() { '_' } else { c })
+        .collect();
+    if part.is_empty() {
+        "club".to_string()
+    } else {
+        part
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name_part() {
+        assert_eq!(file_name_part(" Москва Сити "), "Москва_Сити");
+        assert_eq!(file_name_part("A/B: \"C\""), "A_B___C_");
+        assert_eq!(file_name_part("  "), "club");
+    }
+
+    #[test]
+    fn test_split_file_name_keeps_same_name_clubs_apart() {
+        assert_eq!(split_file_name("Москва Сити", Some("12")), "Москва_Сити_12");
+        assert_ne!(split_file_name("Аура", Some("1")), split_file_name("Аура", Some("2")));
+        assert_eq!(split_file_name(NETWORK_TOTAL_NAME, None), file_name_part(NETWORK_TOTAL_NAME));
+    }
 }
@@ -0,0 +1,231 @@
+use chrono::{DateTime, Datelike, Utc};
+use chrono_tz::Europe::Moscow;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+
+use crate::aura::median;
+use crate::date_utils::DateRange;
+use crate::phone::PhoneNormalizer;
+
+/// Number of monthly cohorts shown in the retention matrix
+pub const COHORT_MONTHS: usize = 12;
+
+/// Visit count buckets as (label, lowest count)
+const FREQUENCY_BUCKETS: &[(&str, usize)] = &[("1", 1), ("2", 2), ("3", 3), ("4-5", 4), ("6-10", 6), ("11+", 11)];
+
+/// Days between visits buckets as (label, lowest gap in days)
+const GAP_BUCKETS: &[(&str, i64)] = &[("0-7", 0), ("8-30", 8), ("31-90", 31), ("91-180", 91), ("181+", 181)];
+
+/// Clients who first visited in the same month and how many of them came back later
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CohortRow {
+    /// Cohort month, "YYYY-MM"
+    pub month: String,
+    pub size: usize,
+    /// Share of the cohort (percent) visiting N months after the first visit; index 0 is the first month
+    pub retention: Vec<f64>,
+}
+
+/// Repeat-visitor and retention metrics for the report period
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetentionStats {
+    pub clients_in_period: usize,
+    /// First visit ever is within the period
+    pub new_clients: usize,
+    /// Visited before the period and again within it
+    pub returning_clients: usize,
+    pub returning_percentage: f64,
+    /// Clients of the period by total number of visits up to the period end
+    pub visit_frequency: Vec<(String, usize)>,
+    /// Gaps between consecutive visits that end within the period
+    pub avg_days_between_visits: Option<f64>,
+    pub median_days_between_visits: Option<f64>,
+    pub gap_distribution: Vec<(String, usize)>,
+    /// Last `COHORT_MONTHS` cohorts up to the period end, oldest first
+    pub cohorts: Vec<CohortRow>,
+}
+
+/// Visit dates of every client, keyed on normalized phone
+#[derive(Debug)]
+pub struct VisitHistory {
+    phones: PhoneNormalizer,
+    visits: HashMap<String, Vec<DateTime<Utc>>>,
+}
+
+impl VisitHistory {
+    pub fn new(phones: PhoneNormalizer) -> Self {
+        Self {
+            phones,
+            visits: HashMap::new(),
+        }
+    }
+
+    /// Add a visit; visits without a usable phone are skipped
+    pub fn add_visit(&mut self, phone: &Value, date: DateTime<Utc>) {
+        if let Some(phone) = self.phones.client_key(phone) {
+            self.visits.entry(phone).or_default().push(date);
+        }
+    }
+
+    /// Compute retention metrics for the period from the full visit history
+    pub fn finish(self, period: &DateRange) -> RetentionStats {
+        let end_month = month_index(period.end);
+        let first_cohort = end_month - COHORT_MONTHS as i32 + 1;
+
+        let mut stats = RetentionStats::default();
+        let mut frequency = vec![0; FREQUENCY_BUCKETS.len()];
+        let mut gaps: Vec<i64> = Vec::new();
+        // Cohort month -> (size, clients visiting per month offset)
+        let mut cohorts: HashMap<i32, (usize, Vec<usize>)> = HashMap::new();
+
+        for mut visits in self.visits.into_values() {
+            visits.retain(|date| *date <= period.end);
+            if visits.is_empty() {
+                continue;
+            }
+            visits.sort();
+
+            let first_visit = visits[0];
+            if visits.iter().any(|date| *date >= period.start) {
+                stats.clients_in_period += 1;
+                if first_visit >= period.start {
+                    stats.new_clients += 1;
+                } else {
+                    stats.returning_clients += 1;
+                }
+                let bucket = FREQUENCY_BUCKETS
+                    .iter()
+                    .rposition(|(_, lowest)| visits.len() >= *lowest)
+                    .unwrap_or(0);
+                frequency[bucket] += 1;
+            }
+
+            for pair in visits.windows(2) {
+                if pair[1] >= period.start {
+                    gaps.push((pair[1] - pair[0]).num_days());
+                }
+            }
+
+            let cohort = month_index(first_visit);
+            if cohort >= first_cohort {
+                let months: BTreeSet<i32> = visits.iter().map(|date| month_index(*date)).collect();
+                let (size, active) = cohorts
+                    .entry(cohort)
+                    .or_insert_with(|| (0, vec![0; (end_month - cohort + 1) as usize]));
+                *size += 1;
+                for month in months {
+                    active[(month - cohort) as usize] += 1;
+                }
+            }
+        }
+
+        if stats.clients_in_period > 0 {
+            stats.returning_percentage = stats.returning_clients as f64 / stats.clients_in_period as f64 * 100.0;
+        }
+        stats.visit_frequency = FREQUENCY_BUCKETS
+            .iter()
+            .zip(frequency)
+            .map(|((label, _), count)| (label.to_string(), count))
+            .collect();
+
+        if !gaps.is_empty() {
+            gaps.sort_unstable();
+            stats.avg_days_between_visits = Some(gaps.iter().sum::<i64>() as f64 / gaps.len() as f64);
+            let days: Vec<f64> = gaps.iter().map(|gap| *gap as f64).collect();
+            stats.median_days_between_visits = Some(median(&days));
+        }
+        let mut gap_counts = vec![0; GAP_BUCKETS.len()];
+        for gap in &gaps {
+            let bucket = GAP_BUCKETS.iter().rposition(|(_, lowest)| gap >= lowest).unwrap_or(0);
+            gap_counts[bucket] += 1;
+        }
+        stats.gap_distribution = GAP_BUCKETS
+            .iter()
+            .zip(gap_counts)
+            .map(|((label, _), count)| (label.to_string(), count))
+            .collect();
+
+        let mut cohort_months: Vec<i32> = cohorts.keys().copied().collect();
+        cohort_months.sort_unstable();
+        stats.cohorts = cohort_months
+            .into_iter()
+            .map(|month| {
+                let (size, active) = &cohorts[&month];
+                CohortRow {
+                    month: format!("{:04}-{:02}", month.div_euclid(12), month.rem_euclid(12) + 1),
+                    size: *size,
+                    retention: active.iter().map(|count| *count as f64 / *size as f64 * 100.0).collect(),
+                }
+            })
+            .collect();
+
+        stats
+    }
+}
+
+/// Months since year 0 in Moscow time
+fn month_index(date: DateTime<Utc>) -> i32 {
+    let local = date.with_timezone(&Moscow);
+    local.year() * 12 + local.month0() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    #[test]
+    fn test_new_and_returning_clients() {
+        let mut history = VisitHistory::new(PhoneNormalizer::default());
+        let visits = [
+            (json!("79990000001"), (2024, 1, 10)),
+            (json!(79990000001u64), (2024, 3, 5)),
+            (json!("+7 999 000-00-02"), (2024, 3, 6)),
+            (json!("79990000003"), (2024, 2, 1)),
+            (json!("79990000002"), (2024, 4, 1)),
+        ];
+        for (phone, (year, month, day)) in &visits {
+            history.add_visit(phone, Utc.with_ymd_and_hms(*year, *month, *day, 10, 0, 0).unwrap());
+        }
+
+        let period = DateRange {
+            start: Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2024, 3, 31, 0, 0, 0).unwrap(),
+            label: String::new(),
+        };
+        let stats = history.finish(&period);
+
+        assert_eq!(stats.clients_in_period, 2);
+        assert_eq!(stats.new_clients, 1);
+        assert_eq!(stats.returning_clients, 1);
+        assert_eq!(stats.visit_frequency[0], ("1".to_string(), 1));
+        assert_eq!(stats.visit_frequency[1], ("2".to_string(), 1));
+        assert_eq!(stats.avg_days_between_visits, Some(55.0));
+
+        let january = stats.cohorts.iter().find(|c| c.month == "2024-01").unwrap();
+        assert_eq!(january.size, 1);
+        assert_eq!(january.retention, vec![100.0, 0.0, 100.0]);
+        assert_eq!(stats.cohorts.len(), 3);
+    }
+
+    #[test]
+    fn test_median_gap_of_even_count_averages_middle_gaps() {
+        let mut history = VisitHistory::new(PhoneNormalizer::default());
+        for (phone, days) in [("79990000001", [1, 5]), ("79990000002", [1, 11])] {
+            for day in days {
+                history.add_visit(&json!(phone), Utc.with_ymd_and_hms(2024, 3, day, 10, 0, 0).unwrap());
+            }
+        }
+
+        let period = DateRange {
+            start: Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2024, 3, 31, 0, 0, 0).unwrap(),
+            label: String::new(),
+        };
+        let stats = history.finish(&period);
+
+        assert_eq!(stats.median_days_between_visits, Some(7.0));
+    }
+}
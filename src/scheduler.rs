@@ -1,13 +1,19 @@
 use anyhow::Result;
 use log::{error, info};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use teloxide::prelude::*;
-use teloxide::types::InputFile;
 use tokio::time::{sleep, Duration};
 
+use crate::chart_images::send_chart_images;
 use crate::config::Config;
 use crate::date_utils::{get_moscow_time, is_schedule_time, Period};
-use crate::report_service::ReportService;
+use crate::privacy::PrivacyMode;
+use crate::report_files::input_file;
+use crate::report_message::{format_stats_message, split_message};
+use crate::report_output::{ReportOutput, ReportOutputs};
+use crate::report_service::{GeneratedReport, ReportService};
+use crate::subscription::Subscription;
 
 pub struct Scheduler {
     bot: Bot,
@@ -52,10 +58,11 @@ impl Scheduler {
         }
     }
 
-    /// Send daily reports to all allowed users
+    /// Send daily reports to all subscribed chats, generating one report per CSV profile and
+    /// privacy mode in use
     async fn send_daily_reports(&self) -> Result<()> {
-        if self.config.allowed_user_ids.is_empty() {
-            info!("No allowed users configured. Skipping scheduled reports.");
+        if self.config.subscriptions.is_empty() {
+            info!("No subscriptions configured. Skipping scheduled reports.");
             return Ok(());
         }
 
@@ -63,104 +70,141 @@ impl Scheduler {
         let output_dir = "reports";
         std::fs::create_dir_all(output_dir)?;
 
-        let (csv_path, pdf_path, stats) = self
-            .report_service
-            .generate_report(Period::Yesterday, output_dir)
-            .await?;
+        // One report per profile and privacy mode, with the outputs any of its chats asked for
+        let mut variants: BTreeMap<(&str, PrivacyMode), ReportOutputs> = BTreeMap::new();
+        for subscription in &self.config.subscriptions {
+            let key = (subscription.csv_profile.as_str(), self.config.subscription_privacy(subscription));
+            let outputs = variants.entry(key).or_insert_with(ReportOutputs::none);
+            *outputs = outputs.union(subscription.outputs);
+        }
+
+        for ((profile_name, privacy), outputs) in variants {
+            let Some(csv_profile) = self.config.csv_profiles.get(profile_name) else {
+                error!("Unknown CSV profile {} in subscriptions", profile_name);
+                continue;
+            };
 
-        // Send to all allowed users
-        for user_id in &self.config.allowed_user_ids {
-            let chat_id = ChatId(*user_id);
-            
-            // Build club statistics section
-            let mut club_stats_text = String::new();
-            if !stats.club_stats.is_empty() {
-                club_stats_text.push_str("\n\n📍 <b>Статистика по комплексам:</b>\n");
-                for club_stat in &stats.club_stats {
-                    let escaped_name = club_stat.club_name
-                        .replace("&", "&amp;")
-                        .replace("<", "&lt;")
-                        .replace(">", "&gt;");
-                    club_stats_text.push_str(&format!(
-                        "\n🏢 <i>{}</i>\n   Генераций: <b>{}</b> ({:.1}%)\n   Клиентов: <b>{}</b>",
-                        escaped_name,
-                        club_stat.total_generations,
-                        club_stat.percentage,
-                        club_stat.unique_clients
-                    ));
+            // Every chat subscribed with this profile and privacy mode
+            let subscriptions: Vec<&Subscription> = self
+                .config
+                .subscriptions
+                .iter()
+                .filter(|s| s.csv_profile == profile_name && self.config.subscription_privacy(s) == privacy)
+                .collect();
+
+            // A failed variant only affects its own chats; the others still get their reports
+            let mut report = match self
+                .report_service
+                .generate_report(Period::Yesterday, output_dir, csv_profile, &self.config.anonymizer(privacy), &outputs)
+                .await
+            {
+                Ok(report) => report,
+                Err(e) => {
+                    error!("Failed to generate report for profile {} ({:?}): {}", profile_name, privacy, e);
+                    for subscription in &subscriptions {
+                        if let Err(e) = self
+                            .bot
+                            .send_message(ChatId(subscription.chat_id), "❌ Не удалось сформировать ежедневный отчет")
+                            .await
+                        {
+                            error!("Failed to notify chat {}: {}", subscription.chat_id, e);
+                        }
+                    }
+                    continue;
                 }
-            }
-            
-            // Build generation time section
-            let generation_time_text = if stats.avg_generation_time > 0.0 {
-                format!("\n\n⏱ <b>Среднее время генерации (done):</b> {:.1} сек", stats.avg_generation_time)
-            } else {
-                String::new()
             };
-            
-            // Build status statistics section
-            let status_text = format!(
-                "\n\n📋 <b>Статусы генераций:</b>\n   ✅ Done: <b>{}</b> ({:.1}%)\n   ⏳ Process: <b>{}</b> ({:.1}%)",
-                stats.done_count,
-                stats.done_percentage,
-                stats.process_count,
-                stats.process_percentage
-            );
-            
-            // Send statistics
-            let stats_message = format!(
-                "📊 <b>Ежедневный отчет</b>\n\n\
-                📈 Всего генераций: <b>{}</b>\n\
-                👥 Уникальных клиентов: <b>{}</b>\n\n\
-                🔴 Низкая аура (&lt;60%): <b>{}</b>\n\
-                🟡 Нормальная аура (60-80%): <b>{}</b>\n\
-                🟢 Высокая аура (&gt;80%): <b>{}</b>{}{}{}",
-                stats.total_records,
-                stats.unique_clients,
-                stats.low_aura,
-                stats.normal_aura,
-                stats.high_aura,
-                club_stats_text,
-                generation_time_text,
-                status_text
-            );
-            
-            if let Err(e) = self.bot.send_message(chat_id, stats_message)
-                .parse_mode(teloxide::types::ParseMode::Html)
-                .await {
-                error!("Failed to send stats to user {}: {}", user_id, e);
+
+            // Pack the archives of the encrypted chats first; when no chat takes the files
+            // unpacked, the plaintext copies are deleted before anything is sent
+            let mut archives: HashMap<i64, Option<String>> = HashMap::new();
+            let mut plain_files_needed = false;
+            for subscription in subscriptions.iter().filter(|s| s.outputs.has_files()) {
+                let Some(password) = self.config.subscription_archive_password(subscription) else {
+                    plain_files_needed = true;
+                    continue;
+                };
+                let archive_path = report
+                    .write_archive(subscription.chat_id, &password, &subscription.outputs)
+                    .map_err(|e| error!("Failed to pack report archive for chat {}: {}", subscription.chat_id, e))
+                    .ok();
+                archives.insert(subscription.chat_id, archive_path);
             }
-            
-            match self.send_report_files(chat_id, &csv_path, &pdf_path).await {
-                Ok(_) => info!("Report sent to user {}", user_id),
-                Err(e) => error!("Failed to send report to user {}: {}", user_id, e),
+            if !plain_files_needed {
+                report.remove_plain_files();
+            }
+
+            for subscription in subscriptions {
+                let chat_id = ChatId(subscription.chat_id);
+                let outputs = subscription.outputs;
+
+                if outputs.contains(ReportOutput::Summary) {
+                    let stats_message = format_stats_message("Ежедневный отчет", &report.stats, self.config.leaderboard_size);
+
+                    for part in split_message(&stats_message) {
+                        if let Err(e) = self.bot.send_message(chat_id, part)
+                            .parse_mode(teloxide::types::ParseMode::Html)
+                            .await {
+                            error!("Failed to send stats to chat {}: {}", subscription.chat_id, e);
+                            break;
+                        }
+                    }
+                }
+
+                if outputs.contains(ReportOutput::Charts) {
+                    if let Err(e) = send_chart_images(&self.bot, chat_id, &report.chart_images).await {
+                        error!("Failed to send charts to chat {}: {}", subscription.chat_id, e);
+                    }
+                }
+
+                if !outputs.has_files() {
+                    continue;
+                }
+                let archive_path = match archives.get(&subscription.chat_id) {
+                    Some(Some(archive_path)) => Some(archive_path.as_str()),
+                    // Packing failed and was logged; the files are never sent unencrypted instead
+                    Some(None) => continue,
+                    None => None,
+                };
+                match self
+                    .send_report_files(chat_id, &report, archive_path, &outputs)
+                    .await
+                {
+                    Ok(_) => info!("Report sent to chat {}", subscription.chat_id),
+                    Err(e) => error!("Failed to send report to chat {}: {}", subscription.chat_id, e),
+                }
             }
         }
 
         Ok(())
     }
 
-    /// Send report files to a chat
+    /// Send the files of the selected outputs to a chat, or its encrypted archive when one was packed
     async fn send_report_files(
         &self,
         chat_id: ChatId,
-        csv_path: &str,
-        pdf_path: &str,
+        report: &GeneratedReport,
+        archive_path: Option<&str>,
+        outputs: &ReportOutputs,
     ) -> Result<()> {
         // Send message
         self.bot
             .send_message(chat_id, "📊 Ежедневный отчет за сегодня")
             .await?;
 
-        // Send CSV
-        self.bot
-            .send_document(chat_id, InputFile::file(csv_path))
-            .await?;
+        if let Some(archive_path) = archive_path {
+            self.bot
+                .send_document(chat_id, input_file(archive_path)?)
+                .caption("🔒 Архив с отчетом (AES-256). Пароль выдает администратор")
+                .await?;
+            return Ok(());
+        }
 
-        // Send PDF
-        self.bot
-            .send_document(chat_id, InputFile::file(pdf_path))
-            .await?;
+        for (path, caption) in report.files(outputs) {
+            self.bot
+                .send_document(chat_id, input_file(path)?)
+                .caption(caption)
+                .await?;
+        }
 
         Ok(())
     }
@@ -0,0 +1,850 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Europe::Moscow;
+use serde::Serialize;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet, HashMap};
+
+use crate::aura::{AuraBuckets, AuraCollector, AuraSummary, DailyAura};
+use crate::club_directory::ClubMap;
+use crate::config::{Config, UnknownClubsMode};
+use crate::data_quality::{record_id, record_status, DataQualityAudit, DataQualitySettings, QualityFinding};
+use crate::date_utils::parse_record_datetime;
+use crate::demographics::{DemographicsCollector, DemographicsStats};
+use crate::phone::PhoneNormalizer;
+use crate::retention::RetentionStats;
+use crate::generation_time::{GenerationTimeCollector, GenerationTimeStats};
+
+/// Club id used to group records without a known club
+pub const UNASSIGNED_CLUB_ID: &str = "";
+/// Display name for records without a known club
+pub const UNASSIGNED_CLUB_NAME: &str = "Без комплекса";
+
+/// Name of the all-clubs total in club tables and split exports
+pub const NETWORK_TOTAL_NAME: &str = "Вся сеть";
+
+/// Statuses of successful and in-flight generations; everything else counts as a failure
+const HEALTHY_STATUSES: &[&str] = &["done", "process"];
+
+/// Number of stuck generation ids kept as samples
+const STUCK_SAMPLE_LIMIT: usize = 10;
+
+/// How a record relates to the club directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClubAssignment<'r> {
+    /// `club_id` is present in the clubs table
+    Known(&'r str),
+    /// Record has no `club_id`
+    Missing,
+    /// `club_id` is not in the clubs table
+    Unknown(&'r str),
+}
+
+/// Determine the club assignment of a record
+pub fn club_assignment<'r>(record: &'r serde_json::Map<String, Value>, clubs: &ClubMap) -> ClubAssignment<'r> {
+    match record.get("club_id").and_then(|v| v.as_str()).map(str::trim) {
+        None | Some("") => ClubAssignment::Missing,
+        Some(id) if clubs.contains_key(id) => ClubAssignment::Known(id),
+        Some(id) => ClubAssignment::Unknown(id),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClubStats {
+    pub club_id: String,
+    pub club_name: String,
+    pub city: Option<String>,
+    pub total_generations: usize,
+    pub unique_clients: usize,
+    pub percentage: f64,
+    pub generation_time: Option<GenerationTimeStats>,
+    /// Records per aura bucket, in `ReportStats::aura_buckets` order
+    pub aura_levels: Vec<usize>,
+    pub aura: Option<AuraSummary>,
+    /// Share of the club's records with status "done"
+    pub done_rate: f64,
+    pub generations_per_client: f64,
+    /// Position by number of generations, starting at 1
+    pub rank: usize,
+    /// Positions gained since the previous period (negative when dropped);
+    /// `None` when the club had no generations in the previous period or it was not loaded
+    pub rank_change: Option<i64>,
+}
+
+/// Per-club counters collected while streaming
+#[derive(Debug)]
+struct ClubAccumulator {
+    generations: usize,
+    phones: HashSet<String>,
+    aura_levels: Vec<usize>,
+    aura: AuraCollector,
+    done: usize,
+    generation_times: GenerationTimeCollector,
+}
+
+impl ClubAccumulator {
+    fn new(generation_time_cap_secs: f64, aura_bucket_count: usize) -> Self {
+        Self {
+            generations: 0,
+            phones: HashSet::new(),
+            aura_levels: vec![0; aura_bucket_count],
+            aura: AuraCollector::default(),
+            done: 0,
+            generation_times: GenerationTimeCollector::new(generation_time_cap_secs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportStats {
+    pub total_records: usize,
+    pub unique_clients: usize,
+    /// Bucket set used for `aura_levels` here, in clubs and in demographics
+    pub aura_buckets: AuraBuckets,
+    /// Records per aura bucket
+    pub aura_levels: Vec<usize>,
+    /// Mean, median and spread of all aura percentages
+    pub aura: Option<AuraSummary>,
+    /// Aura summary per day of the period, in date order
+    pub daily_aura: Vec<DailyAura>,
+    /// Generations per day of the period (Moscow time), in date order
+    pub daily_generations: Vec<(NaiveDate, usize)>,
+    pub club_stats: Vec<ClubStats>,
+    pub generation_time: Option<GenerationTimeStats>, // Only for done status
+    pub done_count: usize,    // Count of records with status "done"
+    pub process_count: usize, // Count of records with status "process"
+    pub status_breakdown: StatusBreakdown,
+    pub demographics: DemographicsStats,
+    /// Calculated from the full visit history; `None` when it could not be loaded
+    pub retention: Option<RetentionStats>,
+    /// Why retention analytics are missing although a detailed output asked for them, e.g.
+    /// the local mirror is disabled
+    pub retention_unavailable: Option<String>,
+    pub data_quality: DataQualityStats,
+}
+
+impl ReportStats {
+    /// Club id -> rank; the previous period is ranked with `ClubRankCounter` instead
+    #[cfg(test)]
+    pub fn club_ranks(&self) -> HashMap<String, usize> {
+        self.club_stats
+            .iter()
+            .map(|club| (club.club_id.clone(), club.rank))
+            .collect()
+    }
+
+    /// Fill in `rank_change` of every club from the ranks of the previous period
+    pub fn apply_previous_ranks(&mut self, previous: &HashMap<String, usize>) {
+        for club in &mut self.club_stats {
+            club.rank_change = previous
+                .get(&club.club_id)
+                .map(|previous_rank| *previous_rank as i64 - club.rank as i64);
+        }
+    }
+}
+
+/// Order of the club ranking: more generations first, ties by club name
+fn rank_order(a: (usize, &str), b: (usize, &str)) -> Ordering {
+    b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1))
+}
+
+/// Counts generations per club to rank clubs without collecting the full statistics; gives the
+/// same ranks as `ReportStats::club_ranks` for the same records
+pub struct ClubRankCounter<'a> {
+    clubs: &'a ClubMap,
+    unknown_clubs_mode: UnknownClubsMode,
+    generations: HashMap<String, usize>,
+}
+
+impl<'a> ClubRankCounter<'a> {
+    pub fn new(clubs: &'a ClubMap, unknown_clubs_mode: UnknownClubsMode) -> Self {
+        Self {
+            clubs,
+            unknown_clubs_mode,
+            generations: HashMap::new(),
+        }
+    }
+
+    pub fn add_record(&mut self, record: &Value) {
+        let Some(obj) = record.as_object() else {
+            return;
+        };
+        let club_id = match club_assignment(obj, self.clubs) {
+            ClubAssignment::Known(id) => id,
+            _ if self.unknown_clubs_mode == UnknownClubsMode::Exclude => return,
+            _ => UNASSIGNED_CLUB_ID,
+        };
+        *self.generations.entry(club_id.to_string()).or_insert(0) += 1;
+    }
+
+    /// Club id -> 1-based rank
+    pub fn finish(self) -> HashMap<String, usize> {
+        let name = |club_id: &str| {
+            self.clubs
+                .get(club_id)
+                .map(|club| club.name.as_str())
+                .unwrap_or(UNASSIGNED_CLUB_NAME)
+        };
+        let mut counts: Vec<(&String, usize)> = self.generations.iter().map(|(id, count)| (id, *count)).collect();
+        counts.sort_by(|a, b| rank_order((a.1, name(a.0)), (b.1, name(b.0))));
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, (club_id, _))| (club_id.clone(), i + 1))
+            .collect()
+    }
+}
+
+/// Number of records with a given status
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatusCount {
+    /// Raw status value; empty when the record has no status
+    pub status: String,
+    pub count: usize,
+    /// Share of all records in the report
+    pub percentage: f64,
+}
+
+impl StatusCount {
+    /// Status for display; records without a status are shown as "(пусто)"
+    pub fn label(&self) -> &str {
+        if self.status.is_empty() {
+            "(пусто)"
+        } else {
+            &self.status
+        }
+    }
+}
+
+/// Every status seen in the period, plus failed and stuck generation rates
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StatusBreakdown {
+    /// Most frequent first
+    pub statuses: Vec<StatusCount>,
+    /// Records with a status other than done/process (including empty)
+    pub failed_count: usize,
+    pub failed_percentage: f64,
+    /// "process" records older than the stuck threshold
+    pub stuck_count: usize,
+    pub stuck_percentage: f64,
+    pub stuck_sample_ids: Vec<String>,
+    pub stuck_after_minutes: i64,
+    pub high_failure_rate: bool,
+    pub high_stuck_rate: bool,
+}
+
+/// Settings that affect how statistics are collected
+#[derive(Debug, Clone)]
+pub struct StatsSettings {
+    pub unknown_clubs_mode: UnknownClubsMode,
+    pub phones: PhoneNormalizer,
+    pub data_quality: DataQualitySettings,
+    /// "process" records older than this are reported as stuck
+    pub stuck_process_minutes: i64,
+    /// Failure share (percent) above which the rate is flagged
+    pub failure_alert_percent: f64,
+    /// Stuck share (percent) above which the rate is flagged
+    pub stuck_alert_percent: f64,
+    /// Timestamp fields whose difference is the generation time
+    pub generation_start_field: String,
+    pub generation_end_field: String,
+    /// Generation times above this many seconds are excluded as outliers
+    pub generation_time_cap_secs: f64,
+    pub aura_buckets: AuraBuckets,
+}
+
+impl StatsSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            unknown_clubs_mode: config.unknown_clubs_mode,
+            phones: config.phone_normalizer.clone(),
+            data_quality: DataQualitySettings {
+                date_field_name: config.date_field_name.clone(),
+                updated_field_name: config.updated_field_name.clone(),
+                generation_start_field: config.generation_start_field.clone(),
+                generation_end_field: config.generation_end_field.clone(),
+                max_generation_secs: config.dq_max_generation_secs,
+                duplicate_window_minutes: config.dq_duplicate_window_minutes,
+                phones: config.phone_normalizer.clone(),
+            },
+            stuck_process_minutes: config.stuck_process_minutes,
+            failure_alert_percent: config.failure_alert_percent,
+            stuck_alert_percent: config.stuck_alert_percent,
+            generation_start_field: config.generation_start_field.clone(),
+            generation_end_field: config.generation_end_field.clone(),
+            generation_time_cap_secs: config.generation_time_cap_secs,
+            aura_buckets: config.aura_buckets.clone(),
+        }
+    }
+}
+
+impl Default for StatsSettings {
+    fn default() -> Self {
+        Self {
+            unknown_clubs_mode: UnknownClubsMode::Exclude,
+            phones: PhoneNormalizer::default(),
+            data_quality: DataQualitySettings::default(),
+            stuck_process_minutes: 30,
+            failure_alert_percent: 5.0,
+            stuck_alert_percent: 5.0,
+            generation_start_field: "CreatedAt".to_string(),
+            generation_end_field: "UpdatedAt".to_string(),
+            generation_time_cap_secs: 1800.0,
+            aura_buckets: AuraBuckets::default(),
+        }
+    }
+}
+
+/// Records that could not be attributed to a club and data quality check results
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DataQualityStats {
+    /// All records of the period, including orphaned ones
+    pub audited_records: usize,
+    /// Records with a missing or unknown `club_id`
+    pub orphaned_records: usize,
+    /// Records without `club_id`
+    pub missing_club_records: usize,
+    /// Unknown club ids with record counts, most frequent first
+    pub unknown_clubs: Vec<(String, usize)>,
+    /// Whether orphaned records are counted under "Без комплекса" or excluded
+    pub orphans_included: bool,
+    /// Checks that found problems, in display order
+    pub findings: Vec<QualityFinding>,
+}
+
+/// Accumulates report statistics one record at a time
+pub struct StatsCollector<'a> {
+    clubs: &'a ClubMap,
+    unknown_clubs_mode: UnknownClubsMode,
+    phones: PhoneNormalizer,
+    unique_phones: HashSet<String>,
+    aura_buckets: AuraBuckets,
+    aura_levels: Vec<usize>,
+    aura: AuraCollector,
+    daily_aura: BTreeMap<NaiveDate, AuraCollector>,
+    daily_generations: BTreeMap<NaiveDate, usize>,
+
+    // Statistics by club
+    club_accumulators: HashMap<String, ClubAccumulator>,
+
+    // Generation time tracking (only for done status)
+    generation_start_field: String,
+    generation_end_field: String,
+    generation_time_cap_secs: f64,
+    generation_times: GenerationTimeCollector,
+
+    // Status tracking
+    done_count: usize,
+    process_count: usize,
+    status_counts: HashMap<String, usize>,
+    stuck_before: DateTime<Utc>,
+    stuck_process_minutes: i64,
+    failure_alert_percent: f64,
+    stuck_alert_percent: f64,
+    stuck_count: usize,
+    stuck_sample_ids: Vec<String>,
+
+    // Age and gender split
+    visit_field: String,
+    demographics: DemographicsCollector,
+
+    // Data quality tracking
+    audited_records: usize,
+    missing_club_records: usize,
+    unknown_club_records: HashMap<String, usize>,
+    audit: DataQualityAudit,
+}
+
+impl<'a> StatsCollector<'a> {
+    pub fn new(clubs: &'a ClubMap, settings: &StatsSettings) -> Self {
+        Self {
+            clubs,
+            unknown_clubs_mode: settings.unknown_clubs_mode,
+            phones: settings.phones.clone(),
+            unique_phones: HashSet::new(),
+            aura_buckets: settings.aura_buckets.clone(),
+            aura_levels: vec![0; settings.aura_buckets.count()],
+            aura: AuraCollector::default(),
+            daily_aura: BTreeMap::new(),
+            daily_generations: BTreeMap::new(),
+            club_accumulators: HashMap::new(),
+            generation_start_field: settings.generation_start_field.clone(),
+            generation_end_field: settings.generation_end_field.clone(),
+            generation_time_cap_secs: settings.generation_time_cap_secs,
+            generation_times: GenerationTimeCollector::new(settings.generation_time_cap_secs),
+            done_count: 0,
+            process_count: 0,
+            status_counts: HashMap::new(),
+            stuck_before: Utc::now() - Duration::minutes(settings.stuck_process_minutes),
+            stuck_process_minutes: settings.stuck_process_minutes,
+            failure_alert_percent: settings.failure_alert_percent,
+            stuck_alert_percent: settings.stuck_alert_percent,
+            stuck_count: 0,
+            stuck_sample_ids: Vec::new(),
+            visit_field: settings.data_quality.date_field_name.clone(),
+            demographics: DemographicsCollector::new(settings.aura_buckets.count()),
+            audited_records: 0,
+            missing_club_records: 0,
+            unknown_club_records: HashMap::new(),
+            audit: DataQualityAudit::new(settings.data_quality.clone()),
+        }
+    }
+
+    /// Add a single record to the statistics
+    pub fn add_record(&mut self, record: &Value) {
+        let Some(obj) = record.as_object() else {
+            return;
+        };
+
+        // Data quality checks cover every record of the period
+        self.audited_records += 1;
+        self.audit.add_record(obj);
+
+        // Records without a known club are tracked for data quality and then
+        // either grouped under "Без комплекса" or left out of all statistics
+        let club_id = match club_assignment(obj, self.clubs) {
+            ClubAssignment::Known(id) => id.to_string(),
+            assignment => {
+                match assignment {
+                    ClubAssignment::Unknown(id) => {
+                        *self.unknown_club_records.entry(id.to_string()).or_insert(0) += 1;
+                    }
+                    _ => self.missing_club_records += 1,
+                }
+                if self.unknown_clubs_mode == UnknownClubsMode::Exclude {
+                    return;
+                }
+                UNASSIGNED_CLUB_ID.to_string()
+            }
+        };
+
+        let club = self
+            .club_accumulators
+            .entry(club_id)
+            .or_insert_with(|| ClubAccumulator::new(self.generation_time_cap_secs, self.aura_buckets.count()));
+        club.generations += 1;
+
+        // Get status
+        let status = record_status(obj);
+
+        // Count statuses
+        *self.status_counts.entry(status.to_string()).or_insert(0) += 1;
+        if status == "done" {
+            self.done_count += 1;
+            club.done += 1;
+        } else if status == "process" {
+            self.process_count += 1;
+
+            // Generations that never left "process", by the configured generation start
+            let created = obj
+                .get(&self.generation_start_field)
+                .and_then(|v| v.as_str())
+                .and_then(parse_record_datetime);
+            if created.is_some_and(|created| created < self.stuck_before) {
+                self.stuck_count += 1;
+                if self.stuck_sample_ids.len() < STUCK_SAMPLE_LIMIT {
+                    self.stuck_sample_ids.push(record_id(obj));
+                }
+            }
+        }
+
+        // Calculate generation time ONLY for done status
+        if status == "done" {
+            let timestamp = |field: &str| obj.get(field).and_then(|v| v.as_str()).and_then(parse_record_datetime);
+            if let (Some(start), Some(end)) = (
+                timestamp(&self.generation_start_field),
+                timestamp(&self.generation_end_field),
+            ) {
+                let secs = end.signed_duration_since(start).num_milliseconds() as f64 / 1000.0;
+                self.generation_times.add(secs);
+                club.generation_times.add(secs);
+            }
+        }
+
+        // Count unique clients by normalized phone
+        let phone_str = obj
+            .get("phone")
+            .and_then(|phone| self.phones.client_key(phone))
+            .unwrap_or_default();
+
+        if !phone_str.is_empty() {
+            self.unique_phones.insert(phone_str.clone());
+            club.phones.insert(phone_str);
+        }
+
+        // Day of the record in Moscow time
+        let day = obj
+            .get(&self.visit_field)
+            .and_then(|v| v.as_str())
+            .and_then(parse_record_datetime)
+            .map(|date| date.with_timezone(&Moscow).date_naive());
+        if let Some(day) = day {
+            *self.daily_generations.entry(day).or_insert(0) += 1;
+        }
+
+        // Parse aura percent from text_aura field
+        let aura_percent = extract_percent_value(obj);
+        let aura_level = aura_percent.map(|percent| self.aura_buckets.index_of(percent));
+        if let (Some(percent), Some(level)) = (aura_percent, aura_level) {
+            self.aura_levels[level] += 1;
+            club.aura_levels[level] += 1;
+            club.aura.add(percent);
+            self.aura.add(percent);
+            if let Some(day) = day {
+                self.daily_aura.entry(day).or_default().add(percent);
+            }
+        }
+
+        self.demographics.add_record(obj, &self.visit_field, aura_level);
+    }
+
+    /// Finalize accumulated counters into report statistics
+    pub fn finish(mut self) -> ReportStats {
+        let clubs = self.clubs;
+
+        // Calculate club statistics
+        let total_records: usize = self.club_accumulators.values().map(|c| c.generations).sum();
+        let mut club_stats: Vec<ClubStats> = self.club_accumulators
+            .drain()
+            .map(|(club_id, acc)| {
+                let unique_clients = acc.phones.len();
+                let generations = acc.generations;
+
+                let percentage = if total_records > 0 {
+                    (generations as f64 / total_records as f64) * 100.0
+                } else {
+                    0.0
+                };
+
+                let club = clubs.get(&club_id);
+                ClubStats {
+                    club_name: club
+                        .map(|c| c.name.clone())
+                        .unwrap_or_else(|| UNASSIGNED_CLUB_NAME.to_string()),
+                    city: club.and_then(|c| c.city.clone()),
+                    club_id,
+                    total_generations: generations,
+                    unique_clients,
+                    percentage,
+                    generation_time: acc.generation_times.finish(),
+                    aura_levels: acc.aura_levels,
+                    aura: acc.aura.finish(),
+                    done_rate: acc.done as f64 / generations as f64 * 100.0,
+                    generations_per_client: if unique_clients > 0 {
+                        generations as f64 / unique_clients as f64
+                    } else {
+                        0.0
+                    },
+                    rank: 0,
+                    rank_change: None,
+                }
+            })
+            .collect();
+
+        // Sort by total_generations descending and assign ranks
+        club_stats.sort_by(|a, b| {
+            rank_order((a.total_generations, &a.club_name), (b.total_generations, &b.club_name))
+        });
+        for (i, club) in club_stats.iter_mut().enumerate() {
+            club.rank = i + 1;
+        }
+
+        // Status shares are computed over all records of the report
+        let share = |count: usize| {
+            if total_records > 0 {
+                (count as f64 / total_records as f64) * 100.0
+            } else {
+                0.0
+            }
+        };
+        let mut statuses: Vec<StatusCount> = self
+            .status_counts
+            .into_iter()
+            .map(|(status, count)| StatusCount {
+                status,
+                count,
+                percentage: share(count),
+            })
+            .collect();
+        statuses.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.status.cmp(&b.status)));
+
+        let failed_count: usize = statuses
+            .iter()
+            .filter(|s| !HEALTHY_STATUSES.contains(&s.status.as_str()))
+            .map(|s| s.count)
+            .sum();
+        let failed_percentage = share(failed_count);
+        let stuck_percentage = share(self.stuck_count);
+
+        let status_breakdown = StatusBreakdown {
+            statuses,
+            failed_count,
+            failed_percentage,
+            stuck_count: self.stuck_count,
+            stuck_percentage,
+            stuck_sample_ids: self.stuck_sample_ids,
+            stuck_after_minutes: self.stuck_process_minutes,
+            high_failure_rate: failed_count > 0 && failed_percentage > self.failure_alert_percent,
+            high_stuck_rate: self.stuck_count > 0 && stuck_percentage > self.stuck_alert_percent,
+        };
+
+        let mut unknown_clubs: Vec<(String, usize)> = self.unknown_club_records.into_iter().collect();
+        unknown_clubs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let unknown_count: usize = unknown_clubs.iter().map(|(_, count)| count).sum();
+
+        let data_quality = DataQualityStats {
+            audited_records: self.audited_records,
+            orphaned_records: self.missing_club_records + unknown_count,
+            missing_club_records: self.missing_club_records,
+            unknown_clubs,
+            orphans_included: self.unknown_clubs_mode == UnknownClubsMode::Include,
+            findings: self.audit.finish(),
+        };
+
+        ReportStats {
+            total_records,
+            unique_clients: self.unique_phones.len(),
+            aura_buckets: self.aura_buckets,
+            aura_levels: self.aura_levels,
+            aura: self.aura.finish(),
+            daily_aura: self
+                .daily_aura
+                .into_iter()
+                .filter_map(|(date, collector)| collector.finish().map(|summary| DailyAura { date, summary }))
+                .collect(),
+            daily_generations: self.daily_generations.into_iter().collect(),
+            club_stats,
+            generation_time: self.generation_times.finish(),
+            done_count: self.done_count,
+            process_count: self.process_count,
+            status_breakdown,
+            demographics: self.demographics.finish(),
+            retention: None,
+            retention_unavailable: None,
+            data_quality,
+        }
+    }
+}
+
+/// Extract percent value from text_aura field
+pub fn extract_percent_value(record: &serde_json::Map<String, Value>) -> Option<f64> {
+    // Try text_aura field first
+    if let Some(text_aura) = record.get("text_aura") {
+        if let Some(aura_obj) = text_aura.as_object() {
+            if let Some(percent) = aura_obj.get("percent") {
+                if let Some(percent_str) = percent.as_str() {
+                    // Parse "90%" to 90.0
+                    let cleaned = percent_str.trim().trim_end_matches('%');
+                    return cleaned.parse::<f64>().ok();
+                } else if let Some(percent_num) = percent.as_f64() {
+                    return Some(percent_num);
+                }
+            }
+        } else if let Some(aura_str) = text_aura.as_str() {
+            if !aura_str.is_empty() {
+                if let Ok(parsed) = serde_json::from_str::<Value>(aura_str) {
+                    if let Some(obj) = parsed.as_object() {
+                        if let Some(percent) = obj.get("percent") {
+                            if let Some(percent_str) = percent.as_str() {
+                                let cleaned = percent_str.trim().trim_end_matches('%');
+                                return cleaned.parse::<f64>().ok();
+                            } else if let Some(percent_num) = percent.as_f64() {
+                                return Some(percent_num);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Fallback to aura field
+    if let Some(aura) = record.get("aura") {
+        if let Some(aura_obj) = aura.as_object() {
+            if let Some(percent) = aura_obj.get("percent") {
+                if let Some(percent_str) = percent.as_str() {
+                    let cleaned = percent_str.trim().trim_end_matches('%');
+                    return cleaned.parse::<f64>().ok();
+                } else if let Some(percent_num) = percent.as_f64() {
+                    return Some(percent_num);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::club_directory::test_clubs;
+    use serde_json::json;
+
+    #[test]
+    fn test_collector_counts_only_known_clubs() {
+        let clubs = test_clubs(&[("c1", "Club 1")]);
+        let mut collector = StatsCollector::new(&clubs, &StatsSettings::default());
+
+        collector.add_record(&json!({"club_id": "c1", "phone": "79990000001", "status": "done", "text_aura": {"percent": "90%"}}));
+        collector.add_record(&json!({"club_id": "c1", "phone": 79990000001u64, "status": "process", "text_aura": "{\"percent\": 50}"}));
+        collector.add_record(&json!({"club_id": "unknown", "phone": "79990000002", "status": "done"}));
+
+        let stats = collector.finish();
+        assert_eq!(stats.total_records, 2);
+        assert_eq!(stats.unique_clients, 1);
+        assert_eq!(stats.aura_levels, vec![1, 0, 1]);
+        assert_eq!(stats.done_count, 1);
+        assert_eq!(stats.club_stats.len(), 1);
+        assert_eq!(stats.data_quality.orphaned_records, 1);
+        assert_eq!(stats.data_quality.unknown_clubs, vec![("unknown".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_collector_groups_orphans_when_included() {
+        let clubs = test_clubs(&[("c1", "Club 1")]);
+        let mut collector = StatsCollector::new(&clubs, &StatsSettings {
+            unknown_clubs_mode: UnknownClubsMode::Include,
+            ..StatsSettings::default()
+        });
+
+        collector.add_record(&json!({"club_id": "c1", "phone": "1", "status": "done"}));
+        collector.add_record(&json!({"club_id": "gone", "phone": "2", "status": "done"}));
+        collector.add_record(&json!({"phone": "3", "status": "process"}));
+
+        let stats = collector.finish();
+        assert_eq!(stats.total_records, 3);
+        assert_eq!(stats.data_quality.orphaned_records, 2);
+        assert_eq!(stats.data_quality.missing_club_records, 1);
+        let unassigned = stats.club_stats.iter().find(|c| c.club_id == UNASSIGNED_CLUB_ID).unwrap();
+        assert_eq!(unassigned.total_generations, 2);
+        assert_eq!(unassigned.club_name, UNASSIGNED_CLUB_NAME);
+    }
+
+    #[test]
+    fn test_status_breakdown_tracks_all_statuses() {
+        let clubs = test_clubs(&[("c1", "Club 1")]);
+        let mut collector = StatsCollector::new(&clubs, &StatsSettings::default());
+
+        collector.add_record(&json!({"Id": 1, "club_id": "c1", "status": "done"}));
+        collector.add_record(&json!({"Id": 2, "club_id": "c1", "status": "error"}));
+        collector.add_record(&json!({"Id": 3, "club_id": "c1"}));
+        collector.add_record(&json!({"Id": 4, "club_id": "c1", "status": "process", "CreatedAt": "2024-01-01 10:00:00"}));
+
+        let breakdown = collector.finish().status_breakdown;
+        assert_eq!(breakdown.statuses.len(), 4);
+        assert!(breakdown.statuses.iter().all(|s| s.count == 1 && s.percentage == 25.0));
+        assert_eq!(breakdown.failed_count, 2);
+        assert!(breakdown.high_failure_rate);
+        assert_eq!(breakdown.stuck_count, 1);
+        assert_eq!(breakdown.stuck_sample_ids, vec!["4"]);
+        assert!(breakdown.high_stuck_rate);
+    }
+
+    #[test]
+    fn test_padded_statuses_and_configured_start_field() {
+        let clubs = test_clubs(&[("c1", "Club 1")]);
+        let mut collector = StatsCollector::new(&clubs, &StatsSettings {
+            generation_start_field: "StartedAt".to_string(),
+            ..StatsSettings::default()
+        });
+
+        collector.add_record(&json!({"Id": 1, "club_id": "c1", "status": " done "}));
+        collector.add_record(&json!({"Id": 2, "club_id": "c1", "status": "process\n", "StartedAt": "2024-01-01 10:00:00"}));
+        // Stuck detection reads only the configured start field
+        collector.add_record(&json!({"Id": 3, "club_id": "c1", "status": "process", "CreatedAt": "2024-01-01 10:00:00"}));
+
+        let stats = collector.finish();
+        assert_eq!(stats.done_count, 1);
+        assert_eq!(stats.status_breakdown.stuck_count, 1);
+        assert_eq!(stats.status_breakdown.stuck_sample_ids, vec!["2"]);
+        assert_eq!(stats.status_breakdown.statuses.len(), 2);
+    }
+
+    #[test]
+    fn test_club_kpis_and_rank_change() {
+        let clubs = test_clubs(&[("c1", "c1"), ("c2", "c2")]);
+        let mut collector = StatsCollector::new(&clubs, &StatsSettings::default());
+
+        collector.add_record(&json!({"club_id": "c1", "phone": "79990000001", "status": "done", "text_aura": {"percent": 90}}));
+        collector.add_record(&json!({"club_id": "c1", "phone": "79990000001", "status": "error", "text_aura": {"percent": 50}}));
+        collector.add_record(&json!({"club_id": "c1", "phone": "79990000002", "status": "done"}));
+        collector.add_record(&json!({"club_id": "c2", "phone": "79990000003", "status": "done", "text_aura": {"percent": 70}}));
+
+        let mut stats = collector.finish();
+        stats.apply_previous_ranks(&HashMap::from([("c1".to_string(), 2)]));
+
+        let c1 = &stats.club_stats[0];
+        assert_eq!((c1.club_id.as_str(), c1.rank), ("c1", 1));
+        assert_eq!(c1.aura_levels, vec![1, 0, 1]);
+        assert_eq!(c1.aura.as_ref().map(|aura| aura.mean), Some(70.0));
+        assert!((c1.done_rate - 200.0 / 3.0).abs() < 1e-9);
+        assert_eq!(c1.generations_per_client, 1.5);
+        assert_eq!(c1.rank_change, Some(1));
+
+        let c2 = &stats.club_stats[1];
+        assert_eq!(c2.rank, 2);
+        assert_eq!(c2.rank_change, None);
+        assert_eq!(stats.club_ranks()["c2"], 2);
+    }
+
+    #[test]
+    fn test_rank_counter_matches_full_statistics() {
+        let clubs = test_clubs(&[("c1", "Б"), ("c2", "А"), ("c3", "В")]);
+        let records = [
+            json!({"club_id": "c1"}),
+            json!({"club_id": "c2"}),
+            json!({"club_id": "c3"}),
+            json!({"club_id": "c3"}),
+            json!({"club_id": "gone"}),
+            json!({"club_id": "gone"}),
+            json!({"club_id": "gone"}),
+            json!({}),
+        ];
+
+        for mode in [UnknownClubsMode::Exclude, UnknownClubsMode::Include] {
+            let settings = StatsSettings { unknown_clubs_mode: mode, ..StatsSettings::default() };
+            let mut collector = StatsCollector::new(&clubs, &settings);
+            let mut counter = ClubRankCounter::new(&clubs, mode);
+            for record in &records {
+                collector.add_record(record);
+                counter.add_record(record);
+            }
+            assert_eq!(counter.finish(), collector.finish().club_ranks());
+        }
+
+        // Ties go by name: "А" (c2) before "Б" (c1)
+        let mut counter = ClubRankCounter::new(&clubs, UnknownClubsMode::Exclude);
+        for record in &records {
+            counter.add_record(record);
+        }
+        let ranks = counter.finish();
+        assert_eq!((ranks["c3"], ranks["c2"], ranks["c1"]), (1, 2, 3));
+    }
+
+    #[test]
+    fn test_aura_summary_overall_and_per_day() {
+        let clubs = test_clubs(&[("c1", "Club 1")]);
+        let mut collector = StatsCollector::new(&clubs, &StatsSettings::default());
+
+        // 22:30 UTC is already the next day in Moscow
+        collector.add_record(&json!({"club_id": "c1", "text_aura": {"percent": 72}, "CreatedAt1": "2024-03-01 10:00:00"}));
+        collector.add_record(&json!({"club_id": "c1", "text_aura": {"percent": 78}, "CreatedAt1": "2024-03-01 22:30:00"}));
+        collector.add_record(&json!({"club_id": "c1", "text_aura": {"percent": 84}, "CreatedAt1": "2024-03-02 12:00:00"}));
+        collector.add_record(&json!({"club_id": "c1", "CreatedAt1": "2024-03-02 12:00:00"}));
+
+        let stats = collector.finish();
+        let aura = stats.aura.unwrap();
+        assert_eq!((aura.count, aura.mean, aura.median), (3, 78.0, 78.0));
+        assert_eq!(stats.club_stats[0].aura.as_ref().unwrap().count, 3);
+
+        let daily: Vec<(String, f64)> = stats
+            .daily_aura
+            .iter()
+            .map(|day| (day.date.to_string(), day.summary.mean))
+            .collect();
+        assert_eq!(daily, vec![("2024-03-01".to_string(), 72.0), ("2024-03-02".to_string(), 81.0)]);
+        let generations: Vec<usize> = stats.daily_generations.iter().map(|(_, count)| *count).collect();
+        assert_eq!(generations, vec![1, 3]);
+    }
+}
@@ -0,0 +1,120 @@
+use serde::Deserialize;
+
+use crate::export_profile::{ExportProfiles, DEFAULT_PROFILE};
+use crate::privacy::PrivacyMode;
+use crate::report_output::{ReportOutput, ReportOutputs};
+
+/// A chat receiving the scheduled daily report
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "SubscriptionEntry")]
+pub struct Subscription {
+    pub chat_id: i64,
+    /// Export profile of the records CSV
+    pub csv_profile: String,
+    /// Privacy mode of the record exports; the chat's role mode when not set
+    pub privacy: Option<PrivacyMode>,
+    /// Deliver the files as an encrypted ZIP; by default only full-PII reports are encrypted
+    /// when `ENCRYPT_FULL_PII` is on
+    pub encrypted: Option<bool>,
+    /// Parts of the report to deliver
+    pub outputs: ReportOutputs,
+}
+
+/// A subscription as written in the subscriptions file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubscriptionEntry {
+    chat_id: i64,
+    #[serde(default = "default_profile")]
+    csv_profile: String,
+    #[serde(default)]
+    privacy: Option<PrivacyMode>,
+    #[serde(default)]
+    encrypted: Option<bool>,
+    /// e.g. `["summary"]`; everything but JSON and Parquet when not set
+    #[serde(default)]
+    outputs: Option<Vec<ReportOutput>>,
+}
+
+impl From<SubscriptionEntry> for Subscription {
+    fn from(entry: SubscriptionEntry) -> Self {
+        Self {
+            chat_id: entry.chat_id,
+            csv_profile: entry.csv_profile,
+            privacy: entry.privacy,
+            encrypted: entry.encrypted,
+            outputs: match entry.outputs {
+                Some(outputs) => outputs.into_iter().collect(),
+                None => ReportOutputs::default(),
+            },
+        }
+    }
+}
+
+fn default_profile() -> String {
+    DEFAULT_PROFILE.to_string()
+}
+
+impl Subscription {
+    /// Subscription with default settings
+    pub fn for_chat(chat_id: i64) -> Self {
+        Self {
+            chat_id,
+            csv_profile: default_profile(),
+            privacy: None,
+            encrypted: None,
+            outputs: ReportOutputs::default(),
+        }
+    }
+}
+
+/// Parse a JSON array of subscriptions, e.g.
+/// `[{"chat_id": 123, "csv_profile": "marketing", "privacy": "masked"}]`,
+/// checking that every referenced profile exists and every output can be produced
+pub fn parse_subscriptions(json: &str, profiles: &ExportProfiles) -> Result<Vec<Subscription>, String> {
+    let subscriptions: Vec<Subscription> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    for subscription in &subscriptions {
+        if profiles.get(&subscription.csv_profile).is_none() {
+            return Err(format!(
+                "chat {}: unknown CSV profile \"{}\"",
+                subscription.chat_id, subscription.csv_profile
+            ));
+        }
+        if let Some(output) = subscription.outputs.unavailable() {
+            return Err(format!(
+                "chat {}: output \"{}\" needs the \"{}\" build feature",
+                subscription.chat_id,
+                output.name(),
+                output.name()
+            ));
+        }
+    }
+    Ok(subscriptions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_subscriptions() {
+        let profiles = ExportProfiles::parse(r#"{"marketing": {"columns": ["phone", "name"]}}"#).unwrap();
+        let subscriptions =
+            parse_subscriptions(r#"[{"chat_id": 1, "csv_profile": "marketing", "privacy": "hashed", "encrypted": true, "outputs": ["summary", "json"]}, {"chat_id": 2}, {"chat_id": 3, "outputs": ["summary", "charts"]}]"#, &profiles)
+                .unwrap();
+        assert_eq!(subscriptions[0].csv_profile, "marketing");
+        assert_eq!(subscriptions[0].privacy, Some(PrivacyMode::Hashed));
+        assert_eq!(subscriptions[0].encrypted, Some(true));
+        assert_eq!(subscriptions[0].outputs, ReportOutputs::none().with(ReportOutput::Summary).with(ReportOutput::Json));
+        assert_eq!(subscriptions[1], Subscription::for_chat(2));
+        let outputs = subscriptions[2].outputs;
+        assert!(outputs.contains(ReportOutput::Charts) && !outputs.has_files());
+
+        assert!(parse_subscriptions(r#"[{"chat_id": 1, "csv_profile": "ops"}]"#, &profiles).is_err());
+        assert!(parse_subscriptions(r#"[{"chat_id": 1, "privacy": "open"}]"#, &profiles).is_err());
+        assert!(parse_subscriptions(r#"[{"chat_id": 1, "outputs": ["docx"]}]"#, &profiles).is_err());
+        assert!(parse_subscriptions(r#"[{"chat_id": 1, "json": true}]"#, &profiles).is_err());
+        let parquet = parse_subscriptions(r#"[{"chat_id": 1, "outputs": ["parquet"]}]"#, &profiles);
+        assert_eq!(parquet.is_ok(), cfg!(feature = "parquet"));
+    }
+}
@@ -0,0 +1,421 @@
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use chrono_tz::Europe::Moscow;
+use log::info;
+use rust_xlsxwriter::{Format, Workbook, Worksheet};
+use serde_json::{Map, Value};
+
+use crate::aura::AuraBuckets;
+use crate::club_directory::ClubMap;
+use crate::data_quality::record_id;
+use crate::date_utils::parse_record_datetime;
+use crate::demographics::parse_birth_date;
+use crate::export_profile::CsvColumn;
+use crate::phone::PhoneNormalizer;
+use crate::privacy::Anonymizer;
+use crate::report_files::{persist, temp_path};
+use crate::stats::{extract_percent_value, ReportStats, UNASSIGNED_CLUB_NAME};
+
+/// Columns, headers and widths of the raw records sheet
+const RECORD_COLUMNS: &[(CsvColumn, &str, f64)] = &[
+    (CsvColumn::Id, "Id", 8.0),
+    (CsvColumn::Phone, "Телефон", 18.0),
+    (CsvColumn::Name, "Имя", 20.0),
+    (CsvColumn::DateVisit, "Дата визита", 18.0),
+    (CsvColumn::Duration, "Продолжительность", 18.0),
+    (CsvColumn::Club, "Комплекс", 28.0),
+    (CsvColumn::Aura, "Аура, %", 10.0),
+    (CsvColumn::AuraLevel, "Уровень ауры", 18.0),
+    (CsvColumn::BirthDate, "Дата рождения", 14.0),
+    (CsvColumn::Sex, "Пол", 8.0),
+    (CsvColumn::Status, "Статус", 10.0),
+];
+
+/// Typed cell value
+enum Cell {
+    Text(String),
+    Number(f64),
+    DateTime(NaiveDateTime),
+    Date(chrono::NaiveDate),
+    Empty,
+}
+
+impl Cell {
+    fn text(value: impl Into<String>) -> Self {
+        Cell::Text(value.into())
+    }
+
+    fn number(value: Option<f64>) -> Self {
+        value.map(Cell::Number).unwrap_or(Cell::Empty)
+    }
+
+    fn count(value: usize) -> Self {
+        Cell::Number(value as f64)
+    }
+}
+
+/// Cell formats shared by all sheets
+struct Formats {
+    header: Format,
+    datetime: Format,
+    date: Format,
+    decimal: Format,
+}
+
+impl Formats {
+    fn new() -> Self {
+        Self {
+            header: Format::new().set_bold().set_background_color("#E0F2F1"),
+            datetime: Format::new().set_num_format("dd.mm.yyyy hh:mm:ss"),
+            date: Format::new().set_num_format("dd.mm.yyyy"),
+            decimal: Format::new().set_num_format("0.0"),
+        }
+    }
+}
+
+/// Incremental writer of the XLSX workbook: raw records are added one at a time,
+/// the summary, club, daily and aura sheets are written from the final statistics
+pub struct XlsxReportWriter {
+    output_path: String,
+    records: Worksheet,
+    /// Record columns left after the privacy mode
+    columns: Vec<CsvColumn>,
+    anonymizer: Anonymizer,
+    formats: Formats,
+    phones: PhoneNormalizer,
+    aura_buckets: AuraBuckets,
+    rows: u32,
+}
+
+impl XlsxReportWriter {
+    /// Start the workbook. Personal data columns of the records sheet follow the anonymizer;
+    /// in the aggregate-only mode the records sheet is left out.
+    pub fn create(
+        output_path: &str,
+        phones: PhoneNormalizer,
+        aura_buckets: AuraBuckets,
+        anonymizer: &Anonymizer,
+    ) -> Result<Self> {
+        info!("Generating XLSX report to: {} (privacy: {})", output_path, anonymizer.mode().name());
+
+        let formats = Formats::new();
+        let mut records = Worksheet::new();
+        records.set_name("Записи")?;
+        let kept: Vec<&(CsvColumn, &str, f64)> = RECORD_COLUMNS
+            .iter()
+            .filter(|(column, _, _)| !anonymizer.drops(*column))
+            .collect();
+        let headers: Vec<&str> = kept.iter().map(|(_, header, _)| *header).collect();
+        let widths: Vec<f64> = kept.iter().map(|(_, _, width)| *width).collect();
+        write_header(&mut records, &headers, &widths, &formats)?;
+
+        Ok(Self {
+            output_path: output_path.to_string(),
+            records,
+            columns: kept.iter().map(|(column, _, _)| *column).collect(),
+            anonymizer: anonymizer.clone(),
+            formats,
+            phones,
+            aura_buckets,
+            rows: 0,
+        })
+    }
+
+    /// Append a single record to the raw records sheet
+    pub fn write_record(&mut self, record: &Value, clubs: &ClubMap) -> Result<()> {
+        if !self.anonymizer.mode().exports_records() {
+            return Ok(());
+        }
+        if let Some(obj) = record.as_object() {
+            let row = self.build_row(obj, clubs);
+            self.rows += 1;
+            write_row(&mut self.records, self.rows, &row, &self.formats)?;
+        }
+        Ok(())
+    }
+
+    /// Add the statistics sheets, save the workbook and return its path
+    pub fn finish(mut self, stats: &ReportStats, period_label: &str) -> Result<String> {
+        let mut workbook = Workbook::new();
+        workbook.push_worksheet(summary_sheet(stats, period_label, &self.formats)?);
+        workbook.push_worksheet(clubs_sheet(stats, &self.formats)?);
+        workbook.push_worksheet(daily_sheet(stats, &self.formats)?);
+        workbook.push_worksheet(aura_sheet(stats, &self.formats)?);
+        if self.anonymizer.mode().exports_records() {
+            self.records.autofilter(0, 0, self.rows, self.columns.len() as u16 - 1)?;
+            workbook.push_worksheet(self.records);
+        }
+        workbook.save(temp_path(&self.output_path))?;
+
+        info!("XLSX report generated successfully with {} records", self.rows);
+        persist(&self.output_path)
+    }
+
+    fn build_row(&self, obj: &Map<String, Value>, clubs: &ClubMap) -> Vec<Cell> {
+        let text = |field: &str| Cell::text(obj.get(field).and_then(|v| v.as_str()).unwrap_or(""));
+        let aura = extract_percent_value(obj);
+
+        self.columns
+            .iter()
+            .map(|column| match column {
+                CsvColumn::Id => match obj.get("Id").and_then(|v| v.as_f64()) {
+                    Some(id) => Cell::Number(id),
+                    None => Cell::text(record_id(obj)),
+                },
+                CsvColumn::Phone => Cell::text(
+                    obj.get("phone")
+                        .and_then(|v| self.anonymizer.phone(&self.phones, v))
+                        .unwrap_or_default(),
+                ),
+                CsvColumn::Name => text("name"),
+                CsvColumn::DateVisit => obj
+                    .get("date_visit")
+                    .and_then(|v| v.as_str())
+                    .and_then(parse_record_datetime)
+                    .map(|date| Cell::DateTime(date.with_timezone(&Moscow).naive_local()))
+                    .unwrap_or(Cell::Empty),
+                CsvColumn::Duration => match obj.get("duration") {
+                    Some(Value::Number(n)) => Cell::number(n.as_f64()),
+                    Some(Value::String(s)) => {
+                        s.trim().parse().map(Cell::Number).unwrap_or_else(|_| Cell::text(s.as_str()))
+                    }
+                    _ => Cell::Empty,
+                },
+                CsvColumn::Club => Cell::text(
+                    obj.get("club_id")
+                        .and_then(|v| v.as_str())
+                        .and_then(|club_id| clubs.get(club_id))
+                        .map(|club| club.name.clone())
+                        .unwrap_or_else(|| UNASSIGNED_CLUB_NAME.to_string()),
+                ),
+                CsvColumn::Aura => Cell::number(aura),
+                CsvColumn::AuraLevel => aura
+                    .map(|percent| Cell::text(self.aura_buckets.bucket_of(percent).label.as_str()))
+                    .unwrap_or(Cell::Empty),
+                CsvColumn::BirthDate => obj
+                    .get("birth_date")
+                    .and_then(|v| v.as_str())
+                    .and_then(parse_birth_date)
+                    .map(Cell::Date)
+                    .unwrap_or_else(|| text("birth_date")),
+                CsvColumn::Sex => text("sex"),
+                CsvColumn::Status => text("status"),
+                // Not part of the records sheet
+                CsvColumn::City | CsvColumn::StartedAt | CsvColumn::FinishedAt | CsvColumn::GenerationSecs => {
+                    Cell::Empty
+                }
+            })
+            .collect()
+    }
+}
+
+/// Key figures of the period, one per row
+fn summary_sheet(stats: &ReportStats, period_label: &str, formats: &Formats) -> Result<Worksheet> {
+    let mut sheet = Worksheet::new();
+    sheet.set_name("Сводка")?;
+    write_header(&mut sheet, &["Показатель", "Значение"], &[40.0, 24.0], formats)?;
+
+    let share = |count: usize| {
+        if stats.total_records > 0 {
+            Some(count as f64 / stats.total_records as f64 * 100.0)
+        } else {
+            None
+        }
+    };
+    let breakdown = &stats.status_breakdown;
+    let mut rows: Vec<(String, Cell)> = vec![
+        ("Период".to_string(), Cell::text(period_label)),
+        ("Всего генераций".to_string(), Cell::count(stats.total_records)),
+        ("Уникальных клиентов".to_string(), Cell::count(stats.unique_clients)),
+        ("Done, %".to_string(), Cell::number(share(stats.done_count))),
+        ("Ошибочных генераций, %".to_string(), Cell::Number(breakdown.failed_percentage)),
+        ("Зависших генераций".to_string(), Cell::count(breakdown.stuck_count)),
+        ("Средняя аура, %".to_string(), Cell::number(stats.aura.as_ref().map(|a| a.mean))),
+        ("Медиана ауры, %".to_string(), Cell::number(stats.aura.as_ref().map(|a| a.median))),
+        ("Стандартное отклонение ауры".to_string(), Cell::number(stats.aura.as_ref().map(|a| a.std_dev))),
+    ];
+    rows.extend(
+        stats
+            .aura_buckets
+            .iter()
+            .zip(&stats.aura_levels)
+            .map(|(bucket, count)| (bucket.label.clone(), Cell::count(*count))),
+    );
+    rows.extend([
+        (
+            "Медиана времени генерации, сек".to_string(),
+            Cell::number(stats.generation_time.as_ref().map(|t| t.median)),
+        ),
+        (
+            "p95 времени генерации, сек".to_string(),
+            Cell::number(stats.generation_time.as_ref().map(|t| t.p95)),
+        ),
+    ]);
+    if let Some(retention) = &stats.retention {
+        rows.extend([
+            ("Новых клиентов".to_string(), Cell::count(retention.new_clients)),
+            ("Вернувшихся клиентов".to_string(), Cell::count(retention.returning_clients)),
+            ("Доля вернувшихся, %".to_string(), Cell::Number(retention.returning_percentage)),
+        ]);
+    }
+
+    for (i, (name, value)) in rows.into_iter().enumerate() {
+        write_row(&mut sheet, i as u32 + 1, &[Cell::Text(name), value], formats)?;
+    }
+    Ok(sheet)
+}
+
+/// Per-club KPIs in rank order
+fn clubs_sheet(stats: &ReportStats, formats: &Formats) -> Result<Worksheet> {
+    let mut sheet = Worksheet::new();
+    sheet.set_name("Комплексы")?;
+
+    let mut headers = vec![
+        "Место", "Изменение места", "Комплекс", "Город", "Генераций", "Доля, %", "Клиентов",
+        "Генераций на клиента", "Done, %", "Средняя аура, %", "Медиана ауры, %", "Стандартное отклонение ауры",
+    ];
+    headers.extend(stats.aura_buckets.iter().map(|bucket| bucket.label.as_str()));
+    headers.extend(["Среднее время генерации, сек", "Медиана времени генерации, сек"]);
+    let mut widths = vec![8.0, 10.0, 28.0, 16.0, 11.0, 9.0, 10.0, 12.0, 9.0, 12.0, 12.0, 14.0];
+    widths.extend(std::iter::repeat_n(14.0, stats.aura_buckets.count()));
+    widths.extend([14.0, 14.0]);
+    write_header(&mut sheet, &headers, &widths, formats)?;
+
+    for (i, club) in stats.club_stats.iter().enumerate() {
+        let mut row = vec![
+            Cell::count(club.rank),
+            Cell::number(club.rank_change.map(|change| change as f64)),
+            Cell::text(club.club_name.as_str()),
+            Cell::text(club.city.clone().unwrap_or_default()),
+            Cell::count(club.total_generations),
+            Cell::Number(club.percentage),
+            Cell::count(club.unique_clients),
+            Cell::Number(club.generations_per_client),
+            Cell::Number(club.done_rate),
+            Cell::number(club.aura.as_ref().map(|a| a.mean)),
+            Cell::number(club.aura.as_ref().map(|a| a.median)),
+            Cell::number(club.aura.as_ref().map(|a| a.std_dev)),
+        ];
+        row.extend(club.aura_levels.iter().map(|count| Cell::count(*count)));
+        row.extend([
+            Cell::number(club.generation_time.as_ref().map(|t| t.mean)),
+            Cell::number(club.generation_time.as_ref().map(|t| t.median)),
+        ]);
+        write_row(&mut sheet, i as u32 + 1, &row, formats)?;
+    }
+
+    sheet.autofilter(0, 0, stats.club_stats.len() as u32, headers.len() as u16 - 1)?;
+    Ok(sheet)
+}
+
+/// Generations and aura summary per day
+fn daily_sheet(stats: &ReportStats, formats: &Formats) -> Result<Worksheet> {
+    let mut sheet = Worksheet::new();
+    sheet.set_name("По дням")?;
+    let headers = ["Дата", "Генераций", "С аурой", "Средняя аура, %", "Медиана ауры, %", "Стандартное отклонение ауры"];
+    write_header(&mut sheet, &headers, &[12.0, 11.0, 10.0, 14.0, 14.0, 16.0], formats)?;
+
+    for (i, (date, generations)) in stats.daily_generations.iter().enumerate() {
+        let aura = stats.daily_aura.iter().find(|day| day.date == *date).map(|day| &day.summary);
+        let row = [
+            Cell::Date(*date),
+            Cell::count(*generations),
+            Cell::count(aura.map(|a| a.count).unwrap_or(0)),
+            Cell::number(aura.map(|a| a.mean)),
+            Cell::number(aura.map(|a| a.median)),
+            Cell::number(aura.map(|a| a.std_dev)),
+        ];
+        write_row(&mut sheet, i as u32 + 1, &row, formats)?;
+    }
+
+    sheet.autofilter(0, 0, stats.daily_generations.len() as u32, headers.len() as u16 - 1)?;
+    Ok(sheet)
+}
+
+/// Records per aura bucket
+fn aura_sheet(stats: &ReportStats, formats: &Formats) -> Result<Worksheet> {
+    let mut sheet = Worksheet::new();
+    sheet.set_name("Аура")?;
+    write_header(&mut sheet, &["Уровень", "Диапазон", "Количество", "Доля, %"], &[22.0, 12.0, 12.0, 10.0], formats)?;
+
+    let with_aura: usize = stats.aura_levels.iter().sum();
+    for (i, (bucket, count)) in stats.aura_buckets.iter().zip(&stats.aura_levels).enumerate() {
+        let row = [
+            Cell::text(bucket.label.as_str()),
+            Cell::text(stats.aura_buckets.range_label(i)),
+            Cell::count(*count),
+            Cell::number((with_aura > 0).then(|| *count as f64 / with_aura as f64 * 100.0)),
+        ];
+        write_row(&mut sheet, i as u32 + 1, &row, formats)?;
+    }
+    Ok(sheet)
+}
+
+/// Write a bold header row, freeze it and set the column widths
+fn write_header(sheet: &mut Worksheet, headers: &[&str], widths: &[f64], formats: &Formats) -> Result<()> {
+    for (column, (header, width)) in headers.iter().zip(widths).enumerate() {
+        sheet.write_string_with_format(0, column as u16, *header, &formats.header)?;
+        sheet.set_column_width(column as u16, *width)?;
+    }
+    sheet.set_freeze_panes(1, 0)?;
+    Ok(())
+}
+
+fn write_row(sheet: &mut Worksheet, row: u32, cells: &[Cell], formats: &Formats) -> Result<()> {
+    for (column, cell) in cells.iter().enumerate() {
+        let column = column as u16;
+        match cell {
+            Cell::Text(text) if !text.is_empty() => {
+                sheet.write_string(row, column, text)?;
+            }
+            Cell::Number(number) if number.fract() == 0.0 => {
+                sheet.write_number(row, column, *number)?;
+            }
+            Cell::Number(number) => {
+                sheet.write_number_with_format(row, column, *number, &formats.decimal)?;
+            }
+            Cell::DateTime(datetime) => {
+                sheet.write_datetime_with_format(row, column, datetime, &formats.datetime)?;
+            }
+            Cell::Date(date) => {
+                sheet.write_datetime_with_format(row, column, date, &formats.date)?;
+            }
+            Cell::Text(_) | Cell::Empty => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::club_directory::test_clubs;
+    use crate::stats::{StatsCollector, StatsSettings};
+    use serde_json::json;
+
+    #[test]
+    fn test_generate_xlsx() {
+        let clubs = test_clubs(&[("c1", "Club 1")]);
+        let mut stats = StatsCollector::new(&clubs, &StatsSettings::default());
+
+        let output_path = "test_output.xlsx";
+        let mut writer = XlsxReportWriter::create(
+            output_path,
+            PhoneNormalizer::default(),
+            AuraBuckets::default(),
+            &Anonymizer::full(),
+        )
+        .unwrap();
+        let record = json!({
+            "Id": 1, "club_id": "c1", "phone": "89990000001", "name": "Анна", "status": "done",
+            "date_visit": "2024-01-01 10:00:00+00:00", "duration": "15", "text_aura": {"percent": "75%"},
+            "birth_date": "17.05.1990", "sex": "female", "CreatedAt1": "2024-01-01 10:00:00+00:00"
+        });
+        stats.add_record(&record);
+        writer.write_record(&record, &clubs).unwrap();
+
+        writer.finish(&stats.finish(), "Тест").unwrap();
+        assert!(std::fs::metadata(output_path).unwrap().len() > 0);
+        std::fs::remove_file(output_path).unwrap();
+    }
+}